@@ -0,0 +1,236 @@
+use crate::lidar::Sample;
+use crate::segmentation::{segment, Cluster, SegmentationConfig};
+use crate::transform::{Mount, Point};
+
+/// Parameters of the beacon detector.
+///
+/// Quality scales differ between sensors (LD06 confidence is 0-255, XV11 strength
+/// and UST05LN reflectance go much higher), so `min_quality` must be tuned for
+/// the sensor in use.
+#[derive(Copy, Clone, Debug)]
+pub struct BeaconConfig {
+    /// Radius of the beacon cylinders, in meters.
+    pub radius: f64,
+    /// Samples below this quality are ignored. Reflective tape gives strong echoes.
+    pub min_quality: u16,
+    /// Least number of high quality points on a beacon.
+    pub min_points: usize,
+    /// Largest RMS distance of the points to the fitted circle, in meters.
+    pub max_residual: f64,
+    /// Beacons farther than this from the sensor are ignored, in meters.
+    pub max_range: f64,
+    /// Tolerance on the visible width of the beacon, in meters.
+    pub width_tolerance: f64,
+    pub segmentation: SegmentationConfig,
+}
+
+impl Default for BeaconConfig {
+    fn default() -> BeaconConfig {
+        BeaconConfig {
+            radius: 0.04,
+            min_quality: 0,
+            min_points: 3,
+            max_residual: 0.01,
+            max_range: 4.0,
+            width_tolerance: 0.03,
+            segmentation: SegmentationConfig {
+                min_points: 3,
+                ..SegmentationConfig::default()
+            },
+        }
+    }
+}
+
+/// A detected beacon.
+#[derive(Clone, Debug)]
+pub struct Beacon {
+    /// Center of the cylinder, in the robot frame.
+    pub center: Point,
+    /// RMS distance of the points to the fitted circle, in meters.
+    pub residual: f64,
+    /// Mean quality of the points.
+    pub quality: f64,
+    /// Indices of the samples in the scan.
+    pub indices: Vec<usize>,
+}
+
+/// Finds cylinders of known radius in scans.
+pub struct BeaconDetector {
+    mount: Mount,
+    pub config: BeaconConfig,
+}
+
+impl BeaconDetector {
+    pub fn new(mount: Mount, config: BeaconConfig) -> BeaconDetector {
+        BeaconDetector { mount, config }
+    }
+
+    pub fn detect(&self, scan: &[Option<Sample>]) -> Vec<Beacon> {
+        segment(scan, &self.mount, &self.config.segmentation)
+            .iter()
+            .filter_map(|c| self.check_cluster(scan, c))
+            .collect()
+    }
+
+    fn check_cluster(&self, scan: &[Option<Sample>], cluster: &Cluster) -> Option<Beacon> {
+        let c = &self.config;
+        if cluster.extent > 2.0 * c.radius + c.width_tolerance {
+            return None;
+        }
+
+        let (indices, points): (Vec<usize>, Vec<Point>) = cluster
+            .indices
+            .iter()
+            .zip(&cluster.points)
+            .filter(|(&i, _)| scan[i].is_some_and(|s| s.quality >= c.min_quality))
+            .map(|(&i, &p)| (i, p))
+            .unzip();
+        if points.len() < c.min_points.max(2) {
+            return None;
+        }
+
+        let sensor = Point::new(self.mount.x, self.mount.y);
+        let center = fit_circle(&points, c.radius, &sensor)?;
+        if center.distance(&sensor) > c.max_range {
+            return None;
+        }
+        let residual = (points
+            .iter()
+            .map(|p| (p.distance(&center) - c.radius).powi(2))
+            .sum::<f64>()
+            / points.len() as f64)
+            .sqrt();
+        if residual > c.max_residual {
+            return None;
+        }
+
+        let quality = indices
+            .iter()
+            .filter_map(|&i| scan[i])
+            .map(|s| s.quality as f64)
+            .sum::<f64>()
+            / indices.len() as f64;
+
+        Some(Beacon {
+            center,
+            residual,
+            quality,
+            indices,
+        })
+    }
+}
+
+/// Fits a circle of known `radius` on points seen from `sensor` (Gauss-Newton).
+///
+/// Returns `None` if the fit is degenerate or puts the center on the
+/// sensor side of the points.
+pub fn fit_circle(points: &[Point], radius: f64, sensor: &Point) -> Option<Point> {
+    let n = points.len() as f64;
+    let centroid = Point::new(
+        points.iter().map(|p| p.x).sum::<f64>() / n,
+        points.iter().map(|p| p.y).sum::<f64>() / n,
+    );
+    // the visible arc faces the sensor : start behind it
+    let d = centroid.distance(sensor);
+    if d == 0.0 {
+        return None;
+    }
+    let mut c = Point::new(
+        centroid.x + (centroid.x - sensor.x) / d * radius,
+        centroid.y + (centroid.y - sensor.y) / d * radius,
+    );
+
+    for _ in 0..20 {
+        // normal equations of J^T J dc = -J^T r
+        let (mut a11, mut a12, mut a22, mut b1, mut b2) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for p in points {
+            let dist = p.distance(&c);
+            if dist < 1e-9 {
+                continue;
+            }
+            let r = dist - radius;
+            let (jx, jy) = ((c.x - p.x) / dist, (c.y - p.y) / dist);
+            a11 += jx * jx;
+            a12 += jx * jy;
+            a22 += jy * jy;
+            b1 -= jx * r;
+            b2 -= jy * r;
+        }
+        let det = a11 * a22 - a12 * a12;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let dx = (a22 * b1 - a12 * b2) / det;
+        let dy = (a11 * b2 - a12 * b1) / det;
+        c = Point::new(c.x + dx, c.y + dy);
+        if dx.hypot(dy) < 1e-6 {
+            break;
+        }
+    }
+
+    if c.distance(sensor) < centroid.distance(sensor) {
+        None
+    } else {
+        Some(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Convention;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn circle_fit() {
+        let (center, radius) = (Point::new(1.5, 0.3), 0.04);
+        let sensor = Point::new(0.0, 0.0);
+        let facing = (sensor.y - center.y).atan2(sensor.x - center.x);
+        let points = (-6..=6)
+            .map(|i| {
+                let a = facing + i as f64 * 0.1;
+                let noise = if i % 2 == 0 { 0.001 } else { -0.001 };
+                Point::new(
+                    center.x + (radius + noise) * a.cos(),
+                    center.y + (radius + noise) * a.sin(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let fitted = fit_circle(&points, radius, &sensor).unwrap();
+        assert!(fitted.distance(&center) < 0.002);
+    }
+
+    #[test]
+    fn detection() {
+        // a beacon and a wall at 3 m, seen by a UST05LN every 0.005 rad
+        let (center, radius) = (Point::new(1.5, 0.3), 0.04);
+        let scan = (0..1257)
+            .map(|i| {
+                let a = -PI + i as f64 * 0.005;
+                let (c, s) = (a.cos(), a.sin());
+                let along = c * center.x + s * center.y;
+                let across2 = center.x.powi(2) + center.y.powi(2) - along.powi(2);
+                let distance = if across2 < radius * radius {
+                    along - (radius * radius - across2).sqrt()
+                } else if c > 0.5 {
+                    3.0 / c
+                } else {
+                    return None;
+                };
+                Some(Sample {
+                    angle: a,
+                    distance: (distance * 1000.0).round() as u16,
+                    quality: 200,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let detector =
+            BeaconDetector::new(Mount::new(Convention::UST05LN), BeaconConfig::default());
+        let beacons = detector.detect(&scan);
+        assert_eq!(beacons.len(), 1);
+        assert!(beacons[0].center.distance(&center) < 0.005);
+        assert!(beacons[0].residual < 0.002);
+        assert_eq!(beacons[0].quality, 200.0);
+    }
+}
@@ -0,0 +1,686 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lidar_rd::calibration::{device_key, stored_correction};
+use lidar_rd::lidar::unix_time;
+use lidar_rd::mcap::McapWriter;
+use lidar_rd::record::{open_recording, Format, ReplayLidar};
+use lidar_rd::{Driver, Lidar, LidarServer, Mount, MountConfig, NetworkLidar, Sample};
+
+const USAGE: &str = "Usage : lidar <command> [options]
+
+Commands :
+    stream                 print the scans on the standard output
+    record <file>          record the scans to a file, with the raw packets for .mcap
+    replay <file>          print the scans of a recording, with its timing
+    info                   describe the lidar and its first scans
+    stats                  print scan statistics every second
+    convert <in> <out>     convert a recording to another format or to .mcap
+    view                   live top-down view, with rpm and error rates
+    serve                  browser visualizer of the scans, clusters and tracks
+    foxglove               publish the scans to Foxglove Studio
+    ivy                    publish scans, obstacles and health on an Ivy bus
+    udp                    send the scans to remote --udp sources
+    mqtt                   publish scan summaries to an MQTT broker
+
+Source of the scans (all commands but convert) :
+    --driver <ld06|xv11|ust05ln>
+    --port <port>          serial port [/dev/ttyUSB0]
+    --baud <rate>          baud rate [default of the driver]
+    --input <file>         read a recording (scans or .mcap) instead of a lidar
+    --udp <address:port>   receive the scans of a remote `lidar udp` instead
+
+Options :
+    --format <format>      scans, text, csv or json [text, scans when recording]
+    --duration <seconds>   stop after this time, 0 to never stop [0]
+    --speed <factor>       replay speed [1]
+    --loop                 replay forever
+    --range <meters>       range shown by view [4]
+    --mount <file>         mount of the lidar for view, serve, foxglove, mqtt and .mcap files,
+                           see lidar_calibrate
+    --listen <address>     address of serve [0.0.0.0:8080] or foxglove [0.0.0.0:8765]
+    --topic <topic>        topic of the foxglove scans [/scan]
+    --frame-id <name>      sensor frame of the foxglove, mqtt and .mcap scans
+                           [frame_id of the mount file, else lidar]
+    --to <address:port,..> destinations of udp, broadcast addresses included
+
+Ivy options :
+    --bus <address:port>   Ivy bus [127.255.255.255:2010]
+    --name <name>          agent name [lidar]
+    --peer <address:port>  also connect to this agent directly
+    --sender <id>          sender of the messages [lidar]
+    --sectors <n>          sectors of the obstacle summary [8]
+    --scan-format <text>   scan message, empty to disable
+                           [{sender} LIDAR_SCAN {time} {angles} {distances}]
+    --obstacles-format <text>
+                           [{sender} LIDAR_OBSTACLES {time} {distance} {bearing} {sectors}]
+    --health-format <text> sent every second
+                           [{sender} LIDAR_STATUS {time} {rpm} {scan_rate} {error_rate}]
+
+MQTT options :
+    --broker <host:port>   broker [localhost:1883]
+    --client-id <id>       client identifier [lidar]
+    --username <name>      user name, with --password <password>
+    --summary-topic <topic>
+                           topic of the summaries, with the closest point per
+                           sector, rpm and error rate [lidar/summary]
+    --scan-topic <topic>   also publish the full scans on this topic
+    --sectors <n>          sectors of the summaries [8]
+    --retain               have the broker retain the last summary";
+
+/// Parsed command line : the command, positional arguments and `--key value` options.
+struct Args {
+    command: String,
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+const FLAGS: [&str; 3] = ["loop", "help", "retain"];
+
+/// Options taking a value.
+const OPTIONS: [&str; 28] = [
+    "driver",
+    "port",
+    "baud",
+    "input",
+    "udp",
+    "format",
+    "duration",
+    "speed",
+    "range",
+    "mount",
+    "listen",
+    "topic",
+    "frame-id",
+    "to",
+    "bus",
+    "name",
+    "peer",
+    "sender",
+    "sectors",
+    "scan-format",
+    "obstacles-format",
+    "health-format",
+    "broker",
+    "client-id",
+    "username",
+    "password",
+    "summary-topic",
+    "scan-topic",
+];
+
+impl Args {
+    fn parse() -> Result<Args, Box<dyn Error>> {
+        let mut args = std::env::args().skip(1);
+        let mut parsed = Args {
+            command: args.next().unwrap_or_else(|| "help".into()),
+            positional: vec![],
+            options: HashMap::new(),
+        };
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(key) if FLAGS.contains(&key) => {
+                    parsed.options.insert(key.into(), "true".into());
+                }
+                Some(key) if OPTIONS.contains(&key) => {
+                    let value = args.next().ok_or(format!("missing value for {}", arg))?;
+                    parsed.options.insert(key.into(), value);
+                }
+                Some(_) => return Err(format!("unknown option {}\n\n{}", arg, USAGE).into()),
+                None => parsed.positional.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn get<T: std::str::FromStr>(&self, key: &str, default: T) -> Result<T, Box<dyn Error>>
+    where
+        T::Err: std::fmt::Display,
+    {
+        match self.options.get(key) {
+            Some(value) => value
+                .parse()
+                .map_err(|e| format!("invalid --{} '{}' : {}", key, value, e).into()),
+            None => Ok(default),
+        }
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        self.options.contains_key(key)
+    }
+
+    fn positional(&self, index: usize, name: &str) -> Result<&str, Box<dyn Error>> {
+        self.positional
+            .get(index)
+            .map(|s| s.as_str())
+            .ok_or_else(|| format!("missing <{}> for {}\n\n{}", name, self.command, USAGE).into())
+    }
+
+    fn duration(&self) -> Result<Option<Duration>, Box<dyn Error>> {
+        let seconds: f64 = self.get("duration", 0.0)?;
+        Ok(if seconds > 0.0 {
+            Some(Duration::from_secs_f64(seconds))
+        } else {
+            None
+        })
+    }
+
+    fn format(&self, default: Format) -> Result<Format, Box<dyn Error>> {
+        self.get("format", default)
+    }
+
+    /// Opens and starts the lidar, or the recording, given by the options.
+    fn source(&self) -> Result<Box<dyn Lidar>, Box<dyn Error>> {
+        let mut lidar: Box<dyn Lidar> = match self.options.get("input") {
+            None if self.options.contains_key("udp") => {
+                Box::new(NetworkLidar::new(&self.options["udp"]))
+            }
+            Some(input) => Box::new(
+                ReplayLidar::new(input)?
+                    .with_speed(self.get("speed", 1.0)?)
+                    .with_loop(self.flag("loop")),
+            ),
+            None => {
+                let driver: Driver = self.get("driver", Driver::LD06)?;
+                let port = self.get("port", "/dev/ttyUSB0".to_string())?;
+                let baud = self.get("baud", driver.baud_rate())?;
+                driver.open_calibrated(&port, baud)?
+            }
+        };
+        lidar.start()?;
+        Ok(lidar)
+    }
+
+    /// Name of the sensor frame given by `--frame-id`, else by the mount file.
+    fn frame_id(&self) -> Result<String, Box<dyn Error>> {
+        if let Some(frame_id) = self.options.get("frame-id") {
+            return Ok(frame_id.clone());
+        }
+        let frame_id = match self.options.get("mount") {
+            Some(path) => MountConfig::load(path)?.frame_id,
+            None => None,
+        };
+        Ok(frame_id.unwrap_or_else(|| "lidar".into()))
+    }
+
+    /// Mount file given by `--mount`, or a lidar at the robot origin.
+    fn mount(&self, lidar: &dyn Lidar) -> Result<Mount, Box<dyn Error>> {
+        match self.options.get("mount") {
+            Some(path) => Mount::load(path),
+            None => Ok(Mount::new(lidar.convention())),
+        }
+    }
+}
+
+/// Calls `f` with each scan and the time since the start (seconds), until the
+/// duration is over, the source is exhausted, or `f` returns `false`.
+fn for_each_scan<F>(
+    lidar: &dyn Lidar,
+    duration: Option<Duration>,
+    mut f: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(f64, Vec<Option<Sample>>) -> Result<bool, Box<dyn Error>>,
+{
+    let start = Instant::now();
+    while lidar.is_running() {
+        if duration.is_some_and(|d| start.elapsed() > d) {
+            break;
+        }
+        match lidar.get_scan() {
+            Some(scan) => {
+                if !f(start.elapsed().as_secs_f64(), scan)? {
+                    break;
+                }
+            }
+            None => thread::sleep(Duration::from_millis(2)),
+        }
+    }
+    Ok(())
+}
+
+/// Prints scans on the standard output, stops quietly when it is closed.
+fn print_scans(lidar: &dyn Lidar, args: &Args, format: Format) -> Result<(), Box<dyn Error>> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    format.write_header(&mut out, lidar.convention())?;
+    for_each_scan(lidar, args.duration()?, |time, scan| {
+        match format
+            .write_scan(&mut out, time, &scan)
+            .and_then(|_| out.flush())
+        {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+            result => result.map(|_| true).map_err(|e| e.into()),
+        }
+    })
+}
+
+fn stream(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut lidar = args.source()?;
+    print_scans(lidar.as_ref(), args, args.format(Format::Text)?)?;
+    lidar.stop();
+    Ok(())
+}
+
+fn replay(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut lidar = ReplayLidar::new(args.positional(0, "file")?)?
+        .with_speed(args.get("speed", 1.0)?)
+        .with_loop(args.flag("loop"));
+    lidar.start()?;
+    print_scans(&lidar, args, args.format(Format::Text)?)?;
+    lidar.stop();
+    Ok(())
+}
+
+fn record(args: &Args) -> Result<(), Box<dyn Error>> {
+    let path = args.positional(0, "file")?;
+    if is_mcap_path(path) {
+        return record_mcap(args, path);
+    }
+    let format = args.format(Format::Scans)?;
+    let mut lidar = args.source()?;
+    let mut out = BufWriter::new(File::create(path)?);
+    format.write_header(&mut out, lidar.convention())?;
+    let mut count = 0;
+    for_each_scan(lidar.as_ref(), args.duration()?, |time, scan| {
+        format.write_scan(&mut out, time, &scan)?;
+        // flushed on every scan so that an interrupted recording stays usable
+        out.flush()?;
+        count += 1;
+        eprint!("\r{} scans recorded", count);
+        Ok(true)
+    })?;
+    eprintln!();
+    lidar.stop();
+    Ok(())
+}
+
+fn is_mcap_path(path: &str) -> bool {
+    Path::new(path).extension().and_then(|e| e.to_str()) == Some("mcap")
+}
+
+/// Records the scans and the raw packets of the lidar to an MCAP file.
+fn record_mcap(args: &Args, path: &str) -> Result<(), Box<dyn Error>> {
+    let mut lidar = args.source()?;
+    let mut out = McapWriter::create(path, &args.mount(lidar.as_ref())?, &args.frame_id()?)?;
+    lidar.set_packet_capture(true);
+    let mut count = 0;
+    for_each_scan(lidar.as_ref(), args.duration()?, |_, scan| {
+        for packet in lidar.take_packets() {
+            out.write_packet(&packet)?;
+        }
+        out.write_scan(unix_time(), &scan)?;
+        out.flush()?;
+        count += 1;
+        eprint!("\r{} scans recorded", count);
+        Ok(true)
+    })?;
+    eprintln!();
+    lidar.stop();
+    out.finish()?;
+    Ok(())
+}
+
+fn convert(args: &Args) -> Result<(), Box<dyn Error>> {
+    let input = args.positional(0, "in")?;
+    let output = args.positional(1, "out")?;
+    let (convention, scans) = open_recording(input)?;
+    let mut count = 0;
+
+    if is_mcap_path(output) {
+        // the samples keep the convention of the recording
+        let mount = match args.options.get("mount") {
+            Some(path) => Mount {
+                convention,
+                ..Mount::load(path)?
+            },
+            None => Mount::new(convention),
+        };
+        let mut out = McapWriter::create(output, &mount, &args.frame_id()?)?;
+        for item in scans {
+            let (time, scan) = item?;
+            out.write_scan(time, &scan)?;
+            count += 1;
+        }
+        out.finish()?;
+        eprintln!("{} scans converted to mcap", count);
+        return Ok(());
+    }
+
+    let default = match Path::new(output).extension().and_then(|e| e.to_str()) {
+        Some("csv") => Format::Csv,
+        Some("json") | Some("jsonl") => Format::Json,
+        Some("txt") => Format::Text,
+        _ => Format::Scans,
+    };
+    let format = args.format(default)?;
+    let mut out = BufWriter::new(File::create(output)?);
+    format.write_header(&mut out, convention)?;
+    for item in scans {
+        let (time, scan) = item?;
+        format.write_scan(&mut out, time, &scan)?;
+        count += 1;
+    }
+    out.flush()?;
+    eprintln!("{} scans converted to {}", count, format);
+    Ok(())
+}
+
+fn info(args: &Args) -> Result<(), Box<dyn Error>> {
+    match args.options.get("input") {
+        Some(input) => println!("recording : {}", input),
+        None if args.options.contains_key("udp") => println!("udp : {}", args.options["udp"]),
+        None => {
+            let driver: Driver = args.get("driver", Driver::LD06)?;
+            let port = args.get("port", "/dev/ttyUSB0".to_string())?;
+            println!("driver : {}", driver);
+            println!("port : {}", port);
+            println!("baud rate : {}", args.get("baud", driver.baud_rate())?);
+            println!("unit : {}", device_key(&port));
+            match stored_correction(&port)? {
+                Some(c) => println!("range correction : {} knots", c.knots.len()),
+                None => println!("range correction : none"),
+            }
+        }
+    }
+
+    let mut lidar = args.source()?;
+    println!(
+        "convention : {}",
+        lidar.convention().name().unwrap_or("custom")
+    );
+    let mut scans = vec![];
+    for_each_scan(
+        lidar.as_ref(),
+        Some(Duration::from_secs(3)),
+        |time, scan| {
+            scans.push((time, scan));
+            Ok(scans.len() < 11)
+        },
+    )?;
+    lidar.stop();
+
+    // the first scan is usually partial
+    if scans.len() < 3 {
+        return Err("no scan received".into());
+    }
+    let scans = &scans[1..];
+    let rate = (scans.len() - 1) as f64 / (scans[scans.len() - 1].0 - scans[0].0);
+    let points = scans.iter().map(|(_, s)| s.len()).sum::<usize>() as f64 / scans.len() as f64;
+    let valid = scans
+        .iter()
+        .map(|(_, s)| s.iter().flatten().count())
+        .sum::<usize>() as f64
+        / scans.len() as f64;
+    println!("scan rate : {:.1} Hz", rate);
+    println!("points per turn : {:.0}", points);
+    println!("valid points per turn : {:.0}", valid);
+    println!("angular resolution : {:.2}°", 360.0 / points);
+    Ok(())
+}
+
+fn stats(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut lidar = args.source()?;
+    let (mut scans, mut points, mut valid) = (0, 0, 0);
+    let (mut sum, mut min, mut max) = (0.0, u16::MAX, 0);
+    let mut last = 0.0;
+    let mut last_stats = lidar.stats();
+    println!("scans/s  points/turn  valid%  min(mm)  mean(mm)  max(mm)    rpm  checksum err%");
+    for_each_scan(lidar.as_ref(), args.duration()?, |time, scan| {
+        scans += 1;
+        points += scan.len();
+        for s in scan.iter().flatten() {
+            valid += 1;
+            sum += s.distance as f64;
+            min = min.min(s.distance);
+            max = max.max(s.distance);
+        }
+        if time - last >= 1.0 {
+            let stats = lidar.stats();
+            let packets = stats.packets - last_stats.packets;
+            let errors = stats.checksum_errors - last_stats.checksum_errors;
+            last_stats = stats;
+            println!(
+                "{:7.1}  {:11.0}  {:6.1}  {:7}  {:8.0}  {:7}  {:>5}  {:13.2}",
+                scans as f64 / (time - last),
+                points as f64 / scans as f64,
+                100.0 * valid as f64 / points.max(1) as f64,
+                if valid > 0 { min } else { 0 },
+                sum / valid.max(1) as f64,
+                max,
+                stats
+                    .rpm
+                    .map_or("-".to_string(), |rpm| format!("{:.0}", rpm)),
+                100.0 * errors as f64 / packets.max(1) as f64
+            );
+            scans = 0;
+            points = 0;
+            valid = 0;
+            sum = 0.0;
+            min = u16::MAX;
+            max = 0;
+            last = time;
+        }
+        Ok(true)
+    })?;
+    lidar.stop();
+    Ok(())
+}
+
+#[cfg(feature = "tui")]
+fn view(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut lidar = args.source()?;
+    let mount = args.mount(lidar.as_ref())?;
+    lidar_rd::viewer::Viewer::new(mount, args.get("range", 4.0)?).run(lidar.as_ref())?;
+    lidar.stop();
+    Ok(())
+}
+
+#[cfg(not(feature = "tui"))]
+fn view(_args: &Args) -> Result<(), Box<dyn Error>> {
+    Err("view needs the tui feature".into())
+}
+
+#[cfg(feature = "web")]
+fn serve(args: &Args) -> Result<(), Box<dyn Error>> {
+    use lidar_rd::segmentation::{segment, SegmentationConfig};
+    use lidar_rd::tracking::{Tracker, TrackerConfig};
+    use lidar_rd::web::{Frame, WebServer};
+    use lidar_rd::{Pose, Scan};
+
+    let server = WebServer::bind(args.get("listen", "0.0.0.0:8080".to_string())?)?;
+    eprintln!("serving on http://{}", server.local_addr());
+    let mut lidar = args.source()?;
+    let mount = args.mount(lidar.as_ref())?;
+    let segmentation = SegmentationConfig::default();
+    let mut tracker = Tracker::new(TrackerConfig::default());
+    for_each_scan(lidar.as_ref(), args.duration()?, |time, scan| {
+        let clusters = segment(&scan, &mount, &segmentation);
+        // without localization, the tracks are kept in the robot frame
+        tracker.update_clusters(time, &Pose::default(), &clusters);
+        server.publish(&Frame {
+            time,
+            points: &scan.to_points(&mount),
+            clusters: &clusters,
+            tracks: tracker.tracks(),
+        });
+        Ok(true)
+    })?;
+    lidar.stop();
+    Ok(())
+}
+
+#[cfg(not(feature = "web"))]
+fn serve(_args: &Args) -> Result<(), Box<dyn Error>> {
+    Err("serve needs the web feature".into())
+}
+
+#[cfg(feature = "foxglove")]
+fn foxglove(args: &Args) -> Result<(), Box<dyn Error>> {
+    use lidar_rd::foxglove::FoxgloveServer;
+    use std::time::SystemTime;
+
+    let server = FoxgloveServer::bind(
+        args.get("listen", "0.0.0.0:8765".to_string())?,
+        &args.get("topic", "/scan".to_string())?,
+        &args.frame_id()?,
+    )?;
+    eprintln!("foxglove server on ws://{}", server.local_addr());
+    let mut lidar = args.source()?;
+    let mount = args.mount(lidar.as_ref())?;
+    for_each_scan(lidar.as_ref(), args.duration()?, |_, scan| {
+        server.publish(SystemTime::now(), &mount, &scan);
+        Ok(true)
+    })?;
+    lidar.stop();
+    Ok(())
+}
+
+#[cfg(not(feature = "foxglove"))]
+fn foxglove(_args: &Args) -> Result<(), Box<dyn Error>> {
+    Err("foxglove needs the foxglove feature".into())
+}
+
+fn ivy(args: &Args) -> Result<(), Box<dyn Error>> {
+    use lidar_rd::ivy::{IvyBus, IvyFormat, DEFAULT_BUS};
+
+    let mut format = IvyFormat::default()
+        .with_sender(&args.get("sender", "lidar".to_string())?)
+        .with_sectors(args.get("sectors", 8)?);
+    if let Some(template) = args.options.get("scan-format") {
+        format = format.with_scan(template)?;
+    }
+    if let Some(template) = args.options.get("obstacles-format") {
+        format = format.with_obstacles(template)?;
+    }
+    if let Some(template) = args.options.get("health-format") {
+        format = format.with_health(template)?;
+    }
+    let bus = IvyBus::start(
+        &args.get("name", "lidar".to_string())?,
+        &args.get("bus", DEFAULT_BUS.to_string())?,
+    )?;
+    if let Some(peer) = args.options.get("peer") {
+        bus.connect(peer)?;
+    }
+    eprintln!("ivy agent on port {}", bus.port());
+
+    let mut lidar = args.source()?;
+    let mount = args.mount(lidar.as_ref())?;
+    let (mut last, mut scans) = (0.0, 0);
+    for_each_scan(lidar.as_ref(), args.duration()?, |time, scan| {
+        let messages = [format.scan(&mount, &scan), format.obstacles(&mount, &scan)];
+        for message in messages.iter().flatten() {
+            bus.send(message);
+        }
+        scans += 1;
+        if time - last >= 1.0 {
+            let valid = scan.iter().flatten().count() as f64 / scan.len().max(1) as f64;
+            let rate = scans as f64 / (time - last);
+            if let Some(message) = format.health(&lidar.stats(), rate, valid) {
+                bus.send(&message);
+            }
+            last = time;
+            scans = 0;
+        }
+        Ok(true)
+    })?;
+    lidar.stop();
+    Ok(())
+}
+
+fn udp(args: &Args) -> Result<(), Box<dyn Error>> {
+    let destinations = args.get("to", String::new())?;
+    if destinations.is_empty() {
+        return Err(format!("missing --to for udp\n\n{}", USAGE).into());
+    }
+    let mut lidar = args.source()?;
+    let mut server = LidarServer::bind("0.0.0.0:0", lidar.convention())?;
+    for destination in destinations.split(',') {
+        server.add_destination(destination.trim())?;
+    }
+    let mut count = 0;
+    for_each_scan(lidar.as_ref(), args.duration()?, |_, scan| {
+        server.publish(&scan)?;
+        count += 1;
+        eprint!("\r{} scans sent", count);
+        Ok(true)
+    })?;
+    eprintln!();
+    lidar.stop();
+    Ok(())
+}
+
+#[cfg(feature = "mqtt")]
+fn mqtt(args: &Args) -> Result<(), Box<dyn Error>> {
+    use lidar_rd::mqtt::{MqttBridge, MqttConfig};
+
+    let mut config = MqttConfig::default()
+        .with_broker(&args.get("broker", "localhost:1883".to_string())?)
+        .with_client_id(&args.get("client-id", "lidar".to_string())?)
+        .with_summary_topic(&args.get("summary-topic", "lidar/summary".to_string())?)
+        .with_scan_topic(args.options.get("scan-topic").map(|t| t.as_str()))
+        .with_frame_id(&args.frame_id()?)
+        .with_sectors(args.get("sectors", 8)?)
+        .with_retain(args.flag("retain"));
+    if args.options.contains_key("password") && !args.options.contains_key("username") {
+        return Err("--password needs --username".into());
+    }
+    if let Some(username) = args.options.get("username") {
+        config =
+            config.with_credentials(username, args.options.get("password").map(|p| p.as_str()));
+    }
+    let mut bridge = MqttBridge::new(config);
+    let mut lidar = args.source()?;
+    let mount = args.mount(lidar.as_ref())?;
+    for_each_scan(lidar.as_ref(), args.duration()?, |_, scan| {
+        bridge.publish_scan(&mount, &scan, &lidar.stats());
+        Ok(true)
+    })?;
+    lidar.stop();
+    Ok(())
+}
+
+#[cfg(not(feature = "mqtt"))]
+fn mqtt(_args: &Args) -> Result<(), Box<dyn Error>> {
+    Err("mqtt needs the mqtt feature".into())
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse()?;
+    if args.flag("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    match args.command.as_str() {
+        "stream" => stream(&args),
+        "record" => record(&args),
+        "replay" => replay(&args),
+        "info" => info(&args),
+        "stats" => stats(&args),
+        "convert" => convert(&args),
+        "view" => view(&args),
+        "serve" => serve(&args),
+        "foxglove" => foxglove(&args),
+        "ivy" => ivy(&args),
+        "udp" => udp(&args),
+        "mqtt" => mqtt(&args),
+        "help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        command => Err(format!("unknown command {}\n\n{}", command, USAGE).into()),
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
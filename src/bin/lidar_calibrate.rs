@@ -0,0 +1,142 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use lidar_rd::beacons::BeaconConfig;
+use lidar_rd::calibration::{CalibrationTarget, ExtrinsicCalibration};
+use lidar_rd::{Driver, Mount, Point};
+
+const USAGE: &str =
+    "Usage : lidar_calibrate --driver <ld06|xv11|ust05ln> --port <port> <target> [options]
+
+Estimates where a lidar is mounted on the robot, and writes the mount file.
+
+Target (robot frame, meters and degrees) :
+    --corner <x>,<y>,<angle>      right angle corner, angle of the first wall
+                                  (the second one is 90° counter-clockwise)
+    --beacons <x>,<y>;<x>,<y>...  beacons at known positions
+    --radius <r>                  radius of the beacons [0.04]
+
+Options :
+    --flipped                     the lidar is mounted upside down
+    --turns <n>                   number of scans to average [20]
+    --output <file>               mount file to write [mount.cfg]";
+
+fn parse_numbers(text: &str) -> Result<Vec<f64>, Box<dyn Error>> {
+    Ok(text
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut driver = None;
+    let mut port = None;
+    let mut corner = None;
+    let mut beacons = None;
+    let mut radius = 0.04;
+    let mut flipped = false;
+    let mut turns = 20;
+    let mut output = "mount.cfg".to_string();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or(format!("missing value for {}\n\n{}", arg, USAGE))
+        };
+        match arg.as_str() {
+            "--driver" => driver = Some(value()?.parse::<Driver>()?),
+            "--port" => port = Some(value()?),
+            "--corner" => corner = Some(parse_numbers(&value()?)?),
+            "--beacons" => {
+                let positions = value()?
+                    .split(';')
+                    .map(parse_numbers)
+                    .collect::<Result<Vec<_>, _>>()?;
+                beacons = Some(positions);
+            }
+            "--radius" => radius = value()?.parse()?,
+            "--flipped" => flipped = true,
+            "--turns" => turns = value()?.parse()?,
+            "--output" => output = value()?,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => return Err(format!("unknown argument {}\n\n{}", arg, USAGE).into()),
+        }
+    }
+
+    let driver = driver.ok_or(format!("missing --driver\n\n{}", USAGE))?;
+    let port = port.ok_or(format!("missing --port\n\n{}", USAGE))?;
+    let target = match (corner, beacons) {
+        (Some(c), None) if c.len() == 3 => CalibrationTarget::Corner {
+            corner: Point::new(c[0], c[1]),
+            direction: c[2].to_radians(),
+        },
+        (None, Some(b)) if b.iter().all(|p| p.len() == 2) => CalibrationTarget::Beacons {
+            positions: b.iter().map(|p| Point::new(p[0], p[1])).collect(),
+            config: BeaconConfig {
+                radius,
+                ..BeaconConfig::default()
+            },
+        },
+        _ => {
+            return Err(format!(
+                "expected one of --corner x,y,angle or --beacons x,y;x,y...\n\n{}",
+                USAGE
+            )
+            .into())
+        }
+    };
+
+    let mount = Mount::new(driver.convention()).with_flipped(flipped);
+    let mut calibration = ExtrinsicCalibration::new(mount, target);
+
+    let mut lidar = driver.open_calibrated(&port, driver.baud_rate())?;
+    lidar.start()?;
+    let start = Instant::now();
+    let mut scans = 0;
+    while calibration.len() < turns {
+        if start.elapsed() > Duration::from_secs(10) + Duration::from_millis(500 * turns as u64) {
+            break;
+        }
+        if let Some(scan) = lidar.get_scan() {
+            scans += 1;
+            match calibration.add_scan(&scan) {
+                Some(m) => println!(
+                    "scan {} : x={:.4} y={:.4} yaw={:.2}°",
+                    scans,
+                    m.x,
+                    m.y,
+                    m.yaw.to_degrees()
+                ),
+                None => println!("scan {} : target not found", scans),
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    lidar.stop();
+
+    let (mount, std) = calibration.result().ok_or("target never found")?;
+    println!(
+        "\nmount from {} scans : x={:.4} y={:.4} yaw={:.2}° (std {:.4}, {:.4}, {:.2}°)",
+        calibration.len(),
+        mount.x,
+        mount.y,
+        mount.yaw.to_degrees(),
+        std[0],
+        std[1],
+        std[2].to_degrees()
+    );
+    mount.save(&output)?;
+    println!("saved to {}", output);
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,756 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serialport::SerialPortType;
+
+use crate::beacons::{BeaconConfig, BeaconDetector};
+use crate::filter::Filter;
+use crate::lidar::Sample;
+use crate::lines::{split_and_merge, LineSegment, SplitAndMergeConfig};
+use crate::localization::{BeaconMap, LocalizationConfig, Localizer};
+use crate::transform::{normalize_angle, Convention, Mount, Point, Scan};
+
+/// Known calibration target, in the robot frame.
+#[derive(Clone, Debug)]
+pub enum CalibrationTarget {
+    /// Right angle corner made of two walls. `direction` is the angle (radians)
+    /// of the first wall, going away from the corner; the second wall goes
+    /// counter-clockwise at 90° from it.
+    Corner { corner: Point, direction: f64 },
+    /// Cylindrical beacons at known positions. At least 2 must be visible.
+    Beacons {
+        positions: Vec<Point>,
+        config: BeaconConfig,
+    },
+}
+
+/// Estimates the mounting pose of a lidar from scans of a known target.
+///
+/// The convention and the `flipped` flag of the mount can't be estimated and
+/// must be given; the offset and yaw are estimated and averaged over scans.
+pub struct ExtrinsicCalibration {
+    target: CalibrationTarget,
+    template: Mount,
+    lines: SplitAndMergeConfig,
+    estimates: Vec<Mount>,
+}
+
+impl ExtrinsicCalibration {
+    pub fn new(template: Mount, target: CalibrationTarget) -> ExtrinsicCalibration {
+        ExtrinsicCalibration {
+            target,
+            template: Mount::new(template.convention).with_flipped(template.flipped),
+            lines: SplitAndMergeConfig {
+                min_length: 0.2,
+                ..SplitAndMergeConfig::default()
+            },
+            estimates: vec![],
+        }
+    }
+
+    pub fn with_lines_config(mut self, lines: SplitAndMergeConfig) -> ExtrinsicCalibration {
+        self.lines = lines;
+        self
+    }
+
+    /// Number of scans in which the target was found.
+    pub fn len(&self) -> usize {
+        self.estimates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.estimates.is_empty()
+    }
+
+    /// Estimates the mount from a single scan, and keeps it for the average.
+    pub fn add_scan(&mut self, scan: &[Option<Sample>]) -> Option<Mount> {
+        let mount = match &self.target {
+            CalibrationTarget::Corner { corner, direction } => {
+                self.locate_corner(scan, *corner, *direction)
+            }
+            CalibrationTarget::Beacons { positions, config } => {
+                let detector = BeaconDetector::new(self.template, *config);
+                let detections = detector
+                    .detect(scan)
+                    .iter()
+                    .map(|b| b.center)
+                    .collect::<Vec<_>>();
+                // the "world" is the robot frame, and the "robot" the sensor
+                let localizer = Localizer::new(
+                    BeaconMap::new(positions.clone()),
+                    LocalizationConfig::default(),
+                );
+                let pose = localizer.estimate(&detections, None)?.pose;
+                Some(
+                    self.template
+                        .with_offset(pose.x, pose.y)
+                        .with_yaw(pose.theta),
+                )
+            }
+        }?;
+        self.estimates.push(mount);
+        Some(mount)
+    }
+
+    /// Mean of the estimates, with the standard deviation of `(x, y, yaw)`.
+    pub fn result(&self) -> Option<(Mount, [f64; 3])> {
+        if self.estimates.is_empty() {
+            return None;
+        }
+        let n = self.estimates.len() as f64;
+        let x = self.estimates.iter().map(|m| m.x).sum::<f64>() / n;
+        let y = self.estimates.iter().map(|m| m.y).sum::<f64>() / n;
+        let yaw = self
+            .estimates
+            .iter()
+            .map(|m| m.yaw.sin())
+            .sum::<f64>()
+            .atan2(self.estimates.iter().map(|m| m.yaw.cos()).sum::<f64>());
+        let std = |f: &dyn Fn(&Mount) -> f64| {
+            (self.estimates.iter().map(|m| f(m).powi(2)).sum::<f64>() / n).sqrt()
+        };
+        let deviation = [
+            std(&|m| m.x - x),
+            std(&|m| m.y - y),
+            std(&|m| normalize_angle(m.yaw - yaw)),
+        ];
+        Some((self.template.with_offset(x, y).with_yaw(yaw), deviation))
+    }
+
+    fn locate_corner(
+        &self,
+        scan: &[Option<Sample>],
+        corner: Point,
+        direction: f64,
+    ) -> Option<Mount> {
+        let points = scan.to_points(&self.template);
+        let segments = split_and_merge(&points, &self.lines);
+
+        // best pair of perpendicular segments meeting at their ends
+        let mut best: Option<(f64, Point, Point, Point)> = None;
+        for (i, s1) in segments.iter().enumerate() {
+            for s2 in segments.iter().skip(i + 1) {
+                let angle = normalize_angle(s1.alpha - s2.alpha).abs();
+                if (angle - std::f64::consts::FRAC_PI_2).abs() > 10f64.to_radians() {
+                    continue;
+                }
+                let c = match s1.intersection(s2) {
+                    Some(c) => c,
+                    None => continue,
+                };
+                let (d1, d2) = match (away_from(s1, &c), away_from(s2, &c)) {
+                    (Some(d1), Some(d2)) => (d1, d2),
+                    _ => continue,
+                };
+                let length = s1.length() + s2.length();
+                if best.as_ref().map_or(true, |b| length > b.0) {
+                    best = Some((length, c, d1, d2));
+                }
+            }
+        }
+        let (_, c, d1, d2) = best?;
+
+        // first wall is the one the second turns counter-clockwise from
+        let d1 = if d1.x * d2.y - d1.y * d2.x > 0.0 {
+            d1
+        } else {
+            d2
+        };
+        let yaw = normalize_angle(direction - d1.angle());
+        let (s, co) = yaw.sin_cos();
+        Some(self.template.with_yaw(yaw).with_offset(
+            corner.x - (co * c.x - s * c.y),
+            corner.y - (s * c.x + co * c.y),
+        ))
+    }
+}
+
+/// Unit direction of a segment going away from `corner`, if one of its ends is near it.
+fn away_from(segment: &LineSegment, corner: &Point) -> Option<Point> {
+    let tolerance = 0.1f64.max(segment.length() * 0.2);
+    let (near, far) = if segment.start.distance(corner) < segment.end.distance(corner) {
+        (segment.start, segment.end)
+    } else {
+        (segment.end, segment.start)
+    };
+    if near.distance(corner) > tolerance {
+        return None;
+    }
+    let len = far.distance(corner);
+    Some(Point::new(
+        (far.x - corner.x) / len,
+        (far.y - corner.y) / len,
+    ))
+}
+
+/// Per-unit correction of the measured ranges, as a pipeline stage.
+///
+/// The bias removed from a sample is interpolated from `knots` at its measured
+/// distance, plus `quality_gain * (quality - quality_ref)` : dimmer returns
+/// tend to be measured farther on triangulation lidars.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RangeCorrection {
+    /// `(measured distance, bias)` pairs in mm, sorted by distance.
+    pub knots: Vec<(f64, f64)>,
+    /// Bias added per unit of quality, in mm.
+    pub quality_gain: f64,
+    pub quality_ref: f64,
+}
+
+impl RangeCorrection {
+    /// Bias of a sample, in mm (measured minus true distance).
+    pub fn bias(&self, distance: f64, quality: f64) -> f64 {
+        let range_bias = match self.knots.iter().position(|k| k.0 > distance) {
+            _ if self.knots.is_empty() => 0.0,
+            Some(0) => self.knots[0].1,
+            None => self.knots[self.knots.len() - 1].1,
+            Some(i) => {
+                let ((d0, b0), (d1, b1)) = (self.knots[i - 1], self.knots[i]);
+                b0 + (b1 - b0) * (distance - d0) / (d1 - d0)
+            }
+        };
+        range_bias + self.quality_gain * (quality - self.quality_ref)
+    }
+
+    /// Parses a correction made of `key = value` lines :
+    ///
+    /// ```text
+    /// knots = 200:12.5 500:8.1 1000:-3.0
+    /// quality_gain = -0.02
+    /// quality_ref = 180
+    /// ```
+    pub fn parse(text: &str) -> Result<RangeCorrection, Box<dyn Error>> {
+        let mut correction = RangeCorrection::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("invalid correction line: {}", line).into()),
+            };
+            match key {
+                "knots" => {
+                    for knot in value.split_whitespace() {
+                        let (d, b) = knot
+                            .split_once(':')
+                            .ok_or_else(|| format!("invalid knot: {}", knot))?;
+                        correction.knots.push((parse_finite(d)?, parse_finite(b)?));
+                    }
+                }
+                "quality_gain" => correction.quality_gain = parse_finite(value)?,
+                "quality_ref" => correction.quality_ref = parse_finite(value)?,
+                _ => return Err(format!("unknown correction key: {}", key).into()),
+            }
+        }
+        correction.knots.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(correction)
+    }
+}
+
+fn parse_finite(value: &str) -> Result<f64, Box<dyn Error>> {
+    match value.parse::<f64>()? {
+        v if v.is_finite() => Ok(v),
+        _ => Err(format!("invalid correction value: {}", value).into()),
+    }
+}
+
+impl fmt::Display for RangeCorrection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let knots = self
+            .knots
+            .iter()
+            .map(|(d, b)| format!("{:.1}:{:.2}", d, b))
+            .collect::<Vec<_>>();
+        writeln!(f, "knots = {}", knots.join(" "))?;
+        writeln!(f, "quality_gain = {}", self.quality_gain)?;
+        writeln!(f, "quality_ref = {}", self.quality_ref)
+    }
+}
+
+impl Filter for RangeCorrection {
+    fn apply(&mut self, scan: &mut [Option<Sample>]) {
+        self.correct(scan);
+    }
+}
+
+impl RangeCorrection {
+    /// Removes the bias of the samples of a scan.
+    pub fn correct(&self, scan: &mut [Option<Sample>]) {
+        for s in scan.iter_mut().flatten() {
+            // 0 is "no echo" for the LD06 and XV11, keep it for the range filters
+            if s.distance > 0 {
+                let d = s.distance as f64 - self.bias(s.distance as f64, s.quality as f64);
+                s.distance = d.round().clamp(1.0, u16::MAX as f64) as u16;
+            }
+        }
+    }
+}
+
+/// Fits a `RangeCorrection` from scans of a flat wall at known distances.
+///
+/// Only the wall distance is needed : its orientation is estimated from the scan,
+/// so the lidar doesn't have to face it exactly.
+pub struct RangeCalibration {
+    mount: Mount,
+    /// Width of the distance bins of the correction, in mm.
+    pub bin_size: f64,
+    /// Samples hitting the wall with a larger incidence angle are ignored, in radians.
+    pub max_incidence: f64,
+    /// Least number of samples in a bin to make a knot.
+    pub min_samples: usize,
+    // (measured, quality, expected) distances in mm
+    observations: Vec<(f64, f64, f64)>,
+}
+
+impl RangeCalibration {
+    pub fn new(convention: Convention) -> RangeCalibration {
+        RangeCalibration {
+            mount: Mount::new(convention),
+            bin_size: 250.0,
+            max_incidence: 30f64.to_radians(),
+            min_samples: 20,
+            observations: vec![],
+        }
+    }
+
+    pub fn with_bin_size(mut self, bin_size: f64) -> RangeCalibration {
+        self.bin_size = bin_size;
+        self
+    }
+
+    pub fn with_max_incidence(mut self, max_incidence: f64) -> RangeCalibration {
+        self.max_incidence = max_incidence;
+        self
+    }
+
+    /// Number of samples kept so far.
+    pub fn len(&self) -> usize {
+        self.observations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observations.is_empty()
+    }
+
+    /// Adds a scan of a flat wall, `distance` meters away from the lidar center
+    /// (perpendicularly). The wall is the longest line of the scan.
+    ///
+    /// Returns the number of samples used.
+    pub fn add_wall_scan(&mut self, scan: &[Option<Sample>], distance: f64) -> usize {
+        let samples = scan.iter().flatten().collect::<Vec<_>>();
+        let points = samples
+            .iter()
+            .map(|s| self.mount.sample_to_point(s))
+            .collect::<Vec<_>>();
+        let config = SplitAndMergeConfig {
+            split_threshold: 0.05,
+            merge_threshold: 0.05,
+            ..SplitAndMergeConfig::default()
+        };
+        let wall = match split_and_merge(&points, &config)
+            .into_iter()
+            .max_by(|a, b| a.length().partial_cmp(&b.length()).unwrap())
+        {
+            Some(wall) => wall,
+            None => return 0,
+        };
+
+        let before = self.observations.len();
+        for &i in &wall.inliers {
+            let incidence = normalize_angle(points[i].angle() - wall.alpha);
+            if incidence.abs() > self.max_incidence {
+                continue;
+            }
+            self.observations.push((
+                samples[i].distance as f64,
+                samples[i].quality as f64,
+                distance * 1000.0 / incidence.cos(),
+            ));
+        }
+        self.observations.len() - before
+    }
+
+    /// Fits the correction, alternating between the distance bias and the
+    /// quality gain. Returns `None` if no bin has enough samples.
+    pub fn fit(&self) -> Option<RangeCorrection> {
+        let n = self.observations.len() as f64;
+        let mut correction = RangeCorrection {
+            knots: vec![],
+            quality_gain: 0.0,
+            quality_ref: self.observations.iter().map(|o| o.1).sum::<f64>() / n,
+        };
+
+        for _ in 0..5 {
+            let mut bins = BTreeMap::<i64, (f64, f64, usize)>::new();
+            for &(measured, quality, expected) in &self.observations {
+                let quality_bias = correction.quality_gain * (quality - correction.quality_ref);
+                let bin = bins.entry((measured / self.bin_size) as i64).or_default();
+                bin.0 += measured;
+                bin.1 += measured - expected - quality_bias;
+                bin.2 += 1;
+            }
+            correction.knots = bins
+                .values()
+                .filter(|b| b.2 >= self.min_samples)
+                .map(|b| (b.0 / b.2 as f64, b.1 / b.2 as f64))
+                .collect();
+            if correction.knots.is_empty() {
+                return None;
+            }
+
+            // quality gain : regression of what the distance bias doesn't explain
+            let (mut cov, mut var) = (0.0, 0.0);
+            let range_only = RangeCorrection {
+                quality_gain: 0.0,
+                ..correction.clone()
+            };
+            for &(measured, quality, expected) in &self.observations {
+                let residual = measured - expected - range_only.bias(measured, quality);
+                let dq = quality - correction.quality_ref;
+                cov += dq * residual;
+                var += dq * dq;
+            }
+            correction.quality_gain = if var > 0.0 { cov / var } else { 0.0 };
+        }
+        Some(correction)
+    }
+}
+
+/// Range corrections of several units, in a file made of `[key]` sections
+/// holding `RangeCorrection`s.
+pub struct CorrectionStore {
+    path: PathBuf,
+}
+
+impl CorrectionStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> CorrectionStore {
+        CorrectionStore {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// `$LIDAR_RD_CALIBRATION`, or `~/.config/lidar_rd/range_corrections.cfg`.
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("LIDAR_RD_CALIBRATION") {
+            return Some(path.into());
+        }
+        env::var_os("HOME").map(|home| {
+            Path::new(&home)
+                .join(".config")
+                .join("lidar_rd")
+                .join("range_corrections.cfg")
+        })
+    }
+
+    fn sections(&self) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut sections: Vec<(String, String)> = vec![];
+        for line in text.lines() {
+            let trimmed = line.trim();
+            if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                sections.push((trimmed[1..trimmed.len() - 1].to_string(), String::new()));
+            } else if let Some((_, body)) = sections.last_mut() {
+                body.push_str(line);
+                body.push('\n');
+            }
+        }
+        Ok(sections)
+    }
+
+    /// Correction stored for a unit, `None` if there is none.
+    pub fn load(&self, key: &str) -> Result<Option<RangeCorrection>, Box<dyn Error>> {
+        match self.sections()?.into_iter().find(|(k, _)| k == key) {
+            Some((_, body)) => Ok(Some(RangeCorrection::parse(&body)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores the correction of a unit, replacing the previous one.
+    pub fn save(&self, key: &str, correction: &RangeCorrection) -> Result<(), Box<dyn Error>> {
+        let mut sections = self.sections()?;
+        sections.retain(|(k, _)| k != key);
+        sections.push((key.to_string(), correction.to_string()));
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = sections
+            .iter()
+            .map(|(k, body)| format!("[{}]\n{}", k, body.trim_end()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        fs::write(&self.path, text + "\n")?;
+        Ok(())
+    }
+}
+
+/// Key identifying the unit connected on `port` : the USB serial number when
+/// there is one, else the port itself.
+pub fn device_key(port: &str) -> String {
+    let resolved = fs::canonicalize(port).unwrap_or_else(|_| PathBuf::from(port));
+    let ports = serialport::available_ports().unwrap_or_default();
+    ports
+        .iter()
+        .filter(|p| {
+            fs::canonicalize(&p.port_name).unwrap_or_else(|_| PathBuf::from(&p.port_name))
+                == resolved
+        })
+        .find_map(|p| match &p.port_type {
+            SerialPortType::UsbPort(usb) => usb
+                .serial_number
+                .as_ref()
+                .map(|serial| format!("usb-{:04x}:{:04x}-{}", usb.vid, usb.pid, serial)),
+            _ => None,
+        })
+        .unwrap_or_else(|| port.to_string())
+}
+
+/// Correction stored in the default store for the unit on `port`, if any.
+pub fn stored_correction(port: &str) -> Result<Option<RangeCorrection>, Box<dyn Error>> {
+    match CorrectionStore::default_path() {
+        Some(path) => CorrectionStore::new(path).load(&device_key(port)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// UST05LN scan from `mount`, `range` giving the distance hit by a ray
+    /// (origin and unit direction in the robot frame).
+    fn scan(mount: &Mount, range: impl Fn(Point, Point) -> Option<f64>) -> Vec<Option<Sample>> {
+        (0..=920)
+            .map(|i| {
+                let a = -2.3 + 0.005 * i as f64;
+                let dir = Point::new((mount.yaw + a).cos(), (mount.yaw + a).sin());
+                range(Point::new(mount.x, mount.y), dir).map(|r| Sample {
+                    angle: mount.convention.from_sensor_angle(a),
+                    distance: (r * 1000.0).round() as u16,
+                    quality: 200,
+                })
+            })
+            .collect()
+    }
+
+    fn hit_segment(o: Point, d: Point, a: Point, b: Point) -> Option<f64> {
+        let (ex, ey) = (b.x - a.x, b.y - a.y);
+        let det = ex * d.y - ey * d.x;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let t = (ex * (a.y - o.y) - ey * (a.x - o.x)) / det;
+        let s = (d.x * (a.y - o.y) - d.y * (a.x - o.x)) / det;
+        if t > 0.0 && (0.0..=1.0).contains(&s) {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    fn hit_circle(o: Point, d: Point, center: Point, radius: f64) -> Option<f64> {
+        let along = (center.x - o.x) * d.x + (center.y - o.y) * d.y;
+        let across2 = o.distance(&center).powi(2) - along * along;
+        if along > 0.0 && across2 < radius * radius {
+            Some(along - (radius * radius - across2).sqrt())
+        } else {
+            None
+        }
+    }
+
+    fn assert_mount(estimated: &Mount, truth: &Mount) {
+        assert!((estimated.x - truth.x).abs() < 0.01, "{:?}", estimated);
+        assert!((estimated.y - truth.y).abs() < 0.01, "{:?}", estimated);
+        assert!(
+            normalize_angle(estimated.yaw - truth.yaw).abs() < 0.01,
+            "{:?}",
+            estimated
+        );
+        assert_eq!(estimated.convention, truth.convention);
+    }
+
+    #[test]
+    fn corner() {
+        let truth = Mount::new(Convention::UST05LN)
+            .with_offset(0.2, -0.1)
+            .with_yaw(0.3);
+        // walls going from (1, 0.5) towards -x, then towards -y
+        let c = Point::new(1.0, 0.5);
+        let walls = |o, d| {
+            let first = hit_segment(o, d, c, Point::new(-2.0, 0.5));
+            let second = hit_segment(o, d, c, Point::new(1.0, -2.0));
+            first.into_iter().chain(second).reduce(f64::min)
+        };
+        let target = CalibrationTarget::Corner {
+            corner: c,
+            direction: std::f64::consts::PI,
+        };
+
+        let mut calibration = ExtrinsicCalibration::new(Mount::new(Convention::UST05LN), target);
+        assert!(calibration.result().is_none());
+        let corner = scan(&truth, walls);
+        assert_mount(&calibration.add_scan(&corner).unwrap(), &truth);
+        calibration.add_scan(&corner).unwrap();
+        assert_eq!(calibration.len(), 2);
+        let (mount, deviation) = calibration.result().unwrap();
+        assert_mount(&mount, &truth);
+        assert!(deviation.iter().all(|&d| d < 1e-9));
+
+        // nothing but a single wall
+        let wall = |o, d| hit_segment(o, d, c, Point::new(-2.0, 0.5));
+        assert!(calibration.add_scan(&scan(&truth, wall)).is_none());
+        assert_eq!(calibration.len(), 2);
+    }
+
+    #[test]
+    fn beacons() {
+        let truth = Mount::new(Convention::UST05LN)
+            .with_offset(-0.1, 0.15)
+            .with_yaw(-0.4);
+        let positions = vec![
+            Point::new(1.0, 0.8),
+            Point::new(1.2, -0.7),
+            Point::new(-0.5, -1.0),
+        ];
+        let beacons = |o, d| {
+            positions
+                .iter()
+                .filter_map(|&p| hit_circle(o, d, p, 0.04))
+                .reduce(f64::min)
+        };
+        let target = CalibrationTarget::Beacons {
+            positions: positions.clone(),
+            config: BeaconConfig::default(),
+        };
+        let mut calibration = ExtrinsicCalibration::new(Mount::new(Convention::UST05LN), target);
+        let mount = calibration.add_scan(&scan(&truth, beacons)).unwrap();
+        assert_mount(&mount, &truth);
+    }
+
+    #[test]
+    fn correction_text() {
+        let text = "# unit 42\nknots = 1000:-3 200:12.5 500:8.1\nquality_gain = -0.02\nquality_ref = 180\n";
+        let correction = RangeCorrection::parse(text).unwrap();
+        assert_eq!(
+            correction.knots,
+            [(200.0, 12.5), (500.0, 8.1), (1000.0, -3.0)]
+        );
+        assert_eq!(
+            (correction.quality_gain, correction.quality_ref),
+            (-0.02, 180.0)
+        );
+        assert_eq!(
+            RangeCorrection::parse(&correction.to_string()).unwrap(),
+            correction
+        );
+
+        for invalid in [
+            "knots = 200:NaN",
+            "knots = inf:1",
+            "knots = 200",
+            "quality_gain = nan",
+            "offset = 3",
+            "knots",
+        ] {
+            assert!(RangeCorrection::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn correction() {
+        let mut correction = RangeCorrection::parse(
+            "knots = 200:10 1000:-10\nquality_gain = 0.1\nquality_ref = 100\n",
+        )
+        .unwrap();
+        assert_eq!(correction.bias(100.0, 100.0), 10.0);
+        assert_eq!(correction.bias(600.0, 100.0), 0.0);
+        assert_eq!(correction.bias(2000.0, 150.0), -5.0);
+        assert_eq!(RangeCorrection::default().bias(500.0, 10.0), 0.0);
+
+        let sample = |distance, quality| {
+            Some(Sample {
+                angle: 0.0,
+                distance,
+                quality,
+            })
+        };
+        let mut scan = [
+            sample(0, 100),
+            sample(640, 100),
+            sample(210, 0),
+            sample(5, 100),
+        ];
+        correction.apply(&mut scan);
+        let distances = scan.iter().map(|s| s.unwrap().distance).collect::<Vec<_>>();
+        assert_eq!(distances, [0, 641, 210, 1]);
+    }
+
+    #[test]
+    fn store() {
+        let dir = env::temp_dir().join(format!("lidar_rd_store_{}", std::process::id()));
+        let store = CorrectionStore::new(dir.join("corrections.cfg"));
+        assert!(store.load("a").unwrap().is_none());
+
+        let a = RangeCorrection {
+            knots: vec![(200.0, 12.5), (1000.0, -3.0)],
+            quality_gain: -0.02,
+            quality_ref: 180.0,
+        };
+        let b = RangeCorrection {
+            knots: vec![(500.0, 1.0)],
+            ..RangeCorrection::default()
+        };
+        store.save("a", &a).unwrap();
+        store.save("b", &b).unwrap();
+        store.save("a", &b).unwrap();
+        assert_eq!(store.load("a").unwrap(), Some(b.clone()));
+        assert_eq!(store.load("b").unwrap(), Some(b));
+        assert!(store.load("c").unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn range_fit() {
+        // a bias going from +20 mm at 0 to -20 mm at 2 m, plus 0.05 mm per unit of quality
+        let true_bias = |d: f64, q: f64| 20.0 - 0.02 * d + 0.05 * (q - 150.0);
+        let mut calibration = RangeCalibration::new(Convention::UST05LN);
+        assert!(calibration.fit().is_none());
+        for &wall in &[0.5, 1.0, 1.5, 2.0] {
+            let scan = (0..200)
+                .map(|i| {
+                    let a = -0.5 + 0.005 * i as f64;
+                    let expected = wall * 1000.0 / a.cos();
+                    let quality = if i % 2 == 0 { 100 } else { 200 };
+                    Some(Sample {
+                        angle: a,
+                        distance: (expected + true_bias(expected, quality as f64)).round() as u16,
+                        quality,
+                    })
+                })
+                .collect::<Vec<_>>();
+            assert!(calibration.add_wall_scan(&scan, wall) > 150);
+        }
+
+        let correction = calibration.fit().unwrap();
+        assert!((correction.quality_gain - 0.05).abs() < 0.005);
+        assert!((correction.quality_ref - 150.0).abs() < 1.0);
+        for &(d, q) in &[(600.0, 100.0), (1200.0, 200.0), (1900.0, 150.0)] {
+            let expected = true_bias(d, q) - 0.05 * (correction.quality_ref - 150.0);
+            assert!(
+                (correction.bias(d, q) - expected).abs() < 1.0,
+                "{} {}",
+                d,
+                q
+            );
+        }
+    }
+}
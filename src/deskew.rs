@@ -0,0 +1,176 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use crate::lidar::Sample;
+use crate::transform::{Convention, Mount, Pose};
+
+/// Gives the robot pose at a given time (seconds), typically interpolated from odometry.
+pub trait PoseSource {
+    fn pose_at(&self, t: f64) -> Option<Pose>;
+}
+
+impl<F: Fn(f64) -> Option<Pose>> PoseSource for F {
+    fn pose_at(&self, t: f64) -> Option<Pose> {
+        self(t)
+    }
+}
+
+/// A `PoseSource` keeping the last timestamped poses and interpolating between them.
+pub struct PoseHistory {
+    poses: VecDeque<(f64, Pose)>,
+    capacity: usize,
+}
+
+impl PoseHistory {
+    pub fn new(capacity: usize) -> PoseHistory {
+        PoseHistory {
+            poses: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Adds a pose. Timestamps are expected to be increasing.
+    pub fn push(&mut self, t: f64, pose: Pose) {
+        if self.poses.len() == self.capacity {
+            self.poses.pop_front();
+        }
+        self.poses.push_back((t, pose));
+    }
+}
+
+impl PoseSource for PoseHistory {
+    fn pose_at(&self, t: f64) -> Option<Pose> {
+        let i = self.poses.iter().position(|&(pt, _)| pt >= t)?;
+        let (t1, p1) = self.poses[i];
+        if t1 == t {
+            return Some(p1);
+        }
+        if i == 0 {
+            return None;
+        }
+        let (t0, p0) = self.poses[i - 1];
+        Some(p0.interpolate(&p1, (t - t0) / (t1 - t0)))
+    }
+}
+
+/// Estimates the acquisition time of each sample of a turn ending at `t_end`,
+/// assuming a constant rotation lasting `period` seconds.
+///
+/// Raw angles increase during the acquisition for all drivers, so the time of
+/// a sample is given by the angle left to sweep until the last sample.
+pub fn sample_times(
+    scan: &[Option<Sample>],
+    convention: Convention,
+    t_end: f64,
+    period: f64,
+) -> Vec<f64> {
+    let last = scan.iter().rev().flatten().next().map(|s| s.angle);
+    scan.iter()
+        .map(|s| match (s, last) {
+            (Some(s), Some(last)) => {
+                let remaining = convention.to_radians(last - s.angle).rem_euclid(2.0 * PI);
+                t_end - period * remaining / (2.0 * PI)
+            }
+            _ => t_end,
+        })
+        .collect()
+}
+
+/// Motion distortion correction.
+///
+/// During a turn, the robot moves and each sample is measured from a different
+/// pose. `Deskew` re-projects every sample into the robot pose at the end of
+/// the turn.
+pub struct Deskew<P: PoseSource> {
+    mount: Mount,
+    source: P,
+}
+
+impl<P: PoseSource> Deskew<P> {
+    pub fn new(mount: Mount, source: P) -> Deskew<P> {
+        Deskew { mount, source }
+    }
+
+    pub fn source_mut(&mut self) -> &mut P {
+        &mut self.source
+    }
+
+    /// Corrects `scan` in place, `times` giving the acquisition time of each sample.
+    ///
+    /// Returns `false`, leaving the scan untouched, when the pose at `t_end` is
+    /// unknown. Samples whose pose is unknown are left as is.
+    pub fn apply(&self, scan: &mut [Option<Sample>], times: &[f64], t_end: f64) -> bool {
+        let end = match self.source.pose_at(t_end) {
+            Some(pose) => pose,
+            None => return false,
+        };
+
+        for (sample, &t) in scan.iter_mut().zip(times) {
+            if let (Some(s), Some(pose)) = (sample.as_mut(), self.source.pose_at(t)) {
+                let p = pose.transform(self.mount.sample_to_point(s));
+                *s = self
+                    .mount
+                    .point_to_sample(end.inverse_transform(p), s.quality);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pose_history() {
+        let mut history = PoseHistory::new(2);
+        history.push(0.0, Pose::new(0.0, 0.0, 0.0));
+        history.push(1.0, Pose::new(1.0, 0.0, 0.0));
+        history.push(2.0, Pose::new(1.0, 2.0, 0.0));
+        assert!(history.pose_at(0.5).is_none());
+        assert!(history.pose_at(2.5).is_none());
+        let pose = history.pose_at(1.5).unwrap();
+        assert!((pose.x - 1.0).abs() < 1e-9 && (pose.y - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn straight_motion() {
+        // a wall at x = 3 m, the robot going forward at 2 m/s
+        let mount = Mount::new(Convention::ROBOT);
+        let robot = |t: f64| Some(Pose::new(2.0 * t, 0.0, 0.0));
+        let (t_end, period) = (1.0, 1.0);
+        let angles = (0..9).map(|i| -0.4 + 0.1 * i as f64).collect::<Vec<_>>();
+        let placeholder = angles
+            .iter()
+            .map(|&angle| {
+                Some(Sample {
+                    angle,
+                    distance: 0,
+                    quality: 100,
+                })
+            })
+            .collect::<Vec<_>>();
+        let times = sample_times(&placeholder, Convention::ROBOT, t_end, period);
+        assert_eq!(times[8], t_end);
+        assert!((times[0] - (t_end - 0.8 / (2.0 * PI))).abs() < 1e-9);
+
+        let mut scan = angles
+            .iter()
+            .zip(&times)
+            .map(|(&angle, &t)| {
+                Some(Sample {
+                    angle,
+                    distance: ((3.0 - 2.0 * t) / angle.cos() * 1000.0).round() as u16,
+                    quality: 100,
+                })
+            })
+            .collect::<Vec<_>>();
+        let skewed = scan.iter().flatten().map(|s| mount.sample_to_point(s).x);
+        assert!(skewed.fold(0.0, f64::max) > 3.0 - 2.0 * t_end + 0.2);
+
+        assert!(Deskew::new(mount, robot).apply(&mut scan, &times, t_end));
+        for s in scan.iter().flatten() {
+            assert!((mount.sample_to_point(s).x - (3.0 - 2.0 * t_end)).abs() < 0.002);
+        }
+    }
+}
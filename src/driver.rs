@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::ld06::LD06;
+use crate::lidar::Lidar;
+use crate::transform::Convention;
+use crate::ust05ln::UST05LN;
+use crate::xv11::XV11;
+
+/// The supported lidar models, to pick one at runtime.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Driver {
+    LD06,
+    XV11,
+    UST05LN,
+}
+
+impl Driver {
+    pub const ALL: [Driver; 3] = [Driver::LD06, Driver::XV11, Driver::UST05LN];
+
+    /// Creates the driver for a lidar connected on `port`.
+    pub fn open(&self, port: &str) -> Box<dyn Lidar> {
+        self.open_with_baud_rate(port, self.baud_rate())
+    }
+
+    pub fn open_with_baud_rate(&self, port: &str, baud_rate: u32) -> Box<dyn Lidar> {
+        match self {
+            Driver::LD06 => Box::new(LD06::new(port).with_baud_rate(baud_rate)),
+            Driver::XV11 => Box::new(XV11::new(port).with_baud_rate(baud_rate)),
+            Driver::UST05LN => Box::new(UST05LN::new(port).with_baud_rate(baud_rate)),
+        }
+    }
+
+    /// Like `open_with_baud_rate`, with the range correction stored for the unit
+    /// (see `calibration::stored_correction`).
+    pub fn open_calibrated(
+        &self,
+        port: &str,
+        baud_rate: u32,
+    ) -> Result<Box<dyn Lidar>, Box<dyn Error>> {
+        Ok(match self {
+            Driver::LD06 => Box::new(
+                LD06::new(port)
+                    .with_baud_rate(baud_rate)
+                    .with_stored_correction()?,
+            ),
+            Driver::XV11 => Box::new(
+                XV11::new(port)
+                    .with_baud_rate(baud_rate)
+                    .with_stored_correction()?,
+            ),
+            Driver::UST05LN => Box::new(
+                UST05LN::new(port)
+                    .with_baud_rate(baud_rate)
+                    .with_stored_correction()?,
+            ),
+        })
+    }
+
+    /// Default baud rate of the model.
+    pub fn baud_rate(&self) -> u32 {
+        match self {
+            Driver::LD06 => LD06::BAUD_RATE,
+            Driver::XV11 => XV11::BAUD_RATE,
+            Driver::UST05LN => UST05LN::BAUD_RATE,
+        }
+    }
+
+    pub fn convention(&self) -> Convention {
+        match self {
+            Driver::LD06 => Convention::LD06,
+            Driver::XV11 => Convention::XV11,
+            Driver::UST05LN => Convention::UST05LN,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Driver::LD06 => "ld06",
+            Driver::XV11 => "xv11",
+            Driver::UST05LN => "ust05ln",
+        }
+    }
+}
+
+impl FromStr for Driver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Driver, String> {
+        Driver::ALL
+            .iter()
+            .find(|d| d.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("unknown driver '{}', expected ld06, xv11 or ust05ln", s))
+    }
+}
+
+impl fmt::Display for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -0,0 +1,680 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::sync::{Arc, Mutex};
+
+use crate::lidar::Sample;
+use crate::map::StaticMap;
+use crate::transform::{normalize_angle, Convention, Mount, Point, Pose};
+
+/// A processing stage applied to scans.
+///
+/// Filters work in place : a rejected sample is replaced by `None`, so sample
+/// indices stay meaningful along the pipeline.
+pub trait Filter: Send {
+    fn apply(&mut self, scan: &mut [Option<Sample>]);
+}
+
+/// An ordered list of filters, itself usable as a `Filter`.
+#[derive(Default)]
+pub struct Pipeline {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline { filters: vec![] }
+    }
+
+    /// Appends a filter at the end of the pipeline.
+    pub fn with<F: Filter + 'static>(mut self, filter: F) -> Pipeline {
+        self.push(filter);
+        self
+    }
+
+    pub fn push<F: Filter + 'static>(&mut self, filter: F) {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Inserts a filter at the start of the pipeline.
+    pub fn prepend<F: Filter + 'static>(&mut self, filter: F) {
+        self.filters.insert(0, Box::new(filter));
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Runs the pipeline on an owned scan.
+    pub fn run(&mut self, mut scan: Vec<Option<Sample>>) -> Vec<Option<Sample>> {
+        self.apply(&mut scan);
+        scan
+    }
+}
+
+impl Filter for Pipeline {
+    fn apply(&mut self, scan: &mut [Option<Sample>]) {
+        for filter in self.filters.iter_mut() {
+            filter.apply(scan);
+        }
+    }
+}
+
+/// Keeps samples whose distance (mm) lies in `[min, max[`.
+pub struct RangeFilter {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl RangeFilter {
+    pub fn new(min: u16, max: u16) -> RangeFilter {
+        RangeFilter { min, max }
+    }
+}
+
+impl Filter for RangeFilter {
+    fn apply(&mut self, scan: &mut [Option<Sample>]) {
+        for sample in scan.iter_mut() {
+            if let Some(s) = sample {
+                if s.distance < self.min || s.distance >= self.max {
+                    *sample = None;
+                }
+            }
+        }
+    }
+}
+
+/// Keeps samples whose quality (LD06 confidence, XV11 strength, UST05LN reflectance) is at least `min`.
+pub struct QualityFilter {
+    pub min: u16,
+}
+
+impl QualityFilter {
+    pub fn new(min: u16) -> QualityFilter {
+        QualityFilter { min }
+    }
+}
+
+impl Filter for QualityFilter {
+    fn apply(&mut self, scan: &mut [Option<Sample>]) {
+        for sample in scan.iter_mut() {
+            if let Some(s) = sample {
+                if s.quality < self.min {
+                    *sample = None;
+                }
+            }
+        }
+    }
+}
+
+/// Blanks angular sectors, typically the parts of the robot body seen by the sensor.
+///
+/// Sectors are given in the robot frame (radians, counter-clockwise), from
+/// `start` to `end` going counter-clockwise.
+pub struct AngularMask {
+    mount: Mount,
+    sectors: Vec<(f64, f64)>,
+}
+
+impl AngularMask {
+    pub fn new(mount: Mount) -> AngularMask {
+        AngularMask {
+            mount,
+            sectors: vec![],
+        }
+    }
+
+    pub fn with_sector(mut self, start: f64, end: f64) -> AngularMask {
+        self.sectors.push((start, end));
+        self
+    }
+
+    fn is_masked(&self, angle: f64) -> bool {
+        self.sectors.iter().any(|&(start, end)| {
+            let width = (end - start).rem_euclid(2.0 * PI);
+            let offset = (angle - start).rem_euclid(2.0 * PI);
+            offset <= width
+        })
+    }
+}
+
+impl Filter for AngularMask {
+    fn apply(&mut self, scan: &mut [Option<Sample>]) {
+        for sample in scan.iter_mut() {
+            if let Some(s) = sample {
+                if self.is_masked(self.mount.robot_angle(s.angle)) {
+                    *sample = None;
+                }
+            }
+        }
+    }
+}
+
+/// Removes veiling points ("mixed pixels") at the edges of objects.
+///
+/// For each pair of neighbors within `window` samples, the angle between the
+/// laser ray and the line joining both points is computed. If it is lower than
+/// `min_angle` or greater than `max_angle` (radians), the farthest point of the
+/// pair is removed, as in the ROS laser_filters `ScanShadowsFilter`.
+pub struct ShadowFilter {
+    convention: Convention,
+    pub min_angle: f64,
+    pub max_angle: f64,
+    pub window: usize,
+}
+
+impl ShadowFilter {
+    pub fn new(
+        convention: Convention,
+        min_angle: f64,
+        max_angle: f64,
+        window: usize,
+    ) -> ShadowFilter {
+        ShadowFilter {
+            convention,
+            min_angle,
+            max_angle,
+            window,
+        }
+    }
+
+    /// `true` if the two samples look like a shadow edge.
+    fn is_shadow(&self, s1: &Sample, s2: &Sample) -> bool {
+        let included = normalize_angle(
+            self.convention.to_sensor_angle(s2.angle) - self.convention.to_sensor_angle(s1.angle),
+        );
+        let (r1, r2) = (s1.distance as f64, s2.distance as f64);
+        let perpendicular_y = r2 * included.sin();
+        let perpendicular_x = r1 - r2 * included.cos();
+        let angle = perpendicular_y.abs().atan2(perpendicular_x);
+        angle < self.min_angle || angle > self.max_angle
+    }
+}
+
+impl Filter for ShadowFilter {
+    fn apply(&mut self, scan: &mut [Option<Sample>]) {
+        let mut shadows = vec![false; scan.len()];
+        for i in 0..scan.len() {
+            let s1 = match &scan[i] {
+                Some(s) => s,
+                None => continue,
+            };
+            for j in (i + 1)..scan.len().min(i + self.window + 1) {
+                if let Some(s2) = &scan[j] {
+                    if self.is_shadow(s1, s2) {
+                        if s1.distance > s2.distance {
+                            shadows[i] = true;
+                        } else {
+                            shadows[j] = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        for (sample, shadow) in scan.iter_mut().zip(shadows) {
+            if shadow {
+                *sample = None;
+            }
+        }
+    }
+}
+
+/// Last scans of a sensor, binned by angle.
+struct BinnedHistory {
+    convention: Convention,
+    bins: usize,
+    depth: usize,
+    scans: VecDeque<Vec<Option<Sample>>>,
+}
+
+impl BinnedHistory {
+    /// At least one bin and one scan are kept.
+    fn new(convention: Convention, bins: usize, depth: usize) -> BinnedHistory {
+        let depth = depth.max(1);
+        BinnedHistory {
+            convention,
+            bins: bins.max(1),
+            depth,
+            scans: VecDeque::with_capacity(depth),
+        }
+    }
+
+    fn bin(&self, sample: &Sample) -> usize {
+        let a = self
+            .convention
+            .to_sensor_angle(sample.angle)
+            .rem_euclid(2.0 * PI);
+        ((a / (2.0 * PI) * self.bins as f64) as usize) % self.bins
+    }
+
+    fn push(&mut self, scan: &[Option<Sample>]) {
+        let mut binned = vec![None; self.bins];
+        for s in scan.iter().flatten() {
+            binned[self.bin(s)] = Some(*s);
+        }
+        if self.scans.len() == self.depth {
+            self.scans.pop_front();
+        }
+        self.scans.push_back(binned);
+    }
+
+    /// Samples stored in the bins `bin - spread..=bin + spread` of the history,
+    /// each bin once when the spread covers the whole turn.
+    fn around(&self, bin: usize, spread: usize) -> impl Iterator<Item = &Sample> + '_ {
+        let bins = self.bins;
+        let spread = spread.min(bins / 2);
+        let width = (2 * spread + 1).min(bins);
+        self.scans.iter().flat_map(move |scan| {
+            (0..width).filter_map(move |k| scan[(bin + bins + k - spread) % bins].as_ref())
+        })
+    }
+
+    fn to_point(&self, s: &Sample) -> Point {
+        let a = self.convention.to_sensor_angle(s.angle);
+        let r = s.distance as f64 / 1000.0;
+        Point::new(r * a.cos(), r * a.sin())
+    }
+}
+
+/// Replaces each distance by the median of the distances measured in the same
+/// angular bin over the last `depth` scans.
+///
+/// A sample is removed when fewer than `min_count` of these scans saw
+/// something in its bin, which stops obstacles from flickering.
+pub struct TemporalMedianFilter {
+    history: BinnedHistory,
+    pub min_count: usize,
+}
+
+impl TemporalMedianFilter {
+    pub fn new(convention: Convention, bins: usize, depth: usize) -> TemporalMedianFilter {
+        TemporalMedianFilter {
+            min_count: depth.max(1).div_ceil(2),
+            history: BinnedHistory::new(convention, bins, depth),
+        }
+    }
+
+    pub fn with_min_count(mut self, min_count: usize) -> TemporalMedianFilter {
+        self.min_count = min_count;
+        self
+    }
+}
+
+impl Filter for TemporalMedianFilter {
+    fn apply(&mut self, scan: &mut [Option<Sample>]) {
+        self.history.push(scan);
+        for sample in scan.iter_mut() {
+            if let Some(s) = sample {
+                let mut distances = self
+                    .history
+                    .around(self.history.bin(s), 0)
+                    .map(|h| h.distance)
+                    .collect::<Vec<_>>();
+                if distances.len() < self.min_count {
+                    *sample = None;
+                } else {
+                    distances.sort_unstable();
+                    s.distance = distances[distances.len() / 2];
+                }
+            }
+        }
+    }
+}
+
+/// Removes isolated points.
+///
+/// A point is kept if at least `min_neighbors` other points lie within
+/// `max_distance` meters of it, looking `spread` samples around it in the
+/// current scan and `spread` bins around it in the last `depth` ones. Points
+/// whose quality is at least `trusted_quality` are always kept : strong echoes
+/// from thin objects such as beacon poles often have no neighbor at all.
+pub struct SpeckleFilter {
+    history: BinnedHistory,
+    pub max_distance: f64,
+    pub min_neighbors: usize,
+    pub spread: usize,
+    pub trusted_quality: Option<u16>,
+}
+
+impl SpeckleFilter {
+    pub fn new(
+        convention: Convention,
+        bins: usize,
+        depth: usize,
+        max_distance: f64,
+    ) -> SpeckleFilter {
+        SpeckleFilter {
+            history: BinnedHistory::new(convention, bins, depth),
+            max_distance,
+            min_neighbors: 1,
+            spread: 2,
+            trusted_quality: None,
+        }
+    }
+
+    pub fn with_min_neighbors(mut self, min_neighbors: usize) -> SpeckleFilter {
+        self.min_neighbors = min_neighbors;
+        self
+    }
+
+    pub fn with_spread(mut self, spread: usize) -> SpeckleFilter {
+        self.spread = spread;
+        self
+    }
+
+    pub fn with_trusted_quality(mut self, quality: u16) -> SpeckleFilter {
+        self.trusted_quality = Some(quality);
+        self
+    }
+}
+
+impl Filter for SpeckleFilter {
+    fn apply(&mut self, scan: &mut [Option<Sample>]) {
+        let history = &self.history;
+        let isolated = (0..scan.len())
+            .map(|i| match &scan[i] {
+                Some(s) => {
+                    if self.trusted_quality.is_some_and(|q| s.quality >= q) {
+                        return false;
+                    }
+                    let p = history.to_point(s);
+                    let window = i.saturating_sub(self.spread)..scan.len().min(i + self.spread + 1);
+                    let current = window.filter(|&k| k != i).filter_map(|k| scan[k].as_ref());
+                    let neighbors = current
+                        .chain(history.around(history.bin(s), self.spread))
+                        .filter(|n| history.to_point(n).distance(&p) <= self.max_distance)
+                        .count();
+                    neighbors < self.min_neighbors
+                }
+                None => false,
+            })
+            .collect::<Vec<_>>();
+
+        self.history.push(scan);
+        for (sample, isolated) in scan.iter_mut().zip(isolated) {
+            if isolated {
+                *sample = None;
+            }
+        }
+    }
+}
+
+/// Removes the samples explained by a known static environment (walls, fixed
+/// elements), so that only dynamic obstacles remain.
+///
+/// The robot pose changes between scans : it is shared through `pose_handle`,
+/// so that it can be updated while the filter sits in a driver pipeline.
+pub struct MapMaskFilter {
+    mount: Mount,
+    map: StaticMap,
+    pub tolerance: f64,
+    pose: Arc<Mutex<Pose>>,
+}
+
+impl MapMaskFilter {
+    pub fn new(mount: Mount, map: StaticMap, tolerance: f64) -> MapMaskFilter {
+        MapMaskFilter {
+            mount,
+            map,
+            tolerance,
+            pose: Arc::new(Mutex::new(Pose::default())),
+        }
+    }
+
+    pub fn set_pose(&self, pose: Pose) {
+        *self.pose.lock().unwrap() = pose;
+    }
+
+    /// Handle to update the robot pose used by the filter.
+    pub fn pose_handle(&self) -> Arc<Mutex<Pose>> {
+        self.pose.clone()
+    }
+}
+
+impl Filter for MapMaskFilter {
+    fn apply(&mut self, scan: &mut [Option<Sample>]) {
+        let pose = *self.pose.lock().unwrap();
+        for sample in scan.iter_mut() {
+            if let Some(s) = sample {
+                let p = pose.transform(self.mount.sample_to_point(s));
+                if self.map.explains(&p, self.tolerance) {
+                    *sample = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(distances: &[u16]) -> Vec<Option<Sample>> {
+        let step = 360.0 / distances.len() as f64;
+        distances
+            .iter()
+            .enumerate()
+            .map(|(i, &distance)| {
+                Some(Sample {
+                    angle: i as f64 * step,
+                    distance,
+                    quality: 100,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn binned_history_bounds() {
+        let mut history = BinnedHistory::new(Convention::LD06, 0, 0);
+        let scan = scan(&[1000; 8]);
+        history.push(&scan);
+        history.push(&scan);
+        assert_eq!(history.scans.len(), 1);
+        assert_eq!(history.bin(scan[3].as_ref().unwrap()), 0);
+        // the whole turn, each bin once
+        assert_eq!(history.around(0, 100).count(), 1);
+
+        let mut history = BinnedHistory::new(Convention::LD06, 4, 2);
+        history.push(&scan);
+        assert_eq!(history.around(0, 1).count(), 3);
+        assert_eq!(history.around(0, 100).count(), 4);
+    }
+
+    fn distances(scan: &[Option<Sample>]) -> Vec<Option<u16>> {
+        scan.iter().map(|s| s.map(|s| s.distance)).collect()
+    }
+
+    #[test]
+    fn range_and_quality() {
+        let mut s = scan(&[99, 100, 500, 1999, 2000]);
+        RangeFilter::new(100, 2000).apply(&mut s);
+        assert_eq!(
+            distances(&s),
+            [None, Some(100), Some(500), Some(1999), None]
+        );
+
+        let mut s = scan(&[1000; 4]);
+        for (i, sample) in s.iter_mut().enumerate() {
+            sample.as_mut().unwrap().quality = 10 * i as u16;
+        }
+        QualityFilter::new(20).apply(&mut s);
+        assert_eq!(distances(&s), [None, None, Some(1000), Some(1000)]);
+    }
+
+    #[test]
+    fn angular_mask() {
+        // LD06 samples every 45 degrees clockwise : 270 degrees is the robot left
+        let mut s = scan(&[1000; 8]);
+        AngularMask::new(Mount::new(Convention::LD06))
+            .with_sector(PI / 2.0 - 0.1, PI / 2.0 + 0.1)
+            .apply(&mut s);
+        let masked = s.iter().map(Option::is_none).collect::<Vec<_>>();
+        assert_eq!(
+            masked,
+            [false, false, false, false, false, false, true, false]
+        );
+
+        // a sector going through PI, and the mount yaw
+        let mut s = scan(&[1000; 8]);
+        AngularMask::new(Mount::new(Convention::LD06).with_yaw(-PI / 2.0))
+            .with_sector(3.0, -3.0)
+            .apply(&mut s);
+        let masked = s.iter().map(Option::is_none).collect::<Vec<_>>();
+        assert_eq!(
+            masked,
+            [false, false, true, false, false, false, false, false]
+        );
+    }
+
+    struct Tag(Arc<Mutex<Vec<&'static str>>>, &'static str);
+
+    impl Filter for Tag {
+        fn apply(&mut self, _scan: &mut [Option<Sample>]) {
+            self.0.lock().unwrap().push(self.1);
+        }
+    }
+
+    #[test]
+    fn pipeline_order() {
+        let order = Arc::new(Mutex::new(vec![]));
+        let mut pipeline = Pipeline::new()
+            .with(Tag(order.clone(), "b"))
+            .with(Tag(order.clone(), "c"));
+        pipeline.prepend(Tag(order.clone(), "a"));
+        pipeline.push(Tag(order.clone(), "d"));
+        assert_eq!(pipeline.len(), 4);
+        pipeline.run(scan(&[1000]));
+        assert_eq!(*order.lock().unwrap(), ["a", "b", "c", "d"]);
+
+        // each filter sees the output of the previous one
+        let mut pipeline = Pipeline::new()
+            .with(RangeFilter::new(100, 2000))
+            .with(RangeFilter::new(0, 1000));
+        let s = pipeline.run(scan(&[50, 500, 1500, 2500]));
+        assert_eq!(distances(&s), [None, Some(500), None, None]);
+    }
+
+    #[test]
+    fn shadows() {
+        let ray = |i: usize, distance: f64| {
+            Some(Sample {
+                angle: (i as f64 - 20.0) * 0.01,
+                distance: distance.round() as u16,
+                quality: 100,
+            })
+        };
+        let mut filter = ShadowFilter::new(Convention::UST05LN, 0.17, 2.97, 2);
+
+        // a flat wall 1 m in front of the sensor
+        let mut wall = (0..40)
+            .map(|i| ray(i, 1000.0 / ((i as f64 - 20.0) * 0.01).cos()))
+            .collect::<Vec<_>>();
+        filter.apply(&mut wall);
+        assert!(wall.iter().all(Option::is_some));
+
+        // an object at 1 m in front of a wall at 3 m, veiling points in between
+        let mut edge = (0..40)
+            .map(|i| match i {
+                0..=19 => ray(i, 1000.0),
+                20..=22 => ray(i, 1500.0 + 500.0 * (i - 20) as f64),
+                _ => ray(i, 3000.0),
+            })
+            .collect::<Vec<_>>();
+        filter.apply(&mut edge);
+        assert!(edge[..20].iter().all(Option::is_some));
+        assert!(edge[20..23].iter().all(Option::is_none));
+        assert!(edge[25..].iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn temporal_median() {
+        let mut filter = TemporalMedianFilter::new(Convention::LD06, 8, 3);
+        let mut steady = scan(&[1000; 8]);
+        steady[5] = None;
+        // nothing is trusted before min_count scans
+        let mut s = steady.clone();
+        filter.apply(&mut s);
+        assert!(s.iter().all(Option::is_none));
+        let mut s = steady.clone();
+        filter.apply(&mut s);
+        assert_eq!(distances(&s), distances(&steady));
+
+        // a blip on a known bin is smoothed, a blip on an empty one removed
+        let mut s = scan(&[1000; 8]);
+        s[2].as_mut().unwrap().distance = 300;
+        filter.apply(&mut s);
+        assert_eq!(distances(&s), distances(&steady));
+
+        // seen again in the next scan, the new obstacle is kept
+        let mut s = scan(&[1000; 8]);
+        filter.apply(&mut s);
+        assert_eq!(s[5].map(|s| s.distance), Some(1000));
+    }
+
+    #[test]
+    fn speckles() {
+        let full = scan(&[1000; 360]);
+        let mut s = vec![None; 360];
+        s[10..15].copy_from_slice(&full[10..15]);
+        s[100] = full[100];
+        s[200] = full[200];
+        s[200].as_mut().unwrap().quality = 250;
+
+        let mut filter =
+            SpeckleFilter::new(Convention::LD06, 360, 1, 0.1).with_trusted_quality(200);
+        filter.apply(&mut s);
+        assert!(s[10..15].iter().all(Option::is_some));
+        assert!(s[100].is_none());
+        assert!(s[200].is_some());
+        assert_eq!(s.iter().flatten().count(), 6);
+
+        // the previous scan counts too
+        let mut filter = SpeckleFilter::new(Convention::LD06, 360, 1, 0.1);
+        let mut first = scan(&[1000; 360]);
+        filter.apply(&mut first);
+        let mut s = vec![None; 360];
+        s[100] = first[100];
+        filter.apply(&mut s);
+        assert!(s[100].is_some());
+    }
+
+    #[test]
+    fn map_mask() {
+        // a wall 1 m ahead of the robot start
+        let map = StaticMap::new().with_segment(Point::new(1.0, -1.0), Point::new(1.0, 1.0));
+        let mut filter = MapMaskFilter::new(Mount::new(Convention::ROBOT), map, 0.05);
+        let samples = || {
+            vec![
+                Some(Sample {
+                    angle: 0.0,
+                    distance: 1000,
+                    quality: 100,
+                }),
+                Some(Sample {
+                    angle: 0.3,
+                    distance: 500,
+                    quality: 100,
+                }),
+            ]
+        };
+        let mut s = samples();
+        filter.apply(&mut s);
+        assert_eq!(distances(&s), [None, Some(500)]);
+
+        // once the robot moved, the first sample lands behind the wall, the second on it
+        *filter.pose_handle().lock().unwrap() = Pose::new(0.5, 0.0, 0.0);
+        let mut s = samples();
+        filter.apply(&mut s);
+        assert_eq!(distances(&s), [Some(1000), None]);
+
+        filter.set_pose(Pose::default());
+        let mut s = samples();
+        filter.apply(&mut s);
+        assert_eq!(distances(&s), [None, Some(500)]);
+    }
+}
@@ -0,0 +1,352 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+use tungstenite::error::ProtocolError;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::HeaderValue;
+use tungstenite::{Message, WebSocket};
+
+use crate::lidar::Sample;
+use crate::mcap::{escape_json, laser_scan_json, LASER_SCAN_SCHEMA};
+use crate::transform::Mount;
+
+/// WebSocket subprotocol of the Foxglove WebSocket protocol.
+pub const SUBPROTOCOL: &str = "foxglove.websocket.v1";
+
+const CHANNEL_ID: u32 = 1;
+
+// opcode of the binary message data frames
+const MESSAGE_DATA: u8 = 0x01;
+
+struct Session {
+    socket: WebSocket<TcpStream>,
+    /// Subscription ids of the client to the scan channel.
+    subscriptions: Vec<u32>,
+    closed: bool,
+}
+
+/// Publishes scans on a `foxglove.LaserScan` channel with the Foxglove
+/// WebSocket protocol, to be opened in Foxglove Studio
+/// ("Open connection" > "Foxglove WebSocket", `ws://host:8765`).
+pub struct FoxgloveServer {
+    addr: SocketAddr,
+    sessions: Arc<Mutex<Vec<Arc<Mutex<Session>>>>>,
+    frame_id: String,
+}
+
+impl FoxgloveServer {
+    /// Starts listening, the scans are advertised on `topic` (e.g. `/scan`) in
+    /// the sensor frame named `frame_id`.
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        topic: &str,
+        frame_id: &str,
+    ) -> io::Result<FoxgloveServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let sessions = Arc::new(Mutex::new(vec![]));
+        let accepted = sessions.clone();
+        let topic = topic.to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sessions = accepted.clone();
+                let topic = topic.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &topic, sessions) {
+                        eprintln!("foxglove connection failed : {}", e);
+                    }
+                });
+            }
+        });
+        Ok(FoxgloveServer {
+            addr,
+            sessions,
+            frame_id: frame_id.to_string(),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of connected clients.
+    pub fn clients(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    /// Sends a scan to the subscribed clients, with `time` as its timestamp.
+    pub fn publish(&self, time: SystemTime, mount: &Mount, scan: &[Option<Sample>]) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut payload = None;
+        let nanos = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        sessions.retain(|session| {
+            let mut guard = session.lock().unwrap();
+            let session = &mut *guard;
+            if session.closed {
+                return false;
+            }
+            let payload =
+                payload.get_or_insert_with(|| laser_scan_json(time, mount, &self.frame_id, scan));
+            for id in &session.subscriptions {
+                let mut data = Vec::with_capacity(13 + payload.len());
+                data.push(MESSAGE_DATA);
+                data.extend_from_slice(&id.to_le_bytes());
+                data.extend_from_slice(&nanos.to_le_bytes());
+                data.extend_from_slice(payload.as_bytes());
+                if session.socket.send(Message::Binary(data)).is_err() {
+                    session.closed = true;
+                    return false;
+                }
+            }
+            true
+        });
+    }
+}
+
+/// Handshake callback accepting only clients of the Foxglove subprotocol.
+// the error type is imposed by tungstenite
+#[allow(clippy::result_large_err)]
+fn offer_subprotocol(request: &Request, mut response: Response) -> Result<Response, ErrorResponse> {
+    let offered = request
+        .headers()
+        .get_all("Sec-WebSocket-Protocol")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|p| p.trim() == SUBPROTOCOL);
+    if !offered {
+        let mut error =
+            ErrorResponse::new(Some(format!("expected the {} subprotocol", SUBPROTOCOL)));
+        *error.status_mut() = tungstenite::http::StatusCode::BAD_REQUEST;
+        return Err(error);
+    }
+    response.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_static(SUBPROTOCOL),
+    );
+    Ok(response)
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    topic: &str,
+    sessions: Arc<Mutex<Vec<Arc<Mutex<Session>>>>>,
+) -> io::Result<()> {
+    let mut socket = tungstenite::accept_hdr(stream, offer_subprotocol)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let server_info = format!(
+        "{{\"op\":\"serverInfo\",\"name\":\"lidar_rd\",\"capabilities\":[],\"supportedEncodings\":[],\"metadata\":{{}},\"sessionId\":\"{}\"}}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    );
+    let advertise = format!(
+        "{{\"op\":\"advertise\",\"channels\":[{{\"id\":{},\"topic\":\"{}\",\"encoding\":\"json\",\"schemaName\":\"foxglove.LaserScan\",\"schema\":\"{}\",\"schemaEncoding\":\"jsonschema\"}}]}}",
+        CHANNEL_ID,
+        escape_json(topic),
+        escape_json(LASER_SCAN_SCHEMA)
+    );
+    let to_io = |e: tungstenite::Error| io::Error::other(e.to_string());
+    socket.send(Message::Text(server_info)).map_err(to_io)?;
+    socket.send(Message::Text(advertise)).map_err(to_io)?;
+    // short reads let the publisher take the socket in between
+    socket
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_millis(20)))?;
+    // a stalled client must not block the others
+    socket
+        .get_ref()
+        .set_write_timeout(Some(Duration::from_millis(500)))?;
+
+    let session = Arc::new(Mutex::new(Session {
+        socket,
+        subscriptions: vec![],
+        closed: false,
+    }));
+    sessions.lock().unwrap().push(session.clone());
+
+    let requests = Requests::new();
+    loop {
+        {
+            let mut guard = session.lock().unwrap();
+            let session = &mut *guard;
+            if session.closed {
+                return Ok(());
+            }
+            match session.socket.read() {
+                Ok(Message::Text(text)) => requests.apply(&text, &mut session.subscriptions),
+                Ok(Message::Close(_)) => {
+                    session.closed = true;
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => {
+                    session.closed = true;
+                    return match e {
+                        tungstenite::Error::ConnectionClosed
+                        | tungstenite::Error::AlreadyClosed
+                        | tungstenite::Error::Protocol(
+                            ProtocolError::ResetWithoutClosingHandshake,
+                        ) => Ok(()),
+                        e => Err(to_io(e)),
+                    };
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Parser of the `subscribe` and `unsubscribe` client operations, other ones are ignored.
+struct Requests {
+    op: Regex,
+    subscription: Regex,
+    id: Regex,
+    channel_id: Regex,
+    subscription_ids: Regex,
+}
+
+impl Requests {
+    fn new() -> Requests {
+        Requests {
+            op: Regex::new(r#""op"\s*:\s*"(\w+)""#).unwrap(),
+            subscription: Regex::new(r"\{[^{}]*\}").unwrap(),
+            id: Regex::new(r#""id"\s*:\s*(\d+)"#).unwrap(),
+            channel_id: Regex::new(r#""channelId"\s*:\s*(\d+)"#).unwrap(),
+            subscription_ids: Regex::new(r#""subscriptionIds"\s*:\s*\[([^\]]*)\]"#).unwrap(),
+        }
+    }
+
+    fn apply(&self, text: &str, subscriptions: &mut Vec<u32>) {
+        let number =
+            |re: &Regex, text: &str| re.captures(text).and_then(|c| c[1].parse::<u32>().ok());
+        match self.op.captures(text).map(|c| c[1].to_string()).as_deref() {
+            Some("subscribe") => {
+                for s in self.subscription.find_iter(text) {
+                    let (id, channel) = (
+                        number(&self.id, s.as_str()),
+                        number(&self.channel_id, s.as_str()),
+                    );
+                    if let (Some(id), Some(CHANNEL_ID)) = (id, channel) {
+                        if !subscriptions.contains(&id) {
+                            subscriptions.push(id);
+                        }
+                    }
+                }
+            }
+            Some("unsubscribe") => {
+                if let Some(ids) = self.subscription_ids.captures(text) {
+                    let ids = ids[1]
+                        .split(',')
+                        .filter_map(|id| id.trim().parse::<u32>().ok())
+                        .collect::<Vec<_>>();
+                    subscriptions.retain(|id| !ids.contains(id));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Convention;
+    use std::time::Instant;
+    use tungstenite::client::IntoClientRequest;
+
+    fn connect(server: &FoxgloveServer) -> WebSocket<TcpStream> {
+        let mut request = format!("ws://{}", server.local_addr())
+            .into_client_request()
+            .unwrap();
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(SUBPROTOCOL),
+        );
+        let stream = TcpStream::connect(server.local_addr()).unwrap();
+        let (socket, response) = tungstenite::client(request, stream).unwrap();
+        assert_eq!(response.headers()["Sec-WebSocket-Protocol"], SUBPROTOCOL);
+        socket
+    }
+
+    fn read_text(socket: &mut WebSocket<TcpStream>) -> String {
+        match socket.read().unwrap() {
+            Message::Text(text) => text,
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn rejects_other_subprotocols() {
+        let server = FoxgloveServer::bind("127.0.0.1:0", "/scan", "lidar").unwrap();
+        let stream = TcpStream::connect(server.local_addr()).unwrap();
+        let url = format!("ws://{}", server.local_addr());
+        assert!(tungstenite::client(url.as_str(), stream).is_err());
+    }
+
+    #[test]
+    fn publishes_to_subscriptions() {
+        let server = FoxgloveServer::bind("127.0.0.1:0", "/front", "laser").unwrap();
+        let mut socket = connect(&server);
+        let server_info = read_text(&mut socket);
+        assert!(server_info.contains("\"op\":\"serverInfo\""));
+        let advertise = read_text(&mut socket);
+        assert!(advertise.contains("\"op\":\"advertise\""));
+        assert!(advertise.contains(&format!("\"id\":{},", CHANNEL_ID)));
+        assert!(advertise.contains("\"topic\":\"/front\""));
+        assert!(advertise.contains("\"schemaName\":\"foxglove.LaserScan\""));
+
+        let subscribe = format!(
+            r#"{{"op":"subscribe","subscriptions":[{{"id":7,"channelId":{}}}]}}"#,
+            CHANNEL_ID
+        );
+        socket.send(Message::Text(subscribe)).unwrap();
+        socket
+            .get_ref()
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+
+        let mount = Mount::new(Convention::LD06);
+        let scan = vec![
+            Some(Sample {
+                angle: 0.0,
+                distance: 1500,
+                quality: 200,
+            }),
+            None,
+        ];
+        let time = UNIX_EPOCH + Duration::from_millis(1500);
+        // the subscription is applied by the connection thread
+        let start = Instant::now();
+        let data = loop {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            server.publish(time, &mount, &scan);
+            match socket.read() {
+                Ok(Message::Binary(data)) => break data,
+                Ok(message) => panic!("unexpected message {:?}", message),
+                Err(tungstenite::Error::Io(e))
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => panic!("{}", e),
+            }
+        };
+        assert_eq!(data[0], MESSAGE_DATA);
+        assert_eq!(data[1..5], 7u32.to_le_bytes());
+        assert_eq!(data[5..13], 1_500_000_000u64.to_le_bytes());
+        let payload = String::from_utf8(data[13..].to_vec()).unwrap();
+        assert_eq!(payload, laser_scan_json(time, &mount, "laser", &scan));
+        assert!(payload.contains("\"frame_id\":\"laser\""));
+    }
+}
@@ -0,0 +1,236 @@
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::lidar::{impl_drop, impl_iterator, DriverStats, Lidar, Sample};
+use crate::transform::{Convention, Mount};
+
+/// A merged scan, with the index of the source lidar of each sample.
+pub struct FusedScan {
+    /// Samples in the robot frame (`Convention::ROBOT`), sorted by angle.
+    pub samples: Vec<Option<Sample>>,
+    pub sources: Vec<usize>,
+}
+
+struct Source {
+    lidar: Box<dyn Lidar>,
+    mount: Mount,
+    latency: Duration,
+    latest: Mutex<Option<(Instant, Vec<Option<Sample>>)>>,
+}
+
+/// Combines several lidars into a single 360° scan in the robot frame.
+///
+/// A merged scan is produced once every source has delivered a scan, the
+/// oldest one being at most `max_age` older than the newest one.
+///
+/// The drivers don't timestamp their scans : a scan is dated when it is
+/// polled by `get_fused_scan`, minus the latency of its source (see
+/// `set_latency`). Merged scans can thus be apart by up to `max_age` plus the
+/// polling period plus the error on the latencies, so poll faster than the
+/// sensors turn and measure the latencies when the sensors differ.
+pub struct FusedLidar {
+    sources: Vec<Source>,
+    max_age: Duration,
+}
+
+impl FusedLidar {
+    pub fn new(max_age: Duration) -> FusedLidar {
+        FusedLidar {
+            sources: vec![],
+            max_age,
+        }
+    }
+
+    /// Adds a lidar mounted on the robot. Its samples are reported with the
+    /// index of the call that added it, starting at 0.
+    pub fn with_source(mut self, lidar: Box<dyn Lidar>, mount: Mount) -> FusedLidar {
+        self.add_source(lidar, mount);
+        self
+    }
+
+    pub fn add_source(&mut self, lidar: Box<dyn Lidar>, mount: Mount) -> usize {
+        self.sources.push(Source {
+            lidar,
+            mount,
+            latency: Duration::ZERO,
+            latest: Mutex::new(None),
+        });
+        self.sources.len() - 1
+    }
+
+    /// Time between the acquisition of a scan by the `source` lidar and its
+    /// delivery by the driver, zero by default.
+    pub fn set_latency(&mut self, source: usize, latency: Duration) {
+        self.sources[source].latency = latency;
+    }
+
+    /// Returns the merged scan, if all sources have a recent enough scan.
+    pub fn get_fused_scan(&self) -> Option<FusedScan> {
+        if self.sources.is_empty() {
+            return None;
+        }
+
+        let now = Instant::now();
+        for source in &self.sources {
+            if let Some(scan) = source.lidar.get_scan() {
+                let acquired = now.checked_sub(source.latency).unwrap_or(now);
+                *source.latest.lock().unwrap() = Some((acquired, scan));
+            }
+        }
+
+        let mut latest = self
+            .sources
+            .iter()
+            .map(|s| s.latest.lock().unwrap())
+            .collect::<Vec<_>>();
+        let times = latest
+            .iter()
+            .map(|l| l.as_ref().map(|(t, _)| *t))
+            .collect::<Option<Vec<_>>>()?;
+        let newest = *times.iter().max()?;
+        let mut complete = true;
+        for (l, t) in latest.iter_mut().zip(&times) {
+            if newest.duration_since(*t) > self.max_age {
+                // too old to be merged with the others, wait for a new one
+                **l = None;
+                complete = false;
+            }
+        }
+        if !complete {
+            return None;
+        }
+
+        let mut merged = vec![];
+        for (index, (source, l)) in self.sources.iter().zip(latest.iter_mut()).enumerate() {
+            let (_, scan) = l.take()?;
+            for s in scan.iter().flatten() {
+                let p = source.mount.sample_to_point(s);
+                let sample = Sample {
+                    angle: p.angle(),
+                    distance: (p.norm() * 1000.0).round().min(u16::MAX as f64) as u16,
+                    quality: s.quality,
+                };
+                merged.push((sample, index));
+            }
+        }
+        merged.sort_by(|a, b| a.0.angle.partial_cmp(&b.0.angle).unwrap());
+
+        let (samples, sources) = merged.into_iter().map(|(s, i)| (Some(s), i)).unzip();
+        Some(FusedScan { samples, sources })
+    }
+}
+
+impl Lidar for FusedLidar {
+    fn get_scan(&self) -> Option<Vec<Option<Sample>>> {
+        self.get_fused_scan().map(|scan| scan.samples)
+    }
+
+    fn start(&mut self) -> Result<(), Box<dyn Error>> {
+        for source in self.sources.iter_mut() {
+            source.lidar.start()?;
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        for source in self.sources.iter_mut() {
+            source.lidar.stop();
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.sources.iter().any(|s| s.lidar.is_running())
+    }
+
+    fn convention(&self) -> Convention {
+        Convention::ROBOT
+    }
+
+    /// Sum of the counters of the sources, without rpm.
+    fn stats(&self) -> DriverStats {
+        self.sources.iter().fold(DriverStats::default(), |acc, s| {
+            let stats = s.lidar.stats();
+            DriverStats {
+                rpm: None,
+                packets: acc.packets + stats.packets,
+                checksum_errors: acc.checksum_errors + stats.checksum_errors,
+                dropped_scans: acc.dropped_scans + stats.dropped_scans,
+            }
+        })
+    }
+}
+
+impl_iterator!(FusedLidar);
+impl_drop!(FusedLidar);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// Delivers one scan of a single sample straight ahead, at `distance` mm.
+    struct OneScan(Mutex<Option<u16>>);
+
+    impl Lidar for OneScan {
+        fn get_scan(&self) -> Option<Vec<Option<Sample>>> {
+            let distance = self.0.lock().unwrap().take()?;
+            Some(vec![
+                Some(Sample {
+                    angle: 0.0,
+                    distance,
+                    quality: 10,
+                }),
+                None,
+            ])
+        }
+        fn start(&mut self) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+        fn stop(&mut self) {}
+        fn is_running(&self) -> bool {
+            true
+        }
+        fn convention(&self) -> Convention {
+            Convention::UST05LN
+        }
+    }
+
+    fn source(distance: u16) -> Box<dyn Lidar> {
+        Box::new(OneScan(Mutex::new(Some(distance))))
+    }
+
+    #[test]
+    fn merges_in_robot_frame() {
+        let fused = FusedLidar::new(Duration::from_millis(100))
+            .with_source(
+                source(1000),
+                Mount::new(Convention::UST05LN).with_offset(0.1, 0.0),
+            )
+            .with_source(
+                source(500),
+                Mount::new(Convention::UST05LN)
+                    .with_offset(-0.1, 0.0)
+                    .with_yaw(PI),
+            );
+        let scan = fused.get_fused_scan().unwrap();
+        assert_eq!(scan.sources, vec![0, 1]);
+        let front = scan.samples[0].unwrap();
+        assert_eq!((front.angle, front.distance), (0.0, 1100));
+        let back = scan.samples[1].unwrap();
+        assert!((back.angle.abs() - PI).abs() < 1e-9);
+        assert_eq!(back.distance, 600);
+        assert!(fused.get_fused_scan().is_none());
+    }
+
+    #[test]
+    fn latency_dates_scans_back() {
+        let mut fused = FusedLidar::new(Duration::from_millis(100))
+            .with_source(source(1000), Mount::new(Convention::UST05LN))
+            .with_source(source(1000), Mount::new(Convention::UST05LN));
+        fused.set_latency(1, Duration::from_millis(300));
+        // acquired 300 ms before the other one : dropped
+        assert!(fused.get_fused_scan().is_none());
+        assert!(fused.sources[1].latest.lock().unwrap().is_none());
+    }
+}
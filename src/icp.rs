@@ -0,0 +1,463 @@
+use crate::lidar::Sample;
+use crate::linalg::{invert, solve, Matrix};
+use crate::transform::{fit_rigid_transform, normalize_angle, Mount, Point, Pose, Scan};
+
+/// 2D k-d tree over a set of points, for nearest neighbor queries.
+pub struct KdTree {
+    points: Vec<Point>,
+    // node i holds points[nodes[i]], split on x at even depths, y at odd ones
+    nodes: Vec<usize>,
+}
+
+impl KdTree {
+    pub fn new(points: &[Point]) -> KdTree {
+        let mut nodes = (0..points.len()).collect::<Vec<_>>();
+        build(points, &mut nodes, 0);
+        KdTree {
+            points: points.to_vec(),
+            nodes,
+        }
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    /// Index and distance of the closest point.
+    pub fn nearest(&self, p: &Point) -> Option<(usize, f64)> {
+        self.k_nearest(p, 1).first().copied()
+    }
+
+    /// Indices and distances of the `k` closest points, closest first.
+    pub fn k_nearest(&self, p: &Point, k: usize) -> Vec<(usize, f64)> {
+        let mut best = Vec::with_capacity(k + 1);
+        if k > 0 {
+            self.search(p, k, 0, self.nodes.len(), 0, &mut best);
+        }
+        best.into_iter().map(|(i, d2)| (i, f64::sqrt(d2))).collect()
+    }
+
+    fn search(
+        &self,
+        p: &Point,
+        k: usize,
+        lo: usize,
+        hi: usize,
+        depth: usize,
+        best: &mut Vec<(usize, f64)>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        let i = self.nodes[mid];
+        let q = &self.points[i];
+        let d2 = (q.x - p.x).powi(2) + (q.y - p.y).powi(2);
+        if best.len() < k || d2 < best[best.len() - 1].1 {
+            let pos = best.iter().position(|&(_, b)| d2 < b).unwrap_or(best.len());
+            best.insert(pos, (i, d2));
+            best.truncate(k);
+        }
+
+        let diff = if depth % 2 == 0 { p.x - q.x } else { p.y - q.y };
+        let (near, far) = if diff < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(p, k, near.0, near.1, depth + 1, best);
+        if best.len() < k || diff * diff < best[best.len() - 1].1 {
+            self.search(p, k, far.0, far.1, depth + 1, best);
+        }
+    }
+}
+
+fn build(points: &[Point], nodes: &mut [usize], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let key = |i: &usize| {
+        if depth % 2 == 0 {
+            points[*i].x
+        } else {
+            points[*i].y
+        }
+    };
+    nodes.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+    let mid = nodes.len() / 2;
+    let (left, right) = nodes.split_at_mut(mid);
+    build(points, left, depth + 1);
+    build(points, &mut right[1..], depth + 1);
+}
+
+/// Error metric minimized by ICP.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IcpMethod {
+    PointToPoint,
+    /// Distance to the line through the reference neighbors (PL-ICP). Converges
+    /// faster in structured environments.
+    PointToLine,
+}
+
+/// Parameters of the scan matcher.
+#[derive(Copy, Clone, Debug)]
+pub struct IcpConfig {
+    pub method: IcpMethod,
+    pub max_iterations: usize,
+    /// Convergence is reached when an iteration moves less than this (meters, radians).
+    pub tolerance: f64,
+    /// Pairs farther apart are not matched, in meters.
+    pub max_correspondence_distance: f64,
+    pub min_correspondences: usize,
+}
+
+impl Default for IcpConfig {
+    fn default() -> IcpConfig {
+        IcpConfig {
+            method: IcpMethod::PointToLine,
+            max_iterations: 30,
+            tolerance: 1e-4,
+            max_correspondence_distance: 0.3,
+            min_correspondences: 10,
+        }
+    }
+}
+
+/// Result of a scan matching.
+#[derive(Clone, Debug)]
+pub struct IcpResult {
+    /// Transform bringing the points of the current scan in the reference frame.
+    pub transform: Pose,
+    /// Covariance of `(x, y, theta)`.
+    pub covariance: [[f64; 3]; 3],
+    pub iterations: usize,
+    pub converged: bool,
+    /// RMS of the residuals, in meters.
+    pub error: f64,
+    pub correspondences: usize,
+}
+
+/// Aligns two point sets with ICP.
+pub struct ScanMatcher {
+    pub config: IcpConfig,
+}
+
+impl ScanMatcher {
+    pub fn new(config: IcpConfig) -> ScanMatcher {
+        ScanMatcher { config }
+    }
+
+    /// Finds the transform bringing `current` onto `reference`, starting from `initial`.
+    ///
+    /// Returns `None` if too few correspondences are found.
+    pub fn align(&self, reference: &KdTree, current: &[Point], initial: Pose) -> Option<IcpResult> {
+        let normals = match self.config.method {
+            IcpMethod::PointToLine => Some(normals(reference)),
+            IcpMethod::PointToPoint => None,
+        };
+
+        let mut transform = initial;
+        let mut converged = false;
+        let mut iterations = 0;
+        let mut pairs = vec![];
+
+        while iterations < self.config.max_iterations {
+            iterations += 1;
+            pairs = self.correspondences(reference, current, &transform);
+            if pairs.len() < self.config.min_correspondences.max(3) {
+                return None;
+            }
+
+            let delta = match &normals {
+                Some(normals) => point_to_line_step(reference.points(), normals, &pairs)?,
+                None => {
+                    let (from, to): (Vec<Point>, Vec<Point>) = pairs
+                        .iter()
+                        .map(|&(p, j)| (p, reference.points()[j]))
+                        .unzip();
+                    fit_rigid_transform(&from, &to)?
+                }
+            };
+            transform = delta.compose(&transform);
+
+            if delta.x.hypot(delta.y) < self.config.tolerance
+                && delta.theta.abs() < self.config.tolerance
+            {
+                converged = true;
+                break;
+            }
+        }
+
+        pairs = self.correspondences(reference, current, &transform);
+        let (error, covariance) = statistics(reference.points(), normals.as_deref(), &pairs);
+        Some(IcpResult {
+            transform,
+            covariance,
+            iterations,
+            converged,
+            error,
+            correspondences: pairs.len(),
+        })
+    }
+
+    /// Transformed current points paired with their closest reference point.
+    fn correspondences(
+        &self,
+        reference: &KdTree,
+        current: &[Point],
+        transform: &Pose,
+    ) -> Vec<(Point, usize)> {
+        current
+            .iter()
+            .filter_map(|p| {
+                let p = transform.transform(*p);
+                match reference.nearest(&p) {
+                    Some((j, d)) if d <= self.config.max_correspondence_distance => Some((p, j)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Normals of the reference points, from their two closest neighbors.
+fn normals(reference: &KdTree) -> Vec<Point> {
+    reference
+        .points()
+        .iter()
+        .map(|p| {
+            let near = reference.k_nearest(p, 3);
+            if near.len() < 3 {
+                return Point::new(0.0, 0.0);
+            }
+            let (a, b) = (reference.points()[near[1].0], reference.points()[near[2].0]);
+            let (dx, dy) = (b.x - a.x, b.y - a.y);
+            let len = dx.hypot(dy);
+            if len < 1e-9 {
+                Point::new(0.0, 0.0)
+            } else {
+                Point::new(-dy / len, dx / len)
+            }
+        })
+        .collect()
+}
+
+/// Residual and jacobian of a pair, for a small motion `(dx, dy, dtheta)`.
+fn residuals(
+    reference: &[Point],
+    normals: Option<&[Point]>,
+    p: &Point,
+    j: usize,
+) -> Vec<(f64, [f64; 3])> {
+    let q = reference[j];
+    match normals {
+        Some(normals) => {
+            let n = normals[j];
+            vec![(
+                n.x * (p.x - q.x) + n.y * (p.y - q.y),
+                [n.x, n.y, -n.x * p.y + n.y * p.x],
+            )]
+        }
+        None => vec![(p.x - q.x, [1.0, 0.0, -p.y]), (p.y - q.y, [0.0, 1.0, p.x])],
+    }
+}
+
+fn point_to_line_step(
+    reference: &[Point],
+    normals: &[Point],
+    pairs: &[(Point, usize)],
+) -> Option<Pose> {
+    let mut h: Matrix<3, 3> = [[0.0; 3]; 3];
+    let mut g = [0.0; 3];
+    for (p, j) in pairs {
+        for (r, jac) in residuals(reference, Some(normals), p, *j) {
+            for a in 0..3 {
+                g[a] -= jac[a] * r;
+                for b in 0..3 {
+                    h[a][b] += jac[a] * jac[b];
+                }
+            }
+        }
+    }
+    let d = solve(&h, &g)?;
+    Some(Pose::new(d[0], d[1], normalize_angle(d[2])))
+}
+
+/// RMS error and covariance estimate `sigma² (JᵀJ)⁻¹` at the solution.
+fn statistics(
+    reference: &[Point],
+    normals: Option<&[Point]>,
+    pairs: &[(Point, usize)],
+) -> (f64, [[f64; 3]; 3]) {
+    let mut h: Matrix<3, 3> = [[0.0; 3]; 3];
+    let (mut sum, mut count) = (0.0, 0);
+    for (p, j) in pairs {
+        for (r, jac) in residuals(reference, normals, p, *j) {
+            sum += r * r;
+            count += 1;
+            for a in 0..3 {
+                for b in 0..3 {
+                    h[a][b] += jac[a] * jac[b];
+                }
+            }
+        }
+    }
+    let error = if count > 0 {
+        (sum / count as f64).sqrt()
+    } else {
+        0.0
+    };
+    let sigma2 = if count > 3 {
+        sum / (count - 3) as f64
+    } else {
+        0.0
+    };
+    let covariance = invert(&h)
+        .map(|hi| hi.map(|row| row.map(|v| v * sigma2)))
+        .unwrap_or([[f64::INFINITY; 3]; 3]);
+    (error, covariance)
+}
+
+/// Lidar odometry : matches each turn against the previous one.
+pub struct LidarOdometry {
+    mount: Mount,
+    matcher: ScanMatcher,
+    reference: Option<KdTree>,
+    pose: Pose,
+}
+
+impl LidarOdometry {
+    pub fn new(mount: Mount, config: IcpConfig) -> LidarOdometry {
+        LidarOdometry {
+            mount,
+            matcher: ScanMatcher::new(config),
+            reference: None,
+            pose: Pose::default(),
+        }
+    }
+
+    /// Pose of the robot relative to its pose at the first turn.
+    pub fn pose(&self) -> Pose {
+        self.pose
+    }
+
+    /// Matches a new turn, as yielded by the `Lidar` iterator.
+    ///
+    /// Returns the motion since the previous turn, or `None` for the first turn
+    /// or when matching fails (the turn then becomes the new reference).
+    pub fn update(&mut self, scan: &[Option<Sample>]) -> Option<IcpResult> {
+        let points = scan.to_points(&self.mount);
+        let result = self
+            .reference
+            .as_ref()
+            .and_then(|reference| self.matcher.align(reference, &points, Pose::default()));
+        if let Some(result) = &result {
+            self.pose = self.pose.compose(&result.transform);
+        }
+        self.reference = Some(KdTree::new(&points));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::Rng;
+
+    #[test]
+    fn nearest_neighbors() {
+        let mut rng = Rng::new(42);
+        let mut random = || {
+            Point::new(
+                rng.below(10_000) as f64 / 1000.0,
+                rng.below(10_000) as f64 / 1000.0,
+            )
+        };
+        let points = (0..500).map(|_| random()).collect::<Vec<_>>();
+        let tree = KdTree::new(&points);
+        assert_eq!(tree.points().len(), 500);
+        for _ in 0..100 {
+            let p = random();
+            let distance = |q: &Point| ((q.x - p.x).powi(2) + (q.y - p.y).powi(2)).sqrt();
+            let mut brute = points.iter().map(distance).collect::<Vec<_>>();
+            brute.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let (i, d) = tree.nearest(&p).unwrap();
+            assert_eq!(d, brute[0]);
+            assert_eq!(distance(&points[i]), d);
+            let k = tree
+                .k_nearest(&p, 5)
+                .iter()
+                .map(|&(_, d)| d)
+                .collect::<Vec<_>>();
+            assert_eq!(k, brute[..5]);
+        }
+        assert!(KdTree::new(&[]).nearest(&Point::new(0.0, 0.0)).is_none());
+    }
+
+    /// Points every 2 cm along the walls of a 4 x 3 m room, starting `offset` meters after the corners.
+    fn room(offset: f64) -> Vec<Point> {
+        let corners = [
+            (-2.0, -1.5),
+            (2.0, -1.5),
+            (2.0, 1.5),
+            (-2.0, 1.5),
+            (-2.0, -1.5),
+        ];
+        corners
+            .windows(2)
+            .flat_map(|w| {
+                let (a, b) = (Point::new(w[0].0, w[0].1), Point::new(w[1].0, w[1].1));
+                let n = (a.distance(&b) / 0.02) as usize;
+                (0..n).map(move |i| {
+                    let t = (i as f64 * 0.02 + offset) / a.distance(&b);
+                    Point::new(a.x + t * (b.x - a.x), a.y + t * (b.y - a.y))
+                })
+            })
+            .collect()
+    }
+
+    fn assert_recovered(method: IcpMethod, reference: &[Point], current: &[Point], motion: Pose) {
+        let current = current
+            .iter()
+            .map(|&p| motion.inverse_transform(p))
+            .collect::<Vec<_>>();
+        let matcher = ScanMatcher::new(IcpConfig {
+            method,
+            max_iterations: 100,
+            ..IcpConfig::default()
+        });
+        let result = matcher
+            .align(&KdTree::new(reference), &current, Pose::default())
+            .unwrap();
+        assert!(result.converged);
+        let t = result.transform;
+        assert!((t.x - motion.x).abs() < 1e-3, "{}", t);
+        assert!((t.y - motion.y).abs() < 1e-3, "{}", t);
+        assert!((t.theta - motion.theta).abs() < 1e-3, "{}", t);
+        assert!(result.error < 0.01);
+    }
+
+    #[test]
+    fn point_to_line() {
+        // the room sampled at other places after the motion
+        let motion = Pose::new(0.1, -0.05, 0.05);
+        assert_recovered(IcpMethod::PointToLine, &room(0.0), &room(0.01), motion);
+    }
+
+    #[test]
+    fn point_to_point() {
+        // scattered posts, seen again after the motion
+        let mut rng = Rng::new(7);
+        let posts = (0..100)
+            .map(|_| {
+                Point::new(
+                    rng.below(10_000) as f64 / 1000.0 - 5.0,
+                    rng.below(10_000) as f64 / 1000.0 - 5.0,
+                )
+            })
+            .collect::<Vec<_>>();
+        let motion = Pose::new(0.1, -0.05, 0.02);
+        assert_recovered(IcpMethod::PointToPoint, &posts, &posts, motion);
+    }
+}
@@ -0,0 +1,640 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+
+use crate::lidar::{unix_time, DriverStats, Sample};
+use crate::safety::sector_minima;
+use crate::transform::{Mount, Scan};
+
+/// Default bus of the Ivy applications : UDP broadcast on port 2010.
+pub const DEFAULT_BUS: &str = "127.255.255.255:2010";
+
+const PROTOCOL_VERSION: u32 = 3;
+
+// message types of the Ivy protocol
+const BYE: u32 = 0;
+const ADD_REGEXP: u32 = 1;
+const MSG: u32 = 2;
+const DEL_REGEXP: u32 = 4;
+const END_REGEXP: u32 = 5;
+const START_REGEXP: u32 = 6;
+const PING: u32 = 9;
+const PONG: u32 = 10;
+
+// separators of the Ivy protocol : after the message id, and after each argument
+const STX: char = '\u{2}';
+const ETX: char = '\u{3}';
+
+struct Peer {
+    stream: TcpStream,
+    // regexps the peer subscribed with, by id
+    regexps: Vec<(u32, Regex)>,
+    closed: bool,
+}
+
+type Peers = Arc<Mutex<Vec<Arc<Mutex<Peer>>>>>;
+
+/// A publish-only agent of the Ivy software bus.
+///
+/// Like the other Ivy agents, it announces itself with a UDP broadcast on the
+/// bus when started, and the agents already there connect to it with TCP. It
+/// then sends each message to the agents whose subscription regexps match it.
+/// Agents started later find it if the bus port can be bound for listening
+/// (it can't when another agent on the same host already has it), or with `connect`.
+///
+/// When dropped, it says goodbye to the agents and stops listening.
+pub struct IvyBus {
+    name: String,
+    port: u16,
+    peers: Peers,
+    stopped: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+    broadcast_thread: Option<JoinHandle<()>>,
+}
+
+impl IvyBus {
+    /// Joins the bus as `name`, with a bus given as `broadcast_address:port`
+    /// (see `DEFAULT_BUS`).
+    pub fn start(name: &str, bus: &str) -> io::Result<IvyBus> {
+        let listener = TcpListener::bind("0.0.0.0:0")?;
+        let port = listener.local_addr()?.port();
+        let peers: Peers = Arc::new(Mutex::new(vec![]));
+        let mut ivy = IvyBus {
+            name: name.to_string(),
+            port,
+            peers,
+            stopped: Arc::new(AtomicBool::new(false)),
+            accept_thread: None,
+            broadcast_thread: None,
+        };
+
+        let (accepted, agent) = (ivy.peers.clone(), ivy.name.clone());
+        let stopped = ivy.stopped.clone();
+        ivy.accept_thread = Some(thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Err(e) = add_peer(stream, &agent, port, &accepted) {
+                    eprintln!("ivy connection failed : {}", e);
+                }
+            }
+        }));
+
+        let bus_addr = bus
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid bus"))?;
+        let app_id = format!(
+            "{}{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            std::process::id()
+        );
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_broadcast(true)?;
+        let hello = format!("{} {} {} {}\n", PROTOCOL_VERSION, port, app_id, name);
+        socket.send_to(hello.as_bytes(), bus_addr)?;
+
+        match UdpSocket::bind(("0.0.0.0", bus_addr.port())) {
+            Ok(socket) => {
+                // short reads to notice when the bus is dropped
+                socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+                let (peers, agent) = (ivy.peers.clone(), ivy.name.clone());
+                let stopped = ivy.stopped.clone();
+                ivy.broadcast_thread = Some(thread::spawn(move || {
+                    listen_broadcasts(socket, &agent, port, &app_id, &peers, &stopped)
+                }));
+            }
+            Err(e) => eprintln!(
+                "ivy : not listening to the bus ({}), only agents already started will connect",
+                e
+            ),
+        }
+        Ok(ivy)
+    }
+
+    /// Connects to an agent directly, without broadcast.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        add_peer(
+            TcpStream::connect(addr)?,
+            &self.name,
+            self.port,
+            &self.peers,
+        )
+    }
+
+    /// TCP port the agents connect to.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Number of connected agents.
+    pub fn peers(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+
+    /// Sends a message to the agents subscribed to it, returns how many received it.
+    pub fn send(&self, message: &str) -> usize {
+        let mut received = 0;
+        self.peers.lock().unwrap().retain(|peer| {
+            let mut guard = peer.lock().unwrap();
+            let peer = &mut *guard;
+            if peer.closed {
+                return false;
+            }
+            let mut sent = false;
+            for (id, regexp) in &peer.regexps {
+                let captures = match regexp.captures(message) {
+                    Some(captures) => captures,
+                    None => continue,
+                };
+                let mut line = format!("{} {}{}", MSG, id, STX);
+                for capture in captures.iter().skip(1) {
+                    line.push_str(capture.map_or("", |c| c.as_str()));
+                    line.push(ETX);
+                }
+                line.push('\n');
+                if peer.stream.write_all(line.as_bytes()).is_err() {
+                    peer.closed = true;
+                    return false;
+                }
+                sent = true;
+            }
+            received += sent as usize;
+            true
+        });
+        received
+    }
+}
+
+impl Drop for IvyBus {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // the accept thread waits for a connection
+        if TcpStream::connect(("127.0.0.1", self.port)).is_ok() {
+            if let Some(thread) = self.accept_thread.take() {
+                let _ = thread.join();
+            }
+        }
+        if let Some(thread) = self.broadcast_thread.take() {
+            let _ = thread.join();
+        }
+        for peer in self.peers.lock().unwrap().drain(..) {
+            let mut peer = peer.lock().unwrap();
+            let _ = write_message(&mut peer.stream, BYE, 0, "");
+            // ends the reading thread of the peer
+            let _ = peer.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn write_message(stream: &mut TcpStream, kind: u32, id: u32, arg: &str) -> io::Result<()> {
+    writeln!(stream, "{} {}{}{}", kind, id, STX, arg)
+}
+
+/// Introduces us to a new peer, and reads its messages in a background thread.
+fn add_peer(mut stream: TcpStream, name: &str, port: u16, peers: &Peers) -> io::Result<()> {
+    // a stalled agent must not block the others
+    stream.set_write_timeout(Some(Duration::from_millis(500)))?;
+    // a publisher has no subscription of its own
+    write_message(&mut stream, START_REGEXP, port as u32, name)?;
+    write_message(&mut stream, END_REGEXP, 0, "")?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let peer = Arc::new(Mutex::new(Peer {
+        stream,
+        regexps: vec![],
+        closed: false,
+    }));
+    peers.lock().unwrap().push(peer.clone());
+    thread::spawn(move || {
+        for line in reader.split(b'\n') {
+            let line = match line {
+                Ok(line) => String::from_utf8_lossy(&line).to_string(),
+                Err(_) => break,
+            };
+            if !handle_message(&line, &peer) {
+                break;
+            }
+        }
+        peer.lock().unwrap().closed = true;
+    });
+    Ok(())
+}
+
+/// Handles a message of a peer, returns `false` when it leaves.
+fn handle_message(line: &str, peer: &Mutex<Peer>) -> bool {
+    let (head, arg) = line.split_once(STX).unwrap_or((line, ""));
+    let mut fields = head.split_whitespace().map(|f| f.parse::<u32>().ok());
+    let (kind, id) = match (fields.next().flatten(), fields.next().flatten()) {
+        (Some(kind), Some(id)) => (kind, id),
+        _ => return true,
+    };
+    let mut peer = peer.lock().unwrap();
+    match kind {
+        BYE => return false,
+        ADD_REGEXP => match Regex::new(arg) {
+            Ok(regexp) => peer.regexps.push((id, regexp)),
+            Err(e) => eprintln!("ivy : unsupported regexp {} : {}", arg, e),
+        },
+        DEL_REGEXP => peer.regexps.retain(|(i, _)| *i != id),
+        PING => {
+            let _ = write_message(&mut peer.stream, PONG, id, "");
+        }
+        _ => {}
+    }
+    true
+}
+
+/// Connects to the agents announcing themselves on the bus.
+fn listen_broadcasts(
+    socket: UdpSocket,
+    name: &str,
+    port: u16,
+    app_id: &str,
+    peers: &Peers,
+    stopped: &AtomicBool,
+) {
+    let mut buffer = [0; 1024];
+    while !stopped.load(Ordering::SeqCst) {
+        let (n, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(_) => break,
+        };
+        let text = String::from_utf8_lossy(&buffer[..n]);
+        let fields = text.split_whitespace().collect::<Vec<_>>();
+        let (version, peer_port, peer_id) = match fields.as_slice() {
+            [version, port, id, ..] => (version.parse::<u32>(), port.parse::<u16>(), *id),
+            _ => continue,
+        };
+        if version != Ok(PROTOCOL_VERSION) || peer_id == app_id {
+            continue;
+        }
+        if let Ok(peer_port) = peer_port {
+            let addr = SocketAddr::new(from.ip(), peer_port);
+            if let Err(e) = TcpStream::connect(addr).and_then(|s| add_peer(s, name, port, peers)) {
+                eprintln!("ivy : failed to connect to {} : {}", addr, e);
+            }
+        }
+    }
+}
+
+/// A message template, where `{name}` placeholders are replaced by values.
+#[derive(Clone, Debug)]
+struct Template {
+    text: String,
+    placeholder: Regex,
+}
+
+impl Template {
+    fn new(text: &str, names: &[&str]) -> Result<Template, Box<dyn Error>> {
+        let placeholder = Regex::new(r"\{(\w+)\}").unwrap();
+        for captures in placeholder.captures_iter(text) {
+            if !names.contains(&&captures[1]) {
+                return Err(format!(
+                    "unknown placeholder {{{}}} in '{}', expected one of {}",
+                    &captures[1],
+                    text,
+                    names.join(", ")
+                )
+                .into());
+            }
+        }
+        Ok(Template {
+            text: text.to_string(),
+            placeholder,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    fn render(&self, values: &HashMap<&str, String>) -> String {
+        self.placeholder
+            .replace_all(&self.text, |captures: &regex::Captures| {
+                values.get(&captures[1]).cloned().unwrap_or_default()
+            })
+            .to_string()
+    }
+}
+
+const SCAN_FIELDS: [&str; 6] = [
+    "sender",
+    "time",
+    "count",
+    "angles",
+    "distances",
+    "qualities",
+];
+const OBSTACLE_FIELDS: [&str; 6] = ["sender", "time", "distance", "bearing", "sectors", "count"];
+const HEALTH_FIELDS: [&str; 8] = [
+    "sender",
+    "time",
+    "rpm",
+    "scan_rate",
+    "packets",
+    "errors",
+    "error_rate",
+    "valid",
+];
+
+/// Text of the Ivy messages, in the Paparazzi style `<sender> <NAME> <fields>`.
+///
+/// Templates use `{name}` placeholders, lists are comma separated :
+/// - scan : `{sender}`, `{time}` (seconds), `{count}`, `{angles}` (robot frame,
+///   degrees), `{distances}` (mm) and `{qualities}`, of the valid samples.
+/// - obstacles : `{sender}`, `{time}`, `{distance}` and `{bearing}` of the
+///   closest point (m, degrees, robot frame), `{sectors}` (closest point per
+///   sector in m, 0 when empty, see `safety::sector_minima`) and `{count}` (valid samples).
+/// - health : `{sender}`, `{time}`, `{rpm}`, `{scan_rate}` (Hz), `{packets}`,
+///   `{errors}`, `{error_rate}` (%) and `{valid}` (valid samples of the last scan, %).
+///
+/// An empty template disables its message.
+#[derive(Clone, Debug)]
+pub struct IvyFormat {
+    pub sender: String,
+    /// Number of sectors of `{sectors}`.
+    pub sectors: usize,
+    scan: Template,
+    obstacles: Template,
+    health: Template,
+}
+
+impl Default for IvyFormat {
+    fn default() -> IvyFormat {
+        IvyFormat {
+            sender: "lidar".to_string(),
+            sectors: 8,
+            scan: Template::new(
+                "{sender} LIDAR_SCAN {time} {angles} {distances}",
+                &SCAN_FIELDS,
+            )
+            .unwrap(),
+            obstacles: Template::new(
+                "{sender} LIDAR_OBSTACLES {time} {distance} {bearing} {sectors}",
+                &OBSTACLE_FIELDS,
+            )
+            .unwrap(),
+            health: Template::new(
+                "{sender} LIDAR_STATUS {time} {rpm} {scan_rate} {error_rate}",
+                &HEALTH_FIELDS,
+            )
+            .unwrap(),
+        }
+    }
+}
+
+fn join<T, F: Fn(&T) -> String>(items: &[T], f: F) -> String {
+    items.iter().map(f).collect::<Vec<_>>().join(",")
+}
+
+impl IvyFormat {
+    pub fn with_sender(mut self, sender: &str) -> IvyFormat {
+        self.sender = sender.to_string();
+        self
+    }
+
+    pub fn with_sectors(mut self, sectors: usize) -> IvyFormat {
+        self.sectors = sectors.max(1);
+        self
+    }
+
+    pub fn with_scan(mut self, template: &str) -> Result<IvyFormat, Box<dyn Error>> {
+        self.scan = Template::new(template, &SCAN_FIELDS)?;
+        Ok(self)
+    }
+
+    pub fn with_obstacles(mut self, template: &str) -> Result<IvyFormat, Box<dyn Error>> {
+        self.obstacles = Template::new(template, &OBSTACLE_FIELDS)?;
+        Ok(self)
+    }
+
+    pub fn with_health(mut self, template: &str) -> Result<IvyFormat, Box<dyn Error>> {
+        self.health = Template::new(template, &HEALTH_FIELDS)?;
+        Ok(self)
+    }
+
+    fn values(&self) -> HashMap<&'static str, String> {
+        let mut values = HashMap::new();
+        values.insert("sender", self.sender.clone());
+        values.insert("time", format!("{:.3}", unix_time()));
+        values
+    }
+
+    pub fn scan(&self, mount: &Mount, scan: &[Option<Sample>]) -> Option<String> {
+        if self.scan.is_empty() {
+            return None;
+        }
+        let samples = scan.iter().flatten().collect::<Vec<_>>();
+        let mut values = self.values();
+        values.insert("count", samples.len().to_string());
+        values.insert(
+            "angles",
+            join(&samples, |s| {
+                format!("{:.1}", mount.robot_angle(s.angle).to_degrees())
+            }),
+        );
+        values.insert("distances", join(&samples, |s| s.distance.to_string()));
+        values.insert("qualities", join(&samples, |s| s.quality.to_string()));
+        Some(self.scan.render(&values))
+    }
+
+    pub fn obstacles(&self, mount: &Mount, scan: &[Option<Sample>]) -> Option<String> {
+        if self.obstacles.is_empty() {
+            return None;
+        }
+        let points = scan.to_points(mount);
+        let closest = points.iter().min_by(|a, b| a.norm().total_cmp(&b.norm()));
+        let mut values = self.values();
+        values.insert(
+            "distance",
+            closest.map_or("0".to_string(), |p| format!("{:.3}", p.norm())),
+        );
+        values.insert(
+            "bearing",
+            closest.map_or("0".to_string(), |p| {
+                format!("{:.1}", p.angle().to_degrees())
+            }),
+        );
+        values.insert(
+            "sectors",
+            join(&sector_minima(&points, self.sectors), |d| {
+                format!("{:.3}", d.unwrap_or(0.0))
+            }),
+        );
+        values.insert("count", points.len().to_string());
+        Some(self.obstacles.render(&values))
+    }
+
+    /// `scan_rate` is in Hz, `valid` is the ratio of valid samples in [0, 1].
+    pub fn health(&self, stats: &DriverStats, scan_rate: f64, valid: f64) -> Option<String> {
+        if self.health.is_empty() {
+            return None;
+        }
+        let mut values = self.values();
+        values.insert("rpm", format!("{:.0}", stats.rpm.unwrap_or(0.0)));
+        values.insert("scan_rate", format!("{:.1}", scan_rate));
+        values.insert("packets", stats.packets.to_string());
+        values.insert("errors", stats.checksum_errors.to_string());
+        values.insert("error_rate", format!("{:.2}", 100.0 * stats.error_rate()));
+        values.insert("valid", format!("{:.1}", 100.0 * valid));
+        Some(self.health.render(&values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Convention;
+    use std::time::Instant;
+
+    /// A bus on a free local port, for the broadcasts of the agent to be read back.
+    fn start() -> IvyBus {
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        IvyBus::start("lidar", &format!("127.0.0.1:{}", port)).unwrap()
+    }
+
+    /// An agent connected to `bus`, which has introduced itself.
+    fn agent(bus: &IvyBus) -> (TcpStream, BufReader<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        bus.connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let start = format!("{} {}{}lidar", START_REGEXP, bus.port(), STX);
+        assert_eq!(read_line(&mut reader), start);
+        assert_eq!(read_line(&mut reader), format!("{} 0{}", END_REGEXP, STX));
+        (stream, reader)
+    }
+
+    fn read_line(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line.trim_end_matches('\n').to_string()
+    }
+
+    /// Sends `message` until `expected` agents receive it, as the bus reads
+    /// the subscriptions in the background.
+    fn send_until(bus: &IvyBus, message: &str, expected: usize) {
+        let start = Instant::now();
+        while bus.send(message) != expected {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn sends_to_subscribers() {
+        let bus = start();
+        let (mut stream, mut reader) = agent(&bus);
+        assert_eq!(bus.peers(), 1);
+        assert_eq!(bus.send("lidar LIDAR_SCAN 1.5 0,90 300,400"), 0);
+
+        writeln!(stream, "1 7{}^lidar LIDAR_SCAN ([0-9.]+) (.*)", STX).unwrap();
+        send_until(&bus, "lidar LIDAR_SCAN 1.5 0,90 300,400", 1);
+        let msg = format!("2 7{}1.5{}0,90 300,400{}", STX, ETX, ETX);
+        assert_eq!(read_line(&mut reader), msg);
+        assert_eq!(bus.send("lidar LIDAR_STATUS 1.5 600"), 0);
+
+        writeln!(stream, "4 7{}\n9 3{}", STX, STX).unwrap();
+        // the regexp is deleted before the ping is answered
+        assert_eq!(read_line(&mut reader), format!("10 3{}", STX));
+        assert_eq!(bus.send("lidar LIDAR_SCAN 1.5 0,90 300,400"), 0);
+
+        // the agent leaves
+        writeln!(stream, "0 0{}", STX).unwrap();
+        let start = Instant::now();
+        while bus.peers() != 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            bus.send("");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn says_goodbye_when_dropped() {
+        let bus = start();
+        let port = bus.port();
+        let (_stream, mut reader) = agent(&bus);
+        drop(bus);
+        assert_eq!(read_line(&mut reader), format!("{} 0{}", BYE, STX));
+        assert_eq!(read_line(&mut reader), "");
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn connects_to_broadcasting_agents() {
+        let bus_port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let bus = IvyBus::start("lidar", &format!("127.0.0.1:{}", bus_port)).unwrap();
+
+        // an agent started after the bus says hello
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let hello = format!(
+            "{} {} 42 agent\n",
+            PROTOCOL_VERSION,
+            listener.local_addr().unwrap().port()
+        );
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .send_to(hello.as_bytes(), ("127.0.0.1", bus_port))
+            .unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        let start = format!("{} {}{}lidar", START_REGEXP, bus.port(), STX);
+        assert_eq!(read_line(&mut reader), start);
+        assert_eq!(read_line(&mut reader), format!("{} 0{}", END_REGEXP, STX));
+        let start = Instant::now();
+        while bus.peers() != 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn templates() {
+        let format = IvyFormat::default()
+            .with_sender("front")
+            .with_health("{sender} STATUS {packets} {errors} {error_rate}")
+            .unwrap();
+        let stats = DriverStats {
+            packets: 200,
+            checksum_errors: 3,
+            ..DriverStats::default()
+        };
+        let health = format.health(&stats, 10.0, 1.0).unwrap();
+        assert_eq!(health, "front STATUS 200 3 1.50");
+        let unknown = IvyFormat::default().with_scan("{sender} {unknown}");
+        assert!(unknown.is_err());
+        let disabled = format.with_scan("").unwrap();
+        assert_eq!(disabled.scan(&Mount::new(Convention::LD06), &[]), None);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::lidar::{impl_drop, impl_iterator, Lidar, Sample, Turn};
+use crate::calibration::{stored_correction, RangeCorrection};
+use crate::filter::{Pipeline, RangeFilter};
+use crate::lidar::{impl_drop, impl_iterator, DriverStats, Lidar, PacketLog, RawPacket, Sample, Turn};
+use crate::transform::Convention;
 use serialport::SerialPort;
 use std::io;
 use std::mem;
@@ -28,9 +31,14 @@ const CRC_TABLE: [u8; 256] = [
 
 pub struct LD06 {
     port: String,
+    baud_rate: u32,
     tx_cmd: Option<mpsc::Sender<()>>,
     join_handle: Option<thread::JoinHandle<()>>,
     data: Arc<Mutex<Box<Option<Turn>>>>,
+    stats: Arc<Mutex<DriverStats>>,
+    packets: PacketLog,
+    correction: Option<RangeCorrection>,
+    filters: Mutex<Pipeline>,
 }
 
 impl Lidar for LD06 {
@@ -38,7 +46,12 @@ impl Lidar for LD06 {
         let mut boxed_turn = self.data.lock().unwrap();
         let bt = mem::replace(&mut *boxed_turn, Box::new(None));
         match *bt {
-            Some(turn) => Some(turn.samples),
+            Some(mut turn) => {
+                if let Some(correction) = &self.correction {
+                    correction.correct(&mut turn.samples);
+                }
+                Some(self.filters.lock().unwrap().run(turn.samples))
+            }
             None => None,
         }
     }
@@ -47,12 +60,14 @@ impl Lidar for LD06 {
         let (tx_cmd, rx_cmd) = mpsc::channel();
         self.tx_cmd = Some(tx_cmd.clone());
 
-        let port = serialport::new(&self.port, 230_400)
+        let port = serialport::new(&self.port, self.baud_rate)
             .timeout(Duration::from_millis(3))
             .open()?;
 
         let adata = self.data.clone();
-        let th = thread::spawn(move || ld06_run(port, rx_cmd, adata));
+        let astats = self.stats.clone();
+        let apackets = self.packets.clone();
+        let th = thread::spawn(move || ld06_run(port, rx_cmd, adata, astats, apackets));
         self.join_handle = Some(th);
         Ok(())
     }
@@ -75,17 +90,70 @@ impl Lidar for LD06 {
             false
         }
     }
+
+    fn convention(&self) -> Convention {
+        Convention::LD06
+    }
+
+    fn stats(&self) -> DriverStats {
+        *self.stats.lock().unwrap()
+    }
+
+    fn set_packet_capture(&self, enabled: bool) {
+        self.packets.set_enabled(enabled);
+    }
+
+    fn take_packets(&self) -> Vec<RawPacket> {
+        self.packets.take()
+    }
 }
 
 impl LD06 {
+    pub const BAUD_RATE: u32 = 230_400;
+
     pub fn new(port: &str) -> LD06 {
         LD06 {
             port: port.into(),
+            baud_rate: LD06::BAUD_RATE,
             tx_cmd: None,
             join_handle: None,
             data: Arc::new(Mutex::new(Box::new(None))),
+            stats: Arc::new(Mutex::new(DriverStats::default())),
+            packets: PacketLog::default(),
+            correction: None,
+            filters: Mutex::new(LD06::default_filters()),
         }
     }
+
+    /// Baud rate used when started, `BAUD_RATE` by default.
+    pub fn with_baud_rate(mut self, baud_rate: u32) -> LD06 {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    /// Filters applied by default : the LD06 reports 0 when it gets no echo, and is specified from 2cm to 12m.
+    pub fn default_filters() -> Pipeline {
+        Pipeline::new().with(RangeFilter::new(20, 12_000))
+    }
+
+    /// Replaces the filters applied to the scans returned by `get_scan`. The range
+    /// correction, if any, is kept and applied before them.
+    pub fn set_filters(&mut self, filters: Pipeline) {
+        self.filters = Mutex::new(filters);
+    }
+
+    /// Corrects the range bias of this unit, see `calibration::RangeCorrection`.
+    pub fn with_range_correction(mut self, correction: RangeCorrection) -> LD06 {
+        self.correction = Some(correction);
+        self
+    }
+
+    /// Corrects the range bias with the correction stored for the unit on the
+    /// port, if any (see `calibration::stored_correction`).
+    pub fn with_stored_correction(mut self) -> Result<LD06, Box<dyn std::error::Error>> {
+        self.correction = stored_correction(&self.port.clone())?;
+        Ok(self)
+    }
 }
 
 enum RcvState {
@@ -100,6 +168,8 @@ struct LD06Transport {
     buffer: Vec<u8>,
     rcv_state: RcvState,
     nb_points: usize,
+    packets: u64,
+    checksum_errors: u64,
 }
 
 fn u16le_from_slice(buffer: &[u8]) -> u16 {
@@ -113,6 +183,8 @@ impl LD06Transport {
             buffer: Vec::new(),
             rcv_state: RcvState::WaitStart,
             nb_points: 0,
+            packets: 0,
+            checksum_errors: 0,
         }
     }
 
@@ -191,12 +263,11 @@ impl LD06Transport {
                 mem::swap(&mut self.buffer, &mut self.frame);
                 self.buffer.clear();
 
-                let calc_check = self.checksum();
-                if c == calc_check {
-                    //return None;
+                self.packets += 1;
+                if c == self.checksum() {
                     return Some(self.parse());
                 } else {
-                    println!("checksum failed: {} {}", c, calc_check);
+                    self.checksum_errors += 1;
                 }
             }
         };
@@ -208,6 +279,8 @@ fn ld06_run(
     mut serial: Box<dyn SerialPort>,
     rx_cmd: Receiver<()>,
     data: Arc<Mutex<Box<Option<Turn>>>>,
+    stats: Arc<Mutex<DriverStats>>,
+    packets: PacketLog,
 ) {
     let mut transport = LD06Transport::new();
 
@@ -220,7 +293,13 @@ fn ld06_run(
         match serial.read(&mut buffer) {
             Ok(nb) => {
                 for c in &buffer[0..nb] {
-                    if let Some((_speed, samples)) = transport.put(*c) {
+                    let received = transport.packets;
+                    let decoded = transport.put(*c);
+                    if transport.packets != received {
+                        packets.push(&transport.frame);
+                    }
+                    if let Some((speed, samples)) = decoded {
+                        stats.lock().unwrap().rpm = Some(speed * 60.0);
                         for s in samples {
                             if s.angle < turn.last_angle() {
                                 // a turn is complete, update LD06 last turn
@@ -235,6 +314,9 @@ fn ld06_run(
                         }
                     }
                 }
+                let mut stats = stats.lock().unwrap();
+                stats.packets = transport.packets;
+                stats.checksum_errors = transport.checksum_errors;
             }
             Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
             Err(e) => eprintln!("{:?}", e),
@@ -242,7 +324,6 @@ fn ld06_run(
 
         match rx_cmd.try_recv() {
             Ok(_) | Err(TryRecvError::Disconnected) => {
-                println!("Terminating.");
                 break;
             }
             Err(TryRecvError::Empty) => {}
@@ -2,9 +2,43 @@ pub mod lidar;
 pub mod ust05ln;
 pub mod xv11;
 pub mod ld06;
+pub mod transform;
+pub mod filter;
+pub mod deskew;
+pub mod segmentation;
+pub mod lines;
+pub mod beacons;
+pub mod localization;
+pub mod map;
+pub mod tracking;
+pub mod occupancy;
+pub mod icp;
+pub mod safety;
+pub mod fusion;
+pub mod driver;
+pub mod calibration;
+pub mod record;
+pub mod mcap;
+pub mod ivy;
+pub mod network;
+#[cfg(feature = "tui")]
+pub mod viewer;
+#[cfg(feature = "web")]
+pub mod web;
+#[cfg(feature = "foxglove")]
+pub mod foxglove;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 
-pub use crate::lidar::{Lidar, Sample};
+mod linalg;
+
+pub use crate::lidar::{DriverStats, Lidar, RawPacket, Sample};
+pub use crate::filter::{Filter, Pipeline};
+pub use crate::transform::{Convention, Mount, MountConfig, Point, Pose, Scan};
 
 pub use crate::ust05ln::UST05LN;
 pub use crate::xv11::XV11;
 pub use crate::ld06::LD06;
+pub use crate::fusion::FusedLidar;
+pub use crate::driver::Driver;
+pub use crate::network::{LidarServer, NetworkLidar};
@@ -1,5 +1,11 @@
+use std::collections::VecDeque;
 use std::fmt;
 use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::transform::Convention;
 
 #[derive(Copy, Clone)]
 pub struct Sample {
@@ -52,11 +58,107 @@ impl fmt::Display for Turn {
 
 
 
+/// Health of the link with a lidar.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DriverStats {
+    /// Rotation speed reported by the sensor, in turns per minute.
+    pub rpm: Option<f64>,
+    /// Packets received, corrupted ones included.
+    pub packets: u64,
+    /// Packets dropped because of a bad checksum, or malformed.
+    pub checksum_errors: u64,
+    /// Scans dropped by the sources reassembling them from several packets,
+    /// because they were incomplete or late.
+    pub dropped_scans: u64,
+}
+
+impl DriverStats {
+    /// Ratio of dropped packets, in [0, 1].
+    pub fn error_rate(&self) -> f64 {
+        if self.packets == 0 {
+            0.0
+        } else {
+            self.checksum_errors as f64 / self.packets as f64
+        }
+    }
+}
+
+/// A packet as read from the sensor, before decoding.
+#[derive(Clone, Debug)]
+pub struct RawPacket {
+    /// Reception time.
+    pub time: SystemTime,
+    pub data: Vec<u8>,
+}
+
+/// Current time in seconds since the UNIX epoch.
+pub fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Raw packets kept by a driver until taken, see `Lidar::take_packets`.
+/// Nothing is kept until the capture is enabled.
+#[derive(Clone, Default)]
+pub(crate) struct PacketLog {
+    enabled: Arc<AtomicBool>,
+    packets: Arc<Mutex<VecDeque<RawPacket>>>,
+}
+
+impl PacketLog {
+    /// Packets kept when they are not taken, the oldest ones are dropped.
+    const CAPACITY: usize = 4096;
+
+    pub(crate) fn push(&self, data: &[u8]) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut packets = self.packets.lock().unwrap();
+        if packets.len() == PacketLog::CAPACITY {
+            packets.pop_front();
+        }
+        packets.push_back(RawPacket {
+            time: SystemTime::now(),
+            data: data.to_vec(),
+        });
+    }
+
+    /// Disabling the capture drops the packets not taken yet.
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.packets.lock().unwrap().clear();
+        }
+    }
+
+    pub(crate) fn take(&self) -> Vec<RawPacket> {
+        self.packets.lock().unwrap().drain(..).collect()
+    }
+}
+
 pub trait Lidar {
     fn get_scan(&self) -> Option<Vec<Option<Sample>>>;
     fn start(&mut self) -> Result<(), Box<dyn Error>>;
     fn stop(&mut self);
     fn is_running(&self) -> bool;
+    fn convention(&self) -> Convention;
+
+    /// Counters since the driver was created, empty for sources that aren't a serial link.
+    fn stats(&self) -> DriverStats {
+        DriverStats::default()
+    }
+
+    /// Starts or stops keeping the raw packets for `take_packets`, off by default.
+    /// Ignored by sources that aren't a serial link.
+    fn set_packet_capture(&self, _enabled: bool) {}
+
+    /// Raw packets received since the previous call, while the capture is enabled.
+    /// Empty for sources that aren't a serial link.
+    fn take_packets(&self) -> Vec<RawPacket> {
+        vec![]
+    }
 }
 
 
@@ -71,6 +173,9 @@ macro_rules! impl_iterator {
                         if let Some(scan) = self.get_scan() {
                             return Some(scan);
                         }
+                        if !self.is_running() {
+                            return None;
+                        }
                     }
                 } else {
                     None
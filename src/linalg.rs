@@ -0,0 +1,120 @@
+pub(crate) type Matrix<const N: usize, const M: usize> = [[f64; M]; N];
+
+pub(crate) fn zeros<const N: usize, const M: usize>() -> Matrix<N, M> {
+    [[0.0; M]; N]
+}
+
+pub(crate) fn identity<const N: usize>() -> Matrix<N, N> {
+    let mut m = zeros();
+    for (i, row) in m.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    m
+}
+
+pub(crate) fn mul<const N: usize, const K: usize, const M: usize>(
+    a: &Matrix<N, K>,
+    b: &Matrix<K, M>,
+) -> Matrix<N, M> {
+    let mut m = zeros();
+    for i in 0..N {
+        for j in 0..M {
+            m[i][j] = (0..K).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+pub(crate) fn transpose<const N: usize, const M: usize>(a: &Matrix<N, M>) -> Matrix<M, N> {
+    let mut m = zeros();
+    for (i, row) in a.iter().enumerate() {
+        for (j, v) in row.iter().enumerate() {
+            m[j][i] = *v;
+        }
+    }
+    m
+}
+
+pub(crate) fn add<const N: usize, const M: usize>(
+    a: &Matrix<N, M>,
+    b: &Matrix<N, M>,
+) -> Matrix<N, M> {
+    let mut m = *a;
+    for i in 0..N {
+        for j in 0..M {
+            m[i][j] += b[i][j];
+        }
+    }
+    m
+}
+
+pub(crate) fn sub<const N: usize, const M: usize>(
+    a: &Matrix<N, M>,
+    b: &Matrix<N, M>,
+) -> Matrix<N, M> {
+    let mut m = *a;
+    for i in 0..N {
+        for j in 0..M {
+            m[i][j] -= b[i][j];
+        }
+    }
+    m
+}
+
+/// Inverse by Gauss-Jordan elimination with partial pivoting.
+pub(crate) fn invert<const N: usize>(a: &Matrix<N, N>) -> Option<Matrix<N, N>> {
+    let mut a = *a;
+    let mut inv = identity::<N>();
+    for col in 0..N {
+        let pivot =
+            (col..N).max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let p = a[col][col];
+        for j in 0..N {
+            a[col][j] /= p;
+            inv[col][j] /= p;
+        }
+        for i in 0..N {
+            if i != col {
+                let f = a[i][col];
+                for j in 0..N {
+                    a[i][j] -= f * a[col][j];
+                    inv[i][j] -= f * inv[col][j];
+                }
+            }
+        }
+    }
+    Some(inv)
+}
+
+/// Solves `a * x = b`.
+pub(crate) fn solve<const N: usize>(a: &Matrix<N, N>, b: &[f64; N]) -> Option<[f64; N]> {
+    let inv = invert(a)?;
+    let mut x = [0.0; N];
+    for (i, row) in inv.iter().enumerate() {
+        x[i] = row.iter().zip(b).map(|(m, v)| m * v).sum();
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse() {
+        let a: Matrix<3, 3> = [[4.0, 1.0, 0.0], [1.0, 3.0, 1.0], [0.0, 1.0, 2.0]];
+        let product = mul(&a, &invert(&a).unwrap());
+        for (i, row) in product.iter().enumerate() {
+            for (j, v) in row.iter().enumerate() {
+                assert!((v - if i == j { 1.0 } else { 0.0 }).abs() < 1e-12);
+            }
+        }
+        assert!(invert(&[[1.0, 2.0], [2.0, 4.0]]).is_none());
+        assert_eq!(transpose(&[[1.0, 2.0, 3.0]]), [[1.0], [2.0], [3.0]]);
+    }
+}
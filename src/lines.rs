@@ -0,0 +1,395 @@
+use std::f64::consts::PI;
+
+use crate::transform::{normalize_angle, Point};
+
+/// A line segment fitted on scan points.
+///
+/// The supporting line is in normal form : `x * cos(alpha) + y * sin(alpha) = rho`,
+/// with `rho >= 0`.
+#[derive(Clone, Debug)]
+pub struct LineSegment {
+    pub start: Point,
+    pub end: Point,
+    pub alpha: f64,
+    pub rho: f64,
+    /// Covariance of `(alpha, rho)`.
+    pub covariance: [[f64; 2]; 2],
+    /// Indices of the points supporting the segment.
+    pub inliers: Vec<usize>,
+}
+
+impl LineSegment {
+    pub fn length(&self) -> f64 {
+        self.start.distance(&self.end)
+    }
+
+    /// Unit vector along the line.
+    pub fn direction(&self) -> Point {
+        Point::new(-self.alpha.sin(), self.alpha.cos())
+    }
+
+    /// Signed distance from a point to the supporting line.
+    pub fn distance_to_line(&self, p: &Point) -> f64 {
+        p.x * self.alpha.cos() + p.y * self.alpha.sin() - self.rho
+    }
+
+    /// Distance from a point to the segment.
+    pub fn distance_to_segment(&self, p: &Point) -> f64 {
+        segment_distance(&self.start, &self.end, p)
+    }
+
+    /// Intersection of the supporting lines of two segments.
+    pub fn intersection(&self, other: &LineSegment) -> Option<Point> {
+        let (c1, s1) = (self.alpha.cos(), self.alpha.sin());
+        let (c2, s2) = (other.alpha.cos(), other.alpha.sin());
+        let det = c1 * s2 - s1 * c2;
+        if det.abs() < 1e-9 {
+            return None;
+        }
+        Some(Point::new(
+            (self.rho * s2 - other.rho * s1) / det,
+            (c1 * other.rho - c2 * self.rho) / det,
+        ))
+    }
+}
+
+/// Distance from `p` to the segment `[a, b]`.
+pub fn segment_distance(a: &Point, b: &Point, p: &Point) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let len2 = dx * dx + dy * dy;
+    if len2 == 0.0 {
+        return a.distance(p);
+    }
+    let t = (((p.x - a.x) * dx + (p.y - a.y) * dy) / len2).clamp(0.0, 1.0);
+    Point::new(a.x + t * dx, a.y + t * dy).distance(p)
+}
+
+/// Fits a line on the points designated by `indices` (total least squares).
+///
+/// Returns `None` with fewer than 2 points.
+pub fn fit_line(points: &[Point], indices: Vec<usize>) -> Option<LineSegment> {
+    if indices.len() < 2 {
+        return None;
+    }
+    let n = indices.len() as f64;
+    let xm = indices.iter().map(|&i| points[i].x).sum::<f64>() / n;
+    let ym = indices.iter().map(|&i| points[i].y).sum::<f64>() / n;
+    let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+    for &i in &indices {
+        let (dx, dy) = (points[i].x - xm, points[i].y - ym);
+        sxx += dx * dx;
+        syy += dy * dy;
+        sxy += dx * dy;
+    }
+
+    let mut alpha = 0.5 * (-2.0 * sxy).atan2(syy - sxx);
+    let mut rho = xm * alpha.cos() + ym * alpha.sin();
+    if rho < 0.0 {
+        rho = -rho;
+        alpha += PI;
+    }
+    let alpha = normalize_angle(alpha);
+
+    // position along the line, relative to the centroid
+    let dir = Point::new(-alpha.sin(), alpha.cos());
+    let along = |p: &Point| (p.x - xm) * dir.x + (p.y - ym) * dir.y;
+    let residuals = indices
+        .iter()
+        .map(|&i| (points[i].x * alpha.cos() + points[i].y * alpha.sin() - rho).powi(2))
+        .sum::<f64>();
+    let sigma2 = if indices.len() > 2 {
+        residuals / (n - 2.0)
+    } else {
+        0.0
+    };
+    let spread = indices
+        .iter()
+        .map(|&i| along(&points[i]).powi(2))
+        .sum::<f64>();
+    let var_alpha = if spread > 0.0 { sigma2 / spread } else { 0.0 };
+    let tc = -xm * alpha.sin() + ym * alpha.cos();
+    let covariance = [
+        [var_alpha, tc * var_alpha],
+        [tc * var_alpha, sigma2 / n + tc * tc * var_alpha],
+    ];
+
+    let (tmin, tmax) = indices
+        .iter()
+        .map(|&i| along(&points[i]))
+        .fold((f64::MAX, f64::MIN), |(lo, hi), t| (lo.min(t), hi.max(t)));
+    let project = |t: f64| {
+        let foot = Point::new(rho * alpha.cos(), rho * alpha.sin());
+        let tf = along(&foot);
+        Point::new(foot.x + (t - tf) * dir.x, foot.y + (t - tf) * dir.y)
+    };
+
+    Some(LineSegment {
+        start: project(tmin),
+        end: project(tmax),
+        alpha,
+        rho,
+        covariance,
+        inliers: indices,
+    })
+}
+
+/// Splits consecutive points into runs separated by jumps larger than `max_gap`.
+fn split_gaps(points: &[Point], indices: &[usize], max_gap: f64) -> Vec<Vec<usize>> {
+    let mut runs: Vec<Vec<usize>> = vec![];
+    for &i in indices {
+        match runs.last_mut() {
+            Some(run) if points[*run.last().unwrap()].distance(&points[i]) <= max_gap => {
+                run.push(i)
+            }
+            _ => runs.push(vec![i]),
+        }
+    }
+    runs
+}
+
+/// Parameters of the split-and-merge extractor.
+#[derive(Copy, Clone, Debug)]
+pub struct SplitAndMergeConfig {
+    /// A segment is split when a point lies farther than this from it (meters).
+    pub split_threshold: f64,
+    /// Adjacent segments are merged when their union still fits within this (meters).
+    pub merge_threshold: f64,
+    /// Largest distance between consecutive points of a segment (meters).
+    pub max_gap: f64,
+    pub min_points: usize,
+    pub min_length: f64,
+}
+
+impl Default for SplitAndMergeConfig {
+    fn default() -> SplitAndMergeConfig {
+        SplitAndMergeConfig {
+            split_threshold: 0.03,
+            merge_threshold: 0.03,
+            max_gap: 0.2,
+            min_points: 5,
+            min_length: 0.1,
+        }
+    }
+}
+
+fn max_deviation(points: &[Point], indices: &[usize]) -> (usize, f64) {
+    let (a, b) = (&points[indices[0]], &points[indices[indices.len() - 1]]);
+    indices
+        .iter()
+        .enumerate()
+        .map(|(k, &i)| (k, segment_distance(a, b, &points[i])))
+        .fold(
+            (0, 0.0),
+            |best, cur| if cur.1 > best.1 { cur } else { best },
+        )
+}
+
+fn split(
+    points: &[Point],
+    indices: &[usize],
+    config: &SplitAndMergeConfig,
+    out: &mut Vec<Vec<usize>>,
+) {
+    if indices.len() < 3 {
+        out.push(indices.to_vec());
+        return;
+    }
+    let (k, d) = max_deviation(points, indices);
+    if d > config.split_threshold && k > 0 && k < indices.len() - 1 {
+        split(points, &indices[..=k], config, out);
+        split(points, &indices[k..], config, out);
+    } else {
+        out.push(indices.to_vec());
+    }
+}
+
+/// Extracts line segments from points ordered along the scan, with split-and-merge.
+pub fn split_and_merge(points: &[Point], config: &SplitAndMergeConfig) -> Vec<LineSegment> {
+    let all = (0..points.len()).collect::<Vec<_>>();
+    let mut groups = vec![];
+    for run in split_gaps(points, &all, config.max_gap) {
+        let mut pieces = vec![];
+        split(points, &run, config, &mut pieces);
+
+        // merge adjacent pieces (they share their boundary point)
+        let mut merged: Vec<Vec<usize>> = vec![];
+        for piece in pieces {
+            if let Some(prev) = merged.last_mut() {
+                let mut candidate = prev.clone();
+                candidate.extend(piece.iter().skip(1));
+                if max_deviation(points, &candidate).1 <= config.merge_threshold {
+                    *prev = candidate;
+                    continue;
+                }
+            }
+            merged.push(piece);
+        }
+        groups.extend(merged);
+    }
+
+    groups
+        .into_iter()
+        .filter(|g| g.len() >= config.min_points)
+        .filter_map(|g| fit_line(points, g))
+        .filter(|s| s.length() >= config.min_length)
+        .collect()
+}
+
+/// Parameters of the incremental RANSAC extractor.
+#[derive(Copy, Clone, Debug)]
+pub struct RansacConfig {
+    /// Largest distance from a point to a line to count as an inlier (meters).
+    pub threshold: f64,
+    pub iterations: usize,
+    pub min_points: usize,
+    pub max_lines: usize,
+    /// Largest distance between consecutive inliers of a segment (meters).
+    pub max_gap: f64,
+    pub seed: u64,
+}
+
+impl Default for RansacConfig {
+    fn default() -> RansacConfig {
+        RansacConfig {
+            threshold: 0.02,
+            iterations: 200,
+            min_points: 8,
+            max_lines: 10,
+            max_gap: 0.2,
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+}
+
+/// xorshift64* generator, good enough to draw RANSAC samples.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 33) as usize % n
+    }
+}
+
+/// Extracts line segments with incremental RANSAC : the best line is found,
+/// its inliers are removed, and the search starts again on the remaining points.
+///
+/// Points need not be ordered.
+pub fn ransac(points: &[Point], config: &RansacConfig) -> Vec<LineSegment> {
+    let mut rng = Rng::new(config.seed);
+    let mut remaining = (0..points.len()).collect::<Vec<_>>();
+    let mut segments = vec![];
+    let min_points = config.min_points.max(2);
+
+    while remaining.len() >= min_points && segments.len() < config.max_lines {
+        let mut best: Vec<usize> = vec![];
+        for _ in 0..config.iterations {
+            let a = points[remaining[rng.below(remaining.len())]];
+            let b = points[remaining[rng.below(remaining.len())]];
+            let len = a.distance(&b);
+            if len < 1e-6 {
+                continue;
+            }
+            let (nx, ny) = ((b.y - a.y) / len, (a.x - b.x) / len);
+            let inliers = remaining
+                .iter()
+                .copied()
+                .filter(|&i| {
+                    ((points[i].x - a.x) * nx + (points[i].y - a.y) * ny).abs() <= config.threshold
+                })
+                .collect::<Vec<_>>();
+            if inliers.len() > best.len() {
+                best = inliers;
+            }
+        }
+        if best.len() < min_points {
+            break;
+        }
+
+        // refine on the consensus set, then keep the points close to the refined line
+        let line = match fit_line(points, best) {
+            Some(line) => line,
+            None => break,
+        };
+        let mut inliers = remaining
+            .iter()
+            .copied()
+            .filter(|&i| line.distance_to_line(&points[i]).abs() <= config.threshold)
+            .collect::<Vec<_>>();
+        if inliers.len() < min_points {
+            inliers = line.inliers.clone();
+        }
+        remaining.retain(|i| !inliers.contains(i));
+
+        // collinear but distinct walls give separate segments
+        let dir = line.direction();
+        inliers.sort_by(|&i, &j| {
+            let ti = points[i].x * dir.x + points[i].y * dir.y;
+            let tj = points[j].x * dir.x + points[j].y * dir.y;
+            ti.partial_cmp(&tj).unwrap()
+        });
+        for run in split_gaps(points, &inliers, config.max_gap) {
+            if run.len() >= min_points {
+                segments.extend(fit_line(points, run));
+            }
+        }
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two walls meeting at (1, 1) : `y = 1` then `x = 1`, with a few mm of noise.
+    fn corner() -> Vec<Point> {
+        let noise = |i: usize| if i % 2 == 0 { 0.004 } else { -0.004 };
+        let first = (0..40).map(|i| Point::new(-1.0 + 0.05 * i as f64, 1.0 + noise(i)));
+        let second = (0..=40).map(|i| Point::new(1.0 + noise(i), 1.0 - 0.05 * i as f64));
+        first.chain(second).collect()
+    }
+
+    fn assert_walls(mut segments: Vec<LineSegment>) {
+        assert_eq!(segments.len(), 2);
+        segments.sort_by(|a, b| a.alpha.partial_cmp(&b.alpha).unwrap());
+        assert!(segments[0].alpha.abs() < 0.01 && (segments[0].rho - 1.0).abs() < 0.01);
+        assert!((segments[1].alpha - PI / 2.0).abs() < 0.01);
+        assert!((segments[1].rho - 1.0).abs() < 0.01);
+        let corner = segments[0].intersection(&segments[1]).unwrap();
+        assert!(corner.distance(&Point::new(1.0, 1.0)) < 0.01);
+    }
+
+    #[test]
+    fn line_fit() {
+        let (alpha, rho) = (0.3f64, 2.0);
+        let foot = Point::new(rho * alpha.cos(), rho * alpha.sin());
+        let points = (0..10)
+            .map(|i| {
+                let t = i as f64 * 0.1 - 0.3;
+                Point::new(foot.x - t * alpha.sin(), foot.y + t * alpha.cos())
+            })
+            .collect::<Vec<_>>();
+        let line = fit_line(&points, (0..10).collect()).unwrap();
+        assert!((line.alpha - alpha).abs() < 1e-9 && (line.rho - rho).abs() < 1e-9);
+        assert!((line.length() - 0.9).abs() < 1e-9);
+        assert!(line.covariance[0][0].abs() < 1e-12);
+        assert!(fit_line(&points, vec![0]).is_none());
+    }
+
+    #[test]
+    fn split_and_merge_corner() {
+        assert_walls(split_and_merge(&corner(), &SplitAndMergeConfig::default()));
+    }
+
+    #[test]
+    fn ransac_corner() {
+        assert_walls(ransac(&corner(), &RansacConfig::default()));
+    }
+}
@@ -0,0 +1,424 @@
+use crate::linalg::{add, invert, mul, transpose, Matrix};
+use crate::transform::{fit_rigid_transform, normalize_angle, Point, Pose};
+
+/// Known beacon positions, in the world frame.
+#[derive(Clone, Debug, Default)]
+pub struct BeaconMap {
+    pub beacons: Vec<Point>,
+}
+
+impl BeaconMap {
+    pub fn new(beacons: Vec<Point>) -> BeaconMap {
+        BeaconMap { beacons }
+    }
+}
+
+/// A pose with its uncertainty.
+#[derive(Clone, Debug)]
+pub struct PoseEstimate {
+    pub pose: Pose,
+    /// Covariance of `(x, y, theta)`.
+    pub covariance: [[f64; 3]; 3],
+    /// Pairs of (detection index, map beacon index) used for the estimate.
+    pub associations: Vec<(usize, usize)>,
+    /// RMS distance between the detections and their beacon, in meters.
+    pub residual: f64,
+}
+
+impl PoseEstimate {
+    /// Wraps an odometry pose to be used as a prior.
+    pub fn prior(pose: Pose, sigma_xy: f64, sigma_theta: f64) -> PoseEstimate {
+        PoseEstimate {
+            pose,
+            covariance: [
+                [sigma_xy * sigma_xy, 0.0, 0.0],
+                [0.0, sigma_xy * sigma_xy, 0.0],
+                [0.0, 0.0, sigma_theta * sigma_theta],
+            ],
+            associations: vec![],
+            residual: 0.0,
+        }
+    }
+}
+
+/// Parameters of the beacon localizer.
+#[derive(Copy, Clone, Debug)]
+pub struct LocalizationConfig {
+    /// Standard deviation of the beacon detections, in meters.
+    pub sigma: f64,
+    /// Largest distance between a detection and a beacon to associate them, in meters.
+    pub gate: f64,
+    /// Associations whose residual exceeds this are rejected as outliers, in meters.
+    pub max_residual: f64,
+    /// Tolerance when matching inter-beacon distances without prior, in meters.
+    pub distance_tolerance: f64,
+    /// Least number of associated beacons to produce an estimate.
+    pub min_beacons: usize,
+}
+
+impl Default for LocalizationConfig {
+    fn default() -> LocalizationConfig {
+        LocalizationConfig {
+            sigma: 0.02,
+            gate: 0.3,
+            max_residual: 0.08,
+            distance_tolerance: 0.08,
+            min_beacons: 2,
+        }
+    }
+}
+
+/// Absolute pose estimation from beacon detections.
+pub struct Localizer {
+    pub map: BeaconMap,
+    pub config: LocalizationConfig,
+}
+
+impl Localizer {
+    pub fn new(map: BeaconMap, config: LocalizationConfig) -> Localizer {
+        Localizer { map, config }
+    }
+
+    /// Estimates the robot pose from beacon centers detected in the robot frame.
+    ///
+    /// With a `prior` (e.g. the last estimate moved by odometry), detections are
+    /// associated to the closest beacon and the prior is fused in the least
+    /// squares. Without prior, associations are found by matching the distances
+    /// between detections with the distances between beacons.
+    pub fn estimate(
+        &self,
+        detections: &[Point],
+        prior: Option<&PoseEstimate>,
+    ) -> Option<PoseEstimate> {
+        let mut associations = match prior {
+            Some(prior) => self.associate(detections, &prior.pose),
+            None => self.search(detections)?,
+        };
+
+        loop {
+            if associations.len() < self.config.min_beacons.max(1) {
+                return None;
+            }
+            let initial = prior
+                .map(|p| p.pose)
+                .or_else(|| self.align(detections, &associations))?;
+            let estimate = self.solve(detections, associations.clone(), initial, prior)?;
+
+            // drop the worst association while it is an outlier
+            let worst = associations
+                .iter()
+                .enumerate()
+                .map(|(k, &(d, b))| {
+                    (
+                        k,
+                        estimate
+                            .pose
+                            .transform(detections[d])
+                            .distance(&self.map.beacons[b]),
+                    )
+                })
+                .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())?;
+            if worst.1 <= self.config.max_residual {
+                return Some(estimate);
+            }
+            associations.remove(worst.0);
+        }
+    }
+
+    /// Nearest neighbor association around `pose`, each beacon used at most once.
+    fn associate(&self, detections: &[Point], pose: &Pose) -> Vec<(usize, usize)> {
+        let mut candidates = vec![];
+        for (d, p) in detections.iter().enumerate() {
+            let world = pose.transform(*p);
+            for (b, beacon) in self.map.beacons.iter().enumerate() {
+                let dist = world.distance(beacon);
+                if dist <= self.config.gate {
+                    candidates.push((dist, d, b));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut associations: Vec<(usize, usize)> = vec![];
+        for (_, d, b) in candidates {
+            if associations.iter().all(|&(ad, ab)| ad != d && ab != b) {
+                associations.push((d, b));
+            }
+        }
+        associations
+    }
+
+    /// Association without prior : every pair of detections is matched against
+    /// every pair of beacons with the same distance, and the hypothesis
+    /// explaining the most detections wins.
+    fn search(&self, detections: &[Point]) -> Option<Vec<(usize, usize)>> {
+        let mut best: Option<(Vec<(usize, usize)>, f64)> = None;
+        for i in 0..detections.len() {
+            for j in (i + 1)..detections.len() {
+                let dd = detections[i].distance(&detections[j]);
+                for a in 0..self.map.beacons.len() {
+                    for b in 0..self.map.beacons.len() {
+                        if a == b {
+                            continue;
+                        }
+                        let (ma, mb) = (self.map.beacons[a], self.map.beacons[b]);
+                        if (ma.distance(&mb) - dd).abs() > self.config.distance_tolerance {
+                            continue;
+                        }
+                        let pose =
+                            match fit_rigid_transform(&[detections[i], detections[j]], &[ma, mb]) {
+                                Some(pose) => pose,
+                                None => continue,
+                            };
+                        let associations = self.associate(detections, &pose);
+                        let error = associations
+                            .iter()
+                            .map(|&(d, b)| {
+                                pose.transform(detections[d]).distance(&self.map.beacons[b])
+                            })
+                            .sum::<f64>();
+                        let better = match &best {
+                            None => true,
+                            Some((assoc, err)) => {
+                                associations.len() > assoc.len()
+                                    || (associations.len() == assoc.len() && error < *err)
+                            }
+                        };
+                        if better {
+                            best = Some((associations, error));
+                        }
+                    }
+                }
+            }
+        }
+        best.map(|(associations, _)| associations)
+    }
+
+    /// Initial guess from the associations alone.
+    fn align(&self, detections: &[Point], associations: &[(usize, usize)]) -> Option<Pose> {
+        let from = associations
+            .iter()
+            .map(|&(d, _)| detections[d])
+            .collect::<Vec<_>>();
+        let to = associations
+            .iter()
+            .map(|&(_, b)| self.map.beacons[b])
+            .collect::<Vec<_>>();
+        fit_rigid_transform(&from, &to)
+    }
+
+    /// Gauss-Newton refinement of the pose, fusing the prior if any.
+    fn solve(
+        &self,
+        detections: &[Point],
+        associations: Vec<(usize, usize)>,
+        initial: Pose,
+        prior: Option<&PoseEstimate>,
+    ) -> Option<PoseEstimate> {
+        let w = 1.0 / (self.config.sigma * self.config.sigma);
+        let prior_info = match prior {
+            Some(p) => Some(invert(&p.covariance)?),
+            None => None,
+        };
+        let mut pose = initial;
+        let mut info: Matrix<3, 3> = [[0.0; 3]; 3];
+
+        for _ in 0..10 {
+            let mut h: Matrix<3, 3> = [[0.0; 3]; 3];
+            let mut g = [0.0; 3];
+            let (s, c) = pose.theta.sin_cos();
+            for &(d, b) in &associations {
+                let p = detections[d];
+                let e = pose.transform(p);
+                let beacon = self.map.beacons[b];
+                let r = [e.x - beacon.x, e.y - beacon.y];
+                let j: Matrix<2, 3> = [
+                    [1.0, 0.0, -s * p.x - c * p.y],
+                    [0.0, 1.0, c * p.x - s * p.y],
+                ];
+                let jt = transpose(&j);
+                h = add(&h, &mul(&jt, &j).map(|row| row.map(|v| v * w)));
+                for (k, gk) in g.iter_mut().enumerate() {
+                    *gk += w * (jt[k][0] * r[0] + jt[k][1] * r[1]);
+                }
+            }
+            if let (Some(prior), Some(pi)) = (prior, &prior_info) {
+                let r = [
+                    pose.x - prior.pose.x,
+                    pose.y - prior.pose.y,
+                    normalize_angle(pose.theta - prior.pose.theta),
+                ];
+                h = add(&h, pi);
+                for (k, gk) in g.iter_mut().enumerate() {
+                    *gk += (0..3).map(|m| pi[k][m] * r[m]).sum::<f64>();
+                }
+            }
+
+            info = h;
+            let hi = invert(&h)?;
+            let dx = (0..3)
+                .map(|k| -(0..3).map(|m| hi[k][m] * g[m]).sum::<f64>())
+                .collect::<Vec<_>>();
+            pose = Pose::new(
+                pose.x + dx[0],
+                pose.y + dx[1],
+                normalize_angle(pose.theta + dx[2]),
+            );
+            if dx.iter().map(|v| v.abs()).sum::<f64>() < 1e-9 {
+                break;
+            }
+        }
+
+        let residual = (associations
+            .iter()
+            .map(|&(d, b)| {
+                pose.transform(detections[d])
+                    .distance(&self.map.beacons[b])
+                    .powi(2)
+            })
+            .sum::<f64>()
+            / associations.len() as f64)
+            .sqrt();
+
+        Some(PoseEstimate {
+            pose,
+            covariance: invert(&info)?,
+            associations,
+            residual,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beacons::{BeaconConfig, BeaconDetector};
+    use crate::lidar::Sample;
+    use crate::transform::{Convention, Mount};
+
+    const RADIUS: f64 = 0.04;
+
+    fn map() -> BeaconMap {
+        BeaconMap::new(vec![
+            Point::new(-0.1, -0.1),
+            Point::new(-0.1, 2.1),
+            Point::new(3.1, 1.0),
+        ])
+    }
+
+    /// Scan of the beacons seen from `pose`, 0.25° steps, nothing else in range.
+    fn synthetic_scan(map: &BeaconMap, pose: &Pose) -> Vec<Option<Sample>> {
+        let centers = map
+            .beacons
+            .iter()
+            .map(|b| pose.inverse_transform(*b))
+            .collect::<Vec<_>>();
+        (0..1440)
+            .map(|i| {
+                let angle = normalize_angle((i as f64 * 0.25).to_radians());
+                let (dy, dx) = angle.sin_cos();
+                centers
+                    .iter()
+                    .filter_map(|c| {
+                        let along = dx * c.x + dy * c.y;
+                        let across = (c.x * c.x + c.y * c.y) - along * along;
+                        let t = along - (RADIUS * RADIUS - across).sqrt();
+                        Some(t).filter(|t| t.is_finite() && *t > 0.0)
+                    })
+                    .min_by(|a, b| a.total_cmp(b))
+                    .map(|t| Sample {
+                        angle,
+                        distance: (t * 1000.0).round() as u16,
+                        quality: 200,
+                    })
+            })
+            .collect()
+    }
+
+    fn detections(map: &BeaconMap, pose: &Pose) -> Vec<Point> {
+        let detector = BeaconDetector::new(Mount::new(Convention::ROBOT), BeaconConfig::default());
+        detector
+            .detect(&synthetic_scan(map, pose))
+            .iter()
+            .map(|b| b.center)
+            .collect()
+    }
+
+    fn assert_pose(estimate: &PoseEstimate, pose: &Pose) {
+        let p = estimate.pose;
+        assert!(
+            (p.x - pose.x).abs() < 0.01
+                && (p.y - pose.y).abs() < 0.01
+                && normalize_angle(p.theta - pose.theta).abs() < 0.01,
+            "estimated {} instead of {}",
+            p,
+            pose
+        );
+    }
+
+    #[test]
+    fn recovers_pose_from_synthetic_scan() {
+        let pose = Pose::new(1.2, 0.8, 0.4);
+        let detections = detections(&map(), &pose);
+        assert_eq!(detections.len(), 3);
+        let localizer = Localizer::new(map(), LocalizationConfig::default());
+
+        let estimate = localizer.estimate(&detections, None).unwrap();
+        assert_pose(&estimate, &pose);
+        assert_eq!(estimate.associations.len(), 3);
+        assert!(estimate.residual < 0.01);
+
+        // from an odometry prior a few centimeters off
+        let prior = PoseEstimate::prior(Pose::new(1.25, 0.75, 0.45), 0.1, 0.1);
+        let estimate = localizer.estimate(&detections, Some(&prior)).unwrap();
+        assert_pose(&estimate, &pose);
+        assert!(estimate.covariance[0][0] > 0.0 && estimate.covariance[0][0] < 0.01);
+    }
+
+    #[test]
+    fn rejects_outliers() {
+        // with three beacons, an outlier can't be told apart from the others
+        let mut map = map();
+        map.beacons.push(Point::new(3.1, -0.1));
+        let pose = Pose::new(2.0, 1.5, -2.0);
+        let mut detections = detections(&map, &pose);
+        assert_eq!(detections.len(), 4);
+        // a reflection close to a beacon, within the association gate
+        detections[0] = Point::new(detections[0].x + 0.25, detections[0].y);
+        let localizer = Localizer::new(map, LocalizationConfig::default());
+        let prior = PoseEstimate::prior(pose, 0.05, 0.05);
+        let estimate = localizer.estimate(&detections, Some(&prior)).unwrap();
+        assert_pose(&estimate, &pose);
+        assert_eq!(estimate.associations.len(), 3);
+        assert!(estimate.associations.iter().all(|&(d, _)| d != 0));
+    }
+
+    #[test]
+    fn too_few_beacons() {
+        let pose = Pose::new(1.2, 0.8, 0.4);
+        let detections = detections(&map(), &pose);
+        let localizer = Localizer::new(map(), LocalizationConfig::default());
+        assert!(localizer.estimate(&[], None).is_none());
+        assert!(localizer.estimate(&detections[..1], None).is_none());
+
+        let config = LocalizationConfig {
+            min_beacons: 3,
+            ..LocalizationConfig::default()
+        };
+        let localizer = Localizer::new(map(), config);
+        assert!(localizer.estimate(&detections[..2], None).is_none());
+        assert!(localizer.estimate(&detections, None).is_some());
+    }
+
+    #[test]
+    fn degenerate_beacons() {
+        // two beacons at the same place don't fix the orientation
+        let map = BeaconMap::new(vec![Point::new(1.0, 1.0), Point::new(1.0, 1.0)]);
+        let localizer = Localizer::new(map.clone(), LocalizationConfig::default());
+        let detections = [Point::new(0.5, 0.0), Point::new(0.5, 0.0)];
+        assert!(localizer.estimate(&detections, None).is_none());
+        // a prior does
+        let prior = PoseEstimate::prior(Pose::new(0.5, 1.0, 0.0), 0.1, 0.1);
+        assert!(localizer.estimate(&detections, Some(&prior)).is_some());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::lines::segment_distance;
+use crate::transform::Point;
+
+/// A closed polygon, vertices in order.
+#[derive(Clone, Debug, Default)]
+pub struct Polygon {
+    pub vertices: Vec<Point>,
+}
+
+impl Polygon {
+    pub fn new(vertices: Vec<Point>) -> Polygon {
+        Polygon { vertices }
+    }
+
+    /// Axis aligned rectangle from two opposite corners.
+    pub fn rectangle(a: Point, b: Point) -> Polygon {
+        Polygon::new(vec![
+            Point::new(a.x, a.y),
+            Point::new(b.x, a.y),
+            Point::new(b.x, b.y),
+            Point::new(a.x, b.y),
+        ])
+    }
+
+    /// Iterates over the edges of the polygon.
+    pub fn edges(&self) -> impl Iterator<Item = (&Point, &Point)> {
+        let n = self.vertices.len();
+        (0..n).map(move |i| (&self.vertices[i], &self.vertices[(i + 1) % n]))
+    }
+
+    /// Even-odd rule point in polygon test.
+    pub fn contains(&self, p: &Point) -> bool {
+        let mut inside = false;
+        for (a, b) in self.edges() {
+            if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+
+    /// Distance from a point to the polygon, 0 inside.
+    pub fn distance(&self, p: &Point) -> f64 {
+        if self.contains(p) {
+            return 0.0;
+        }
+        self.edges()
+            .map(|(a, b)| segment_distance(a, b, p))
+            .fold(f64::MAX, f64::min)
+    }
+}
+
+/// Static elements of a known environment (walls, fixed obstacles), in the world frame.
+#[derive(Clone, Debug, Default)]
+pub struct StaticMap {
+    pub segments: Vec<(Point, Point)>,
+    pub polygons: Vec<Polygon>,
+    /// Playing area. Anything outside is considered static.
+    pub bounds: Option<Polygon>,
+}
+
+impl StaticMap {
+    pub fn new() -> StaticMap {
+        StaticMap::default()
+    }
+
+    pub fn with_segment(mut self, a: Point, b: Point) -> StaticMap {
+        self.segments.push((a, b));
+        self
+    }
+
+    pub fn with_polygon(mut self, polygon: Polygon) -> StaticMap {
+        self.polygons.push(polygon);
+        self
+    }
+
+    pub fn with_bounds(mut self, bounds: Polygon) -> StaticMap {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    /// Distance from a point to the closest static element.
+    pub fn distance(&self, p: &Point) -> f64 {
+        let segments = self.segments.iter().map(|(a, b)| segment_distance(a, b, p));
+        let polygons = self.polygons.iter().map(|poly| poly.distance(p));
+        let bounds = self.bounds.iter().map(|b| {
+            b.edges()
+                .map(|(a, c)| segment_distance(a, c, p))
+                .fold(f64::MAX, f64::min)
+        });
+        segments
+            .chain(polygons)
+            .chain(bounds)
+            .fold(f64::MAX, f64::min)
+    }
+
+    /// `true` if the point is out of the playing area.
+    pub fn is_out_of_bounds(&self, p: &Point) -> bool {
+        self.bounds.as_ref().is_some_and(|b| !b.contains(p))
+    }
+
+    /// `true` if the point is explained by the map, within `tolerance` meters.
+    pub fn explains(&self, p: &Point, tolerance: f64) -> bool {
+        self.is_out_of_bounds(p) || self.distance(p) <= tolerance
+    }
+}
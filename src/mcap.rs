@@ -0,0 +1,704 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+
+use crate::lidar::{RawPacket, Sample};
+use crate::record::{convention_fields, parse_convention, TimedScan};
+use crate::transform::{Convention, Mount};
+
+const MAGIC: &[u8; 8] = b"\x89MCAP0\r\n";
+
+const OP_HEADER: u8 = 0x01;
+const OP_FOOTER: u8 = 0x02;
+const OP_SCHEMA: u8 = 0x03;
+const OP_CHANNEL: u8 = 0x04;
+const OP_MESSAGE: u8 = 0x05;
+const OP_DATA_END: u8 = 0x0f;
+
+/// Topic of the `foxglove.LaserScan` messages.
+pub const SCAN_TOPIC: &str = "/scan";
+/// Topic of the raw packets, as read from the sensor.
+pub const PACKET_TOPIC: &str = "/packets";
+
+const SCAN_CHANNEL: u16 = 1;
+const PACKET_CHANNEL: u16 = 2;
+const LASER_SCAN_SCHEMA_ID: u16 = 1;
+
+/// JSON schema of the `foxglove.LaserScan` messages.
+pub const LASER_SCAN_SCHEMA: &str = r#"{"title":"foxglove.LaserScan","type":"object","properties":{"timestamp":{"type":"object","properties":{"sec":{"type":"integer","minimum":0},"nsec":{"type":"integer","minimum":0,"maximum":999999999}}},"frame_id":{"type":"string"},"pose":{"type":"object","properties":{"position":{"type":"object","properties":{"x":{"type":"number"},"y":{"type":"number"},"z":{"type":"number"}}},"orientation":{"type":"object","properties":{"x":{"type":"number"},"y":{"type":"number"},"z":{"type":"number"},"w":{"type":"number"}}}}},"start_angle":{"type":"number"},"end_angle":{"type":"number"},"ranges":{"type":"array","items":{"type":["number","null"]}},"intensities":{"type":"array","items":{"type":["number","null"]}}}}"#;
+
+/// A scan as a `foxglove.LaserScan` JSON message, in the sensor frame named
+/// `frame_id` (counter-clockwise, meters).
+///
+/// The samples are binned at regular angles, `scan.len()` bins from the
+/// smallest to the largest sensor angle of the scan ; the closest sample is kept
+/// per bin and empty bins are `null`.
+pub fn laser_scan_json(
+    time: SystemTime,
+    mount: &Mount,
+    frame_id: &str,
+    scan: &[Option<Sample>],
+) -> String {
+    let angles = scan
+        .iter()
+        .flatten()
+        .map(|s| (mount.convention.to_sensor_angle(s.angle), s))
+        .collect::<Vec<_>>();
+    let start = angles.iter().map(|a| a.0).fold(f64::INFINITY, f64::min);
+    let end = angles.iter().map(|a| a.0).fold(f64::NEG_INFINITY, f64::max);
+    let (start, end) = if angles.is_empty() {
+        (0.0, 0.0)
+    } else {
+        (start, end)
+    };
+    let n = scan.len().max(1);
+    let step = if n > 1 {
+        (end - start) / (n - 1) as f64
+    } else {
+        0.0
+    };
+    let mut bins: Vec<Option<&Sample>> = vec![None; n];
+    for (a, s) in angles {
+        let i = if step > 0.0 {
+            (((a - start) / step).round() as usize).min(n - 1)
+        } else {
+            0
+        };
+        if bins[i].map_or(true, |b| s.distance < b.distance) {
+            bins[i] = Some(s);
+        }
+    }
+    let ranges = bins
+        .iter()
+        .map(|b| {
+            b.map_or("null".to_string(), |s| {
+                format!("{:.3}", s.distance as f64 / 1000.0)
+            })
+        })
+        .collect::<Vec<_>>();
+    let intensities = bins
+        .iter()
+        .map(|b| b.map_or("null".to_string(), |s| s.quality.to_string()))
+        .collect::<Vec<_>>();
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        concat!(
+            "{{\"timestamp\":{{\"sec\":{},\"nsec\":{}}},\"frame_id\":\"{}\",",
+            "\"pose\":{{\"position\":{{\"x\":0,\"y\":0,\"z\":0}},\"orientation\":{{\"x\":0,\"y\":0,\"z\":0,\"w\":1}}}},",
+            "\"start_angle\":{},\"end_angle\":{},\"ranges\":[{}],\"intensities\":[{}]}}"
+        ),
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos(),
+        escape_json(frame_id),
+        start,
+        end,
+        ranges.join(","),
+        intensities.join(",")
+    )
+}
+
+pub(crate) fn escape_json(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Parses a `foxglove.LaserScan` JSON message back into a scan of `convention`,
+/// with one sample per range. The time is in seconds since the UNIX epoch.
+fn parse_laser_scan(
+    json: &str,
+    convention: Convention,
+    patterns: &ScanPatterns,
+) -> Result<TimedScan, Box<dyn Error>> {
+    let number = |re: &Regex| -> Result<f64, Box<dyn Error>> {
+        let captures = re.captures(json).ok_or("invalid LaserScan message")?;
+        Ok(captures[1].parse()?)
+    };
+    let list = |re: &Regex| -> Vec<Option<f64>> {
+        re.captures(json)
+            .map(|c| c[1].split(',').map(|v| v.trim().parse().ok()).collect())
+            .unwrap_or_default()
+    };
+    let time = number(&patterns.sec)? + number(&patterns.nsec)? * 1e-9;
+    let (start, end) = (number(&patterns.start_angle)?, number(&patterns.end_angle)?);
+    let ranges = list(&patterns.ranges);
+    let intensities = list(&patterns.intensities);
+    let step = if ranges.len() > 1 {
+        (end - start) / (ranges.len() - 1) as f64
+    } else {
+        0.0
+    };
+    let mut bins = ranges
+        .iter()
+        .enumerate()
+        .map(|(i, range)| {
+            let angle = convention.from_sensor_angle(start + i as f64 * step);
+            let sample = range.map(|range| Sample {
+                angle,
+                distance: (range * 1000.0).round().clamp(0.0, u16::MAX as f64) as u16,
+                quality: intensities
+                    .get(i)
+                    .copied()
+                    .flatten()
+                    .map_or(0, |q| q.clamp(0.0, u16::MAX as f64) as u16),
+            });
+            (angle, sample)
+        })
+        .collect::<Vec<_>>();
+    // in the order of the driver
+    bins.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok((time, bins.into_iter().map(|(_, s)| s).collect()))
+}
+
+struct ScanPatterns {
+    sec: Regex,
+    nsec: Regex,
+    start_angle: Regex,
+    end_angle: Regex,
+    ranges: Regex,
+    intensities: Regex,
+}
+
+impl ScanPatterns {
+    fn new() -> ScanPatterns {
+        let number = |key: &str| Regex::new(&format!(r#""{}"\s*:\s*([-+0-9.eE]+)"#, key)).unwrap();
+        let list = |key: &str| Regex::new(&format!(r#""{}"\s*:\s*\[([^\]]*)\]"#, key)).unwrap();
+        ScanPatterns {
+            sec: number("sec"),
+            nsec: number("nsec"),
+            start_angle: number("start_angle"),
+            end_angle: number("end_angle"),
+            ranges: list("ranges"),
+            intensities: list("intensities"),
+        }
+    }
+}
+
+fn put_string(out: &mut Vec<u8>, text: &str) {
+    out.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// Writes scans, and optionally raw packets, to an MCAP file that opens in
+/// Foxglove Studio and the other MCAP tools.
+///
+/// Scans are `foxglove.LaserScan` JSON messages on `SCAN_TOPIC`, whose channel
+/// metadata keeps the convention and frame of the sensor. Packets are written
+/// unchanged on `PACKET_TOPIC`. The file is not chunked nor indexed.
+pub struct McapWriter<W: Write> {
+    out: W,
+    mount: Mount,
+    frame_id: String,
+    sequence: u32,
+}
+
+impl<W: Write> McapWriter<W> {
+    /// Writes the header, schema and channels of a lidar mounted as `mount`,
+    /// whose sensor frame is named `frame_id`.
+    pub fn new(mut out: W, mount: &Mount, frame_id: &str) -> io::Result<McapWriter<W>> {
+        out.write_all(MAGIC)?;
+        let mut writer = McapWriter {
+            out,
+            mount: *mount,
+            frame_id: frame_id.to_string(),
+            sequence: 0,
+        };
+
+        let mut header = vec![];
+        put_string(&mut header, "");
+        put_string(&mut header, "lidar_rd");
+        writer.record(OP_HEADER, &header)?;
+
+        let mut schema = vec![];
+        schema.extend_from_slice(&LASER_SCAN_SCHEMA_ID.to_le_bytes());
+        put_string(&mut schema, "foxglove.LaserScan");
+        put_string(&mut schema, "jsonschema");
+        put_string(&mut schema, LASER_SCAN_SCHEMA);
+        writer.record(OP_SCHEMA, &schema)?;
+
+        let convention = convention_fields(&mount.convention);
+        let metadata = convention
+            .iter()
+            .map(|(key, value)| (*key, value.as_str()))
+            .collect::<Vec<_>>();
+        writer.channel(
+            SCAN_CHANNEL,
+            LASER_SCAN_SCHEMA_ID,
+            SCAN_TOPIC,
+            "json",
+            &[&metadata[..], &[("frame_id", frame_id)]].concat(),
+        )?;
+        // schemaless channel of opaque bytes
+        writer.channel(PACKET_CHANNEL, 0, PACKET_TOPIC, "", &metadata)?;
+        Ok(writer)
+    }
+
+    fn record(&mut self, op: u8, content: &[u8]) -> io::Result<()> {
+        self.out.write_all(&[op])?;
+        self.out.write_all(&(content.len() as u64).to_le_bytes())?;
+        self.out.write_all(content)
+    }
+
+    fn channel(
+        &mut self,
+        id: u16,
+        schema_id: u16,
+        topic: &str,
+        encoding: &str,
+        metadata: &[(&str, &str)],
+    ) -> io::Result<()> {
+        let mut map = vec![];
+        for (key, value) in metadata {
+            put_string(&mut map, key);
+            put_string(&mut map, value);
+        }
+        let mut channel = vec![];
+        channel.extend_from_slice(&id.to_le_bytes());
+        channel.extend_from_slice(&schema_id.to_le_bytes());
+        put_string(&mut channel, topic);
+        put_string(&mut channel, encoding);
+        channel.extend_from_slice(&(map.len() as u32).to_le_bytes());
+        channel.extend_from_slice(&map);
+        self.record(OP_CHANNEL, &channel)
+    }
+
+    fn message(&mut self, channel: u16, time: SystemTime, data: &[u8]) -> io::Result<()> {
+        let mut message = Vec::with_capacity(22 + data.len());
+        message.extend_from_slice(&channel.to_le_bytes());
+        message.extend_from_slice(&self.sequence.to_le_bytes());
+        message.extend_from_slice(&nanos(time).to_le_bytes());
+        message.extend_from_slice(&nanos(time).to_le_bytes());
+        message.extend_from_slice(data);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.record(OP_MESSAGE, &message)
+    }
+
+    /// Writes a scan received at `time`, in seconds since the UNIX epoch.
+    pub fn write_scan(&mut self, time: f64, scan: &[Option<Sample>]) -> io::Result<()> {
+        let time = UNIX_EPOCH + Duration::from_secs_f64(time.max(0.0));
+        let json = laser_scan_json(time, &self.mount, &self.frame_id, scan);
+        self.message(SCAN_CHANNEL, time, json.as_bytes())
+    }
+
+    pub fn write_packet(&mut self, packet: &RawPacket) -> io::Result<()> {
+        self.message(PACKET_CHANNEL, packet.time, &packet.data)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// Ends the file, which is needed for it to be complete.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.record(OP_DATA_END, &0u32.to_le_bytes())?;
+        // no summary section
+        self.record(OP_FOOTER, &[0; 20])?;
+        self.out.write_all(MAGIC)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl McapWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, mount: &Mount, frame_id: &str) -> io::Result<Self> {
+        McapWriter::new(BufWriter::new(File::create(path)?), mount, frame_id)
+    }
+}
+
+/// A message of a `McapReader`.
+pub enum McapMessage {
+    Scan(TimedScan),
+    Packet(RawPacket),
+}
+
+/// Reads back the scans and packets of an MCAP file, as written by `McapWriter`.
+///
+/// Other `foxglove.LaserScan` JSON channels are read as scans too. The samples
+/// are at the angles of the LaserScan bins, not at the measured ones.
+pub struct McapReader<R: Read> {
+    input: R,
+    convention: Convention,
+    frame_id: String,
+    // topic and kind of the known channels
+    channels: HashMap<u16, ChannelKind>,
+    // messages read while looking for the scan channel
+    pending: VecDeque<McapMessage>,
+    patterns: ScanPatterns,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ChannelKind {
+    Scan,
+    Packet,
+}
+
+/// Cursor over the content of a record.
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated record",
+            ));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let n = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(n)?).to_string())
+    }
+
+    fn map(&mut self) -> io::Result<HashMap<String, String>> {
+        let n = self.u32()? as usize;
+        let mut fields = Fields {
+            data: self.take(n)?,
+        };
+        let mut map = HashMap::new();
+        while !fields.data.is_empty() {
+            let key = fields.string()?;
+            map.insert(key, fields.string()?);
+        }
+        Ok(map)
+    }
+}
+
+impl<R: Read> McapReader<R> {
+    /// Reads the file up to the scan channel, to know the convention of the scans.
+    pub fn new(mut input: R) -> Result<McapReader<R>, Box<dyn Error>> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err("not an MCAP file".into());
+        }
+        let mut reader = McapReader {
+            input,
+            convention: Convention::ROBOT,
+            frame_id: String::new(),
+            channels: HashMap::new(),
+            pending: VecDeque::new(),
+            patterns: ScanPatterns::new(),
+        };
+        // the scan channel is declared before its first message
+        let has_scans =
+            |reader: &McapReader<R>| reader.channels.values().any(|&k| k == ChannelKind::Scan);
+        while !has_scans(&reader) {
+            match reader.read_message()? {
+                Some(message) => reader.pending.push_back(message),
+                None => break,
+            }
+        }
+        if !has_scans(&reader) {
+            return Err("no LaserScan channel in the MCAP file".into());
+        }
+        Ok(reader)
+    }
+
+    /// Convention of the scan angles, from the channel metadata.
+    pub fn convention(&self) -> Convention {
+        self.convention
+    }
+
+    /// Frame of the scans, from the LaserScan channel metadata.
+    pub fn frame_id(&self) -> &str {
+        &self.frame_id
+    }
+
+    /// Only the scans, skipping the packets.
+    pub fn scans(self) -> impl Iterator<Item = Result<TimedScan, Box<dyn Error>>> {
+        self.filter_map(|message| match message {
+            Ok(McapMessage::Scan(scan)) => Some(Ok(scan)),
+            Ok(McapMessage::Packet(_)) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    /// Next scan or packet, `None` at the end of the data (or of a truncated file).
+    fn read_message(&mut self) -> Result<Option<McapMessage>, Box<dyn Error>> {
+        loop {
+            let mut op = [0; 1];
+            if self.input.read(&mut op)? == 0 {
+                return Ok(None);
+            }
+            let mut length = [0; 8];
+            let mut content = vec![];
+            let complete = self.input.read_exact(&mut length).is_ok() && {
+                let length = u64::from_le_bytes(length);
+                (&mut self.input).take(length).read_to_end(&mut content)? as u64 == length
+            };
+            if !complete {
+                // an interrupted recording
+                return Ok(None);
+            }
+            let mut fields = Fields { data: &content };
+            match op[0] {
+                OP_CHANNEL => {
+                    let id = fields.u16()?;
+                    let _schema_id = fields.u16()?;
+                    let topic = fields.string()?;
+                    let encoding = fields.string()?;
+                    let metadata = fields.map()?;
+                    let is_scan = encoding == "json"
+                        && (topic == SCAN_TOPIC || metadata.contains_key("frame_id"));
+                    if is_scan && !self.channels.values().any(|&k| k == ChannelKind::Scan) {
+                        if metadata.contains_key("convention") || metadata.contains_key("unit") {
+                            self.convention =
+                                parse_convention(|key| metadata.get(key).map(|v| v.as_str()))?;
+                        }
+                        self.frame_id = metadata.get("frame_id").cloned().unwrap_or_default();
+                        self.channels.insert(id, ChannelKind::Scan);
+                    } else if topic == PACKET_TOPIC {
+                        self.channels.insert(id, ChannelKind::Packet);
+                    }
+                }
+                OP_MESSAGE => {
+                    let channel = fields.u16()?;
+                    let _sequence = fields.u32()?;
+                    let log_time = fields.u64()?;
+                    let _publish_time = fields.u64()?;
+                    match self.channels.get(&channel) {
+                        Some(ChannelKind::Scan) => {
+                            let json = String::from_utf8_lossy(fields.data);
+                            return parse_laser_scan(&json, self.convention, &self.patterns)
+                                .map(|scan| Some(McapMessage::Scan(scan)));
+                        }
+                        Some(ChannelKind::Packet) => {
+                            return Ok(Some(McapMessage::Packet(RawPacket {
+                                time: UNIX_EPOCH + Duration::from_nanos(log_time),
+                                data: fields.data.to_vec(),
+                            })))
+                        }
+                        None => {}
+                    }
+                }
+                OP_DATA_END | OP_FOOTER => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl McapReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        McapReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> Iterator for McapReader<R> {
+    type Item = Result<McapMessage, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(message) = self.pending.pop_front() {
+            return Some(Ok(message));
+        }
+        self.read_message().transpose()
+    }
+}
+
+/// Whether the file starts like an MCAP file.
+pub fn is_mcap<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    let mut magic = [0; 8];
+    let mut file = File::open(path)?;
+    Ok(file.read_exact(&mut magic).is_ok() && &magic == MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::AngleUnit;
+    use std::f64::consts::PI;
+
+    const TIME: f64 = 1_700_000_000.25;
+
+    fn scan(convention: Convention, distances: &[Option<u16>]) -> Vec<Option<Sample>> {
+        let step = 2.0 * PI / distances.len() as f64;
+        distances
+            .iter()
+            .enumerate()
+            .map(|(i, d)| {
+                d.map(|distance| Sample {
+                    angle: convention.from_sensor_angle(i as f64 * step),
+                    distance,
+                    quality: 100 + i as u16,
+                })
+            })
+            .collect()
+    }
+
+    /// (bin, distance, quality) of the valid samples, bins in sensor angle order.
+    fn bins(convention: Convention, scan: &[Option<Sample>]) -> Vec<(usize, u16, u16)> {
+        let step = 2.0 * PI / scan.len() as f64;
+        let mut bins = scan
+            .iter()
+            .flatten()
+            .map(|s| {
+                let a = convention.to_sensor_angle(s.angle).rem_euclid(2.0 * PI);
+                let bin = (a / step).round() as usize % scan.len();
+                (bin, s.distance, s.quality)
+            })
+            .collect::<Vec<_>>();
+        bins.sort();
+        bins
+    }
+
+    fn recording(scans: usize) -> Vec<u8> {
+        let convention = Convention::LD06;
+        let mut writer = McapWriter::new(vec![], &Mount::new(convention), "laser").unwrap();
+        for i in 0..scans {
+            writer
+                .write_packet(&RawPacket {
+                    time: UNIX_EPOCH + Duration::from_millis(1500 + i as u64),
+                    data: vec![0x54, 0x2c, i as u8, b'\n'],
+                })
+                .unwrap();
+            let distances = [Some(1000 + i as u16), None, Some(2500), Some(750)];
+            writer
+                .write_scan(TIME + i as f64, &scan(convention, &distances))
+                .unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = recording(2);
+        assert!(data.starts_with(MAGIC) && data.ends_with(MAGIC));
+        let reader = McapReader::new(&data[..]).unwrap();
+        assert_eq!(reader.convention(), Convention::LD06);
+        assert_eq!(reader.frame_id(), "laser");
+
+        let messages = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(messages.len(), 4);
+        for (i, pair) in messages.chunks(2).enumerate() {
+            match &pair[0] {
+                McapMessage::Packet(packet) => {
+                    assert_eq!(packet.data, [0x54, 0x2c, i as u8, b'\n']);
+                    let time = UNIX_EPOCH + Duration::from_millis(1500 + i as u64);
+                    assert_eq!(packet.time, time);
+                }
+                McapMessage::Scan(_) => panic!("expected a packet"),
+            }
+            match &pair[1] {
+                McapMessage::Scan((time, samples)) => {
+                    assert!((time - (TIME + i as f64)).abs() < 1e-6);
+                    assert_eq!(samples.len(), 4);
+                    let expected = vec![(0, 1000 + i as u16, 100), (2, 2500, 102), (3, 750, 103)];
+                    assert_eq!(bins(Convention::LD06, samples), expected);
+                }
+                McapMessage::Packet(_) => panic!("expected a scan"),
+            }
+        }
+    }
+
+    #[test]
+    fn scans_only() {
+        let data = recording(3);
+        let scans = McapReader::new(&data[..]).unwrap().scans();
+        let times = scans.map(|s| s.unwrap().0).collect::<Vec<_>>();
+        assert_eq!(times.len(), 3);
+        assert!((times[2] - (TIME + 2.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn truncated_file() {
+        let data = recording(3);
+        // without the data end (13 bytes), footer (29 bytes) and magic
+        let end = data.len() - 13 - 29 - MAGIC.len();
+        let scans = McapReader::new(&data[..end]).unwrap().scans();
+        assert_eq!(scans.collect::<Result<Vec<_>, _>>().unwrap().len(), 3);
+        // the end of the last scan is missing
+        let scans = McapReader::new(&data[..end - 10]).unwrap().scans();
+        assert_eq!(scans.collect::<Result<Vec<_>, _>>().unwrap().len(), 2);
+
+        // cut before the scan channel
+        assert!(McapReader::new(&data[..MAGIC.len() + 20]).is_err());
+        assert!(McapReader::new(&data[..4]).is_err());
+        assert!(McapReader::new(&b"not an mcap file"[..]).is_err());
+    }
+
+    #[test]
+    fn partial_turns() {
+        // a UST05LN turn : 541 samples every 0.5 degree over 270 degrees
+        let convention = Convention::UST05LN;
+        let turn = (0..541)
+            .map(|i| {
+                let sample = Sample {
+                    angle: (-135.0 + 0.5 * i as f64).to_radians(),
+                    distance: 500 + i as u16,
+                    quality: i as u16,
+                };
+                if i % 7 == 3 {
+                    None
+                } else {
+                    Some(sample)
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut writer = McapWriter::new(vec![], &Mount::new(convention), "laser").unwrap();
+        writer.write_scan(TIME, &turn).unwrap();
+        let data = writer.finish().unwrap();
+
+        let mut scans = McapReader::new(&data[..]).unwrap().scans();
+        let (_, samples) = scans.next().unwrap().unwrap();
+        assert_eq!(samples.len(), 541);
+        assert_eq!(
+            samples.iter().flatten().count(),
+            turn.iter().flatten().count()
+        );
+        for (read, written) in samples.iter().zip(&turn) {
+            match (read, written) {
+                (Some(r), Some(w)) => {
+                    assert!((r.angle - w.angle).abs() < 1e-9);
+                    assert_eq!((r.distance, r.quality), (w.distance, w.quality));
+                }
+                (None, None) => {}
+                _ => panic!("{:?} read back as {:?}", written, read),
+            }
+        }
+    }
+
+    #[test]
+    fn custom_convention() {
+        let convention = Convention::new(AngleUnit::Degrees, false, 0.5);
+        let distances = [Some(1000), Some(1100), None, Some(1300)];
+        let mut writer = McapWriter::new(vec![], &Mount::new(convention), "laser").unwrap();
+        writer
+            .write_scan(TIME, &scan(convention, &distances))
+            .unwrap();
+        let data = writer.finish().unwrap();
+
+        let reader = McapReader::new(&data[..]).unwrap();
+        assert_eq!(reader.convention(), convention);
+        let (_, samples) = reader.scans().next().unwrap().unwrap();
+        let expected = vec![(0, 1000, 100), (1, 1100, 101), (3, 1300, 103)];
+        assert_eq!(bins(convention, &samples), expected);
+    }
+}
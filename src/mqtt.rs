@@ -0,0 +1,726 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::lidar::{unix_time, DriverStats, Sample};
+use crate::safety::sector_minima;
+use crate::transform::{Mount, Scan};
+
+// control packet types of MQTT 3.1.1, in the high nibble of the first byte
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+const DISCONNECT: u8 = 0xe0;
+
+const PROTOCOL_LEVEL: u8 = 4;
+// largest remaining length, on 4 bytes
+const MAX_LENGTH: usize = 268_435_455;
+
+const CONNACK_ERRORS: [&str; 5] = [
+    "unacceptable protocol version",
+    "identifier rejected",
+    "server unavailable",
+    "bad user name or password",
+    "not authorized",
+];
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn push_string(packet: &mut Vec<u8>, s: &str) {
+    packet.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    packet.extend_from_slice(s.as_bytes());
+}
+
+/// Fixed header and `body` of a control packet.
+fn packet(kind: u8, body: &[u8]) -> io::Result<Vec<u8>> {
+    if body.len() > MAX_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "packet too large for MQTT",
+        ));
+    }
+    let mut packet = vec![kind];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    Ok(packet)
+}
+
+/// Length of the control packet starting `data`, `None` until it is complete.
+fn packet_length(data: &[u8]) -> io::Result<Option<usize>> {
+    let mut length = 0;
+    for (i, byte) in data.iter().skip(1).take(4).enumerate() {
+        length += ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            let total = 2 + i + length;
+            return Ok(Some(total).filter(|&total| data.len() >= total));
+        }
+    }
+    if data.len() > 4 {
+        return Err(invalid_data("invalid remaining length".to_string()));
+    }
+    Ok(None)
+}
+
+/// Settings of the connection to the broker.
+#[derive(Clone, Debug)]
+pub struct MqttOptions {
+    /// Broker, as `host:port`.
+    pub broker: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The broker closes the connection after 1.5 times this without packet,
+    /// pings are sent twice as often. The connection is considered lost when
+    /// a ping isn't answered within this time.
+    pub keep_alive: Duration,
+    pub connect_timeout: Duration,
+}
+
+impl Default for MqttOptions {
+    fn default() -> MqttOptions {
+        MqttOptions {
+            broker: "localhost:1883".to_string(),
+            client_id: "lidar".to_string(),
+            username: None,
+            password: None,
+            keep_alive: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// A publish-only MQTT 3.1.1 client, with QoS 0 messages and a clean session.
+///
+/// A background thread reads what the broker sends, and pings it when the
+/// connection is idle. Once the connection is lost, or the broker stops
+/// answering the pings, `is_connected` is `false` and publishing fails :
+/// connect again, see `MqttBridge`.
+pub struct MqttClient {
+    stream: Arc<Mutex<TcpStream>>,
+    connected: Arc<AtomicBool>,
+}
+
+impl MqttClient {
+    pub fn connect(options: &MqttOptions) -> io::Result<MqttClient> {
+        // MQTT 3.1.1 section 3.1.2.9
+        if options.password.is_some() && options.username.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an MQTT password needs a user name",
+            ));
+        }
+        let addr = options
+            .broker
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid broker"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, options.connect_timeout)?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(options.connect_timeout))?;
+        stream.set_read_timeout(Some(options.connect_timeout))?;
+
+        let mut flags = 0x02; // clean session
+        let mut body = vec![];
+        push_string(&mut body, "MQTT");
+        body.push(PROTOCOL_LEVEL);
+        if options.username.is_some() {
+            flags |= 0x80;
+        }
+        if options.password.is_some() {
+            flags |= 0x40;
+        }
+        body.push(flags);
+        let keep_alive = options.keep_alive.as_secs().min(u16::MAX as u64) as u16;
+        body.extend_from_slice(&keep_alive.to_be_bytes());
+        push_string(&mut body, &options.client_id);
+        for field in [&options.username, &options.password]
+            .iter()
+            .copied()
+            .flatten()
+        {
+            push_string(&mut body, field);
+        }
+        stream.write_all(&packet(CONNECT, &body)?)?;
+
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != CONNACK || connack[1] != 2 {
+            return Err(invalid_data(format!(
+                "unexpected answer to CONNECT : {:02x?}",
+                connack
+            )));
+        }
+        if connack[3] != 0 {
+            let reason = CONNACK_ERRORS
+                .get(connack[3] as usize - 1)
+                .copied()
+                .unwrap_or("unknown error");
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("connection refused by the broker : {}", reason),
+            ));
+        }
+
+        let keep_alive = if keep_alive == 0 {
+            None
+        } else {
+            Some(options.keep_alive)
+        };
+        stream.set_read_timeout(keep_alive.map(|k| k / 2))?;
+        let reader = stream.try_clone()?;
+        let client = MqttClient {
+            stream: Arc::new(Mutex::new(stream)),
+            connected: Arc::new(AtomicBool::new(true)),
+        };
+        let (writer, connected) = (client.stream.clone(), client.connected.clone());
+        thread::spawn(move || keep_alive_run(reader, writer, connected, keep_alive));
+        Ok(client)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Publishes `payload` on `topic`, with QoS 0.
+    pub fn publish(&self, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+        if !self.is_connected() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "connection to the broker lost",
+            ));
+        }
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+        push_string(&mut body, topic);
+        body.extend_from_slice(payload);
+        let packet = packet(PUBLISH | retain as u8, &body)?;
+        let result = self.stream.lock().unwrap().write_all(&packet);
+        if result.is_err() {
+            self.connected.store(false, Ordering::SeqCst);
+        }
+        result
+    }
+
+    /// Closes the connection, without waiting for the messages to be delivered.
+    pub fn disconnect(&self) {
+        let mut stream = self.stream.lock().unwrap();
+        if self.connected.swap(false, Ordering::SeqCst) {
+            let _ = stream.write_all(&[DISCONNECT, 0]);
+        }
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl Drop for MqttClient {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+/// Reads what the broker sends, and pings it on read timeouts, which happen
+/// every half `keep_alive` when idle. Ends when the connection is lost, or
+/// when a ping isn't answered within `keep_alive` (a half-open connection).
+fn keep_alive_run(
+    mut reader: TcpStream,
+    writer: Arc<Mutex<TcpStream>>,
+    connected: Arc<AtomicBool>,
+    keep_alive: Option<Duration>,
+) {
+    let mut buffer = [0; 256];
+    let mut received = vec![];
+    // when the unanswered ping was sent
+    let mut ping: Option<Instant> = None;
+    while connected.load(Ordering::SeqCst) {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                received.extend_from_slice(&buffer[..n]);
+                // the other packets are ignored
+                loop {
+                    match packet_length(&received) {
+                        Ok(Some(length)) => {
+                            if received[0] & 0xf0 == PINGRESP {
+                                ping = None;
+                            }
+                            received.drain(..length);
+                        }
+                        Ok(None) => break,
+                        Err(_) => {
+                            connected.store(false, Ordering::SeqCst);
+                            return;
+                        }
+                    }
+                }
+            }
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                if ping.is_none() {
+                    if writer.lock().unwrap().write_all(&[PINGREQ, 0]).is_err() {
+                        break;
+                    }
+                    ping = Some(Instant::now());
+                }
+            }
+            Err(_) => break,
+        }
+        if let (Some(sent), Some(keep_alive)) = (ping, keep_alive) {
+            if sent.elapsed() >= keep_alive {
+                break;
+            }
+        }
+    }
+    connected.store(false, Ordering::SeqCst);
+}
+
+/// What `MqttBridge` publishes, and where.
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    pub options: MqttOptions,
+    /// Topic of the scan summaries, see `summary_json`.
+    pub summary_topic: String,
+    /// Topic of the full scans (see `scan_json`), not published when `None`.
+    pub scan_topic: Option<String>,
+    /// Name of the sensor frame in the full scans.
+    pub frame_id: String,
+    /// Number of sectors of the summaries.
+    pub sectors: usize,
+    /// Summaries are retained by the broker, for the dashboards started later.
+    pub retain: bool,
+    /// First delay before reconnecting, doubled after each failure.
+    pub reconnect_delay: Duration,
+    pub max_reconnect_delay: Duration,
+}
+
+impl Default for MqttConfig {
+    fn default() -> MqttConfig {
+        MqttConfig {
+            options: MqttOptions::default(),
+            summary_topic: "lidar/summary".to_string(),
+            scan_topic: None,
+            frame_id: "lidar".to_string(),
+            sectors: 8,
+            retain: false,
+            reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl MqttConfig {
+    pub fn with_broker(mut self, broker: &str) -> MqttConfig {
+        self.options.broker = broker.to_string();
+        self
+    }
+
+    pub fn with_client_id(mut self, client_id: &str) -> MqttConfig {
+        self.options.client_id = client_id.to_string();
+        self
+    }
+
+    pub fn with_credentials(mut self, username: &str, password: Option<&str>) -> MqttConfig {
+        self.options.username = Some(username.to_string());
+        self.options.password = password.map(|p| p.to_string());
+        self
+    }
+
+    pub fn with_summary_topic(mut self, topic: &str) -> MqttConfig {
+        self.summary_topic = topic.to_string();
+        self
+    }
+
+    pub fn with_scan_topic(mut self, topic: Option<&str>) -> MqttConfig {
+        self.scan_topic = topic.map(|t| t.to_string());
+        self
+    }
+
+    pub fn with_frame_id(mut self, frame_id: &str) -> MqttConfig {
+        self.frame_id = frame_id.to_string();
+        self
+    }
+
+    pub fn with_sectors(mut self, sectors: usize) -> MqttConfig {
+        self.sectors = sectors.max(1);
+        self
+    }
+
+    pub fn with_retain(mut self, retain: bool) -> MqttConfig {
+        self.retain = retain;
+        self
+    }
+}
+
+fn json_number(value: Option<f64>, precision: usize) -> String {
+    match value {
+        Some(v) if v.is_finite() => format!("{:.*}", precision, v),
+        _ => "null".to_string(),
+    }
+}
+
+/// Summary of a scan :
+/// `{"time":…,"rpm":…,"scan_rate":…,"packets":…,"errors":…,"error_rate":…,"valid":…,"sectors":[…]}`,
+/// with the unix time in seconds, `scan_rate` in Hz, `error_rate` and `valid`
+/// (ratio of valid samples) in [0, 1], and the closest point of each sector in
+/// meters, `null` when empty (see `safety::sector_minima`). `rpm` is `null`
+/// when the driver doesn't report it.
+pub fn summary_json(
+    mount: &Mount,
+    scan: &[Option<Sample>],
+    sectors: usize,
+    stats: &DriverStats,
+    scan_rate: f64,
+) -> String {
+    let valid = scan.iter().flatten().count() as f64 / scan.len().max(1) as f64;
+    let minima = sector_minima(&scan.to_points(mount), sectors)
+        .into_iter()
+        .map(|d| json_number(d, 3))
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"time\":{:.3},\"rpm\":{},\"scan_rate\":{:.2},\"packets\":{},\"errors\":{},\"error_rate\":{:.4},\"valid\":{:.3},\"sectors\":[{}]}}",
+        unix_time(),
+        json_number(stats.rpm, 1),
+        scan_rate,
+        stats.packets,
+        stats.checksum_errors,
+        stats.error_rate(),
+        valid,
+        minima.join(",")
+    )
+}
+
+/// Points of a scan in the robot frame, in meters :
+/// `{"time":…,"frame_id":…,"points":[[x,y],…]}`, `frame_id` naming the sensor frame.
+pub fn scan_json(mount: &Mount, frame_id: &str, scan: &[Option<Sample>]) -> String {
+    let points = scan
+        .to_points(mount)
+        .iter()
+        .map(|p| format!("[{:.3},{:.3}]", p.x, p.y))
+        .collect::<Vec<_>>();
+    format!(
+        "{{\"time\":{:.3},\"frame_id\":\"{}\",\"points\":[{}]}}",
+        unix_time(),
+        crate::mcap::escape_json(frame_id),
+        points.join(",")
+    )
+}
+
+/// Publishes scan summaries, and optionally full scans, to an MQTT broker.
+///
+/// It connects on the first scan, and reconnects when the connection is lost,
+/// waiting longer after each failed attempt (up to `max_reconnect_delay`).
+/// Scans published while disconnected are dropped. Connecting blocks for up
+/// to `connect_timeout`.
+pub struct MqttBridge {
+    config: MqttConfig,
+    client: Option<MqttClient>,
+    retry_at: Option<Instant>,
+    delay: Duration,
+    // scan rate, measured over about a second
+    rate_start: Instant,
+    rate_scans: u32,
+    scan_rate: f64,
+}
+
+impl MqttBridge {
+    pub fn new(config: MqttConfig) -> MqttBridge {
+        MqttBridge {
+            delay: config.reconnect_delay,
+            config,
+            client: None,
+            retry_at: None,
+            rate_start: Instant::now(),
+            rate_scans: 0,
+            scan_rate: 0.0,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.as_ref().is_some_and(|c| c.is_connected())
+    }
+
+    /// Connects if not connected and the reconnection delay is over, returns
+    /// whether it is connected.
+    pub fn ensure_connected(&mut self) -> bool {
+        if self.is_connected() {
+            return true;
+        }
+        let broker = &self.config.options.broker;
+        if self.client.take().is_some() {
+            eprintln!("mqtt : connection to {} lost", broker);
+        }
+        if self.retry_at.is_some_and(|t| Instant::now() < t) {
+            return false;
+        }
+        match MqttClient::connect(&self.config.options) {
+            Ok(client) => {
+                eprintln!("mqtt : connected to {}", broker);
+                self.client = Some(client);
+                self.retry_at = None;
+                self.delay = self.config.reconnect_delay;
+                true
+            }
+            Err(e) => {
+                eprintln!(
+                    "mqtt : failed to connect to {} : {}, retrying in {:.0?}",
+                    broker, e, self.delay
+                );
+                self.retry_at = Some(Instant::now() + self.delay);
+                self.delay = (self.delay * 2).min(self.config.max_reconnect_delay);
+                false
+            }
+        }
+    }
+
+    /// Publishes the summary of a scan, and the scan itself if enabled.
+    pub fn publish_scan(&mut self, mount: &Mount, scan: &[Option<Sample>], stats: &DriverStats) {
+        self.rate_scans += 1;
+        let elapsed = self.rate_start.elapsed().as_secs_f64();
+        if elapsed >= 1.0 {
+            self.scan_rate = self.rate_scans as f64 / elapsed;
+            self.rate_start = Instant::now();
+            self.rate_scans = 0;
+        }
+        if !self.ensure_connected() {
+            return;
+        }
+        let mut messages = vec![(
+            &self.config.summary_topic,
+            summary_json(mount, scan, self.config.sectors, stats, self.scan_rate),
+            self.config.retain,
+        )];
+        if let Some(topic) = &self.config.scan_topic {
+            messages.push((topic, scan_json(mount, &self.config.frame_id, scan), false));
+        }
+        let client = self.client.as_ref().unwrap();
+        for (topic, payload, retain) in messages {
+            if let Err(e) = client.publish(topic, payload.as_bytes(), retain) {
+                eprintln!("mqtt : failed to publish on {} : {}", topic, e);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Convention;
+    use std::net::TcpListener;
+
+    /// Options of a client of a local broker stub.
+    fn stub() -> (TcpListener, MqttOptions) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = MqttOptions {
+            broker: listener.local_addr().unwrap().to_string(),
+            client_id: "lidar-1".to_string(),
+            ..MqttOptions::default()
+        };
+        (listener, options)
+    }
+
+    /// Type and body of the next packet received by the stub.
+    fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        let kind = byte[0];
+        let mut length = 0;
+        for i in 0..4 {
+            stream.read_exact(&mut byte).unwrap();
+            length += ((byte[0] & 0x7f) as usize) << (7 * i);
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).unwrap();
+        (kind, body)
+    }
+
+    /// Accepts a client, checks its CONNECT and answers with `return_code`.
+    fn accept(listener: &TcpListener, return_code: u8) -> TcpStream {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(read_packet(&mut stream).0, CONNECT);
+        stream.write_all(&[CONNACK, 2, 0, return_code]).unwrap();
+        stream
+    }
+
+    fn connect(options: &MqttOptions) -> thread::JoinHandle<io::Result<MqttClient>> {
+        let options = options.clone();
+        thread::spawn(move || MqttClient::connect(&options))
+    }
+
+    #[test]
+    fn remaining_lengths() {
+        for (length, header) in [
+            (0, vec![0x30, 0]),
+            (127, vec![0x30, 0x7f]),
+            (128, vec![0x30, 0x80, 0x01]),
+            (16_383, vec![0x30, 0xff, 0x7f]),
+            (16_384, vec![0x30, 0x80, 0x80, 0x01]),
+            (2_097_152, vec![0x30, 0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let packet = packet(PUBLISH, &vec![0; length]).unwrap();
+            assert_eq!(packet[..header.len()], header[..]);
+            assert_eq!(packet_length(&packet).unwrap(), Some(packet.len()));
+            assert_eq!(packet_length(&packet[..packet.len() - 1]).unwrap(), None);
+        }
+        assert!(packet(PUBLISH, &vec![0; MAX_LENGTH + 1]).is_err());
+        assert!(packet_length(&[PUBLISH, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn connect_packet() {
+        let (listener, mut options) = stub();
+        options.username = Some("user".to_string());
+        options.password = Some("secret".to_string());
+        let client = connect(&options);
+        let (mut stream, _) = listener.accept().unwrap();
+        let (kind, body) = read_packet(&mut stream);
+        assert_eq!(kind, CONNECT);
+        let mut expected = vec![0, 4, b'M', b'Q', b'T', b'T', PROTOCOL_LEVEL, 0xc2, 0, 30];
+        for field in ["lidar-1", "user", "secret"] {
+            expected.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            expected.extend_from_slice(field.as_bytes());
+        }
+        assert_eq!(body, expected);
+        stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+        let client = client.join().unwrap().unwrap();
+        assert!(client.is_connected());
+
+        client.disconnect();
+        assert_eq!(read_packet(&mut stream), (DISCONNECT, vec![]));
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn password_without_username() {
+        let (_listener, mut options) = stub();
+        options.password = Some("secret".to_string());
+        let error = MqttClient::connect(&options).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn refused_connections() {
+        let (listener, options) = stub();
+        for (code, reason) in CONNACK_ERRORS.iter().enumerate() {
+            let client = connect(&options);
+            accept(&listener, code as u8 + 1);
+            let error = client.join().unwrap().err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+            assert!(error.to_string().ends_with(reason), "{}", error);
+        }
+        let client = connect(&options);
+        let (mut stream, _) = listener.accept().unwrap();
+        read_packet(&mut stream);
+        stream.write_all(&[PUBLISH, 2, 0, 0]).unwrap();
+        let error = client.join().unwrap().err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn publish_packets() {
+        let (listener, options) = stub();
+        let client = connect(&options);
+        let mut stream = accept(&listener, 0);
+        let client = client.join().unwrap().unwrap();
+
+        let payload = vec![b'x'; 300];
+        client.publish("lidar/scan", &payload, true).unwrap();
+        client.publish("lidar/summary", b"{}", false).unwrap();
+
+        // retained, with 312 bytes of remaining length
+        let mut header = [0; 3];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header, [PUBLISH | 1, 0xb8, 0x02]);
+        let mut body = vec![0; 312];
+        stream.read_exact(&mut body).unwrap();
+        assert_eq!(body[..12], *b"\0\x0alidar/scan");
+        assert_eq!(body[12..], payload[..]);
+        let mut expected = vec![0, 13];
+        expected.extend_from_slice(b"lidar/summary{}");
+        assert_eq!(read_packet(&mut stream), (PUBLISH, expected));
+    }
+
+    #[test]
+    fn unanswered_pings() {
+        let (listener, mut options) = stub();
+        options.keep_alive = Duration::from_secs(1);
+        let client = connect(&options);
+        let mut stream = accept(&listener, 0);
+        let client = client.join().unwrap().unwrap();
+
+        // answered pings keep the connection up
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(1600) {
+            assert_eq!(read_packet(&mut stream), (PINGREQ, vec![]));
+            stream.write_all(&[PINGRESP, 0]).unwrap();
+        }
+        assert!(client.is_connected());
+
+        // a half-open connection is detected
+        assert_eq!(read_packet(&mut stream), (PINGREQ, vec![]));
+        let start = Instant::now();
+        while client.is_connected() {
+            assert!(start.elapsed() < Duration::from_secs(3));
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(client.publish("lidar/summary", b"{}", false).is_err());
+    }
+
+    #[test]
+    fn reconnects() {
+        let (listener, options) = stub();
+        let config = MqttConfig {
+            options,
+            reconnect_delay: Duration::from_millis(10),
+            ..MqttConfig::default()
+        };
+        let mut bridge = MqttBridge::new(config);
+        let mount = Mount::new(Convention::LD06);
+        let scan = vec![Some(Sample {
+            angle: 0.0,
+            distance: 1000,
+            quality: 200,
+        })];
+        let stats = DriverStats::default();
+
+        let broker = thread::spawn(move || {
+            let mut topics = vec![];
+            for _ in 0..2 {
+                let mut stream = accept(&listener, 0);
+                let (kind, body) = read_packet(&mut stream);
+                assert_eq!(kind, PUBLISH);
+                topics.push(String::from_utf8_lossy(&body[2..15]).to_string());
+                // the connection is dropped
+            }
+            topics
+        });
+        bridge.publish_scan(&mount, &scan, &stats);
+        let start = Instant::now();
+        while bridge.is_connected() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        bridge.publish_scan(&mount, &scan, &stats);
+        assert_eq!(broker.join().unwrap(), ["lidar/summary", "lidar/summary"]);
+    }
+}
@@ -0,0 +1,609 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::error::Error;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::lidar::{impl_drop, impl_iterator, unix_time, DriverStats, Lidar, Sample};
+use crate::transform::{AngleUnit, Convention};
+
+const MAGIC: &[u8; 2] = b"LD";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 32;
+const SAMPLE_SIZE: usize = 8;
+
+// incomplete scans kept, waiting for their missing datagrams
+const MAX_PARTIALS: usize = 8;
+
+/// Samples per datagram, which keeps datagrams under the usual 1500 bytes MTU.
+pub const SAMPLES_PER_DATAGRAM: usize = 150;
+
+/// Splits a scan into datagrams.
+///
+/// Each datagram holds, little endian : `LD`, the version (`u8`, 1), the
+/// convention (`u8` flags, 1 for degrees, 2 for clockwise and 4 for the robot
+/// frame, then `f64` zero),
+/// the scan sequence number (`u32`), the scan time (`f64`, seconds since the
+/// UNIX epoch), the fragment index and count (`u16`), the index of its first
+/// sample and the number of samples of the scan (`u16`), then its samples :
+/// `f32` angle (NaN for a missing sample), `u16` distance and `u16` quality.
+pub fn encode_scan(
+    sequence: u32,
+    time: f64,
+    convention: Convention,
+    scan: &[Option<Sample>],
+) -> Vec<Vec<u8>> {
+    let scan = &scan[..scan.len().min(u16::MAX as usize)];
+    let count = scan.len().div_ceil(SAMPLES_PER_DATAGRAM).max(1);
+    let flags = (convention.unit == AngleUnit::Degrees) as u8
+        | (convention.clockwise as u8) << 1
+        | ((convention == Convention::ROBOT) as u8) << 2;
+    (0..count)
+        .map(|i| {
+            let first = i * SAMPLES_PER_DATAGRAM;
+            let samples = &scan[first..(first + SAMPLES_PER_DATAGRAM).min(scan.len())];
+            let mut datagram = Vec::with_capacity(HEADER_SIZE + SAMPLE_SIZE * samples.len());
+            datagram.extend_from_slice(MAGIC);
+            datagram.push(VERSION);
+            datagram.push(flags);
+            datagram.extend_from_slice(&convention.zero.to_le_bytes());
+            datagram.extend_from_slice(&sequence.to_le_bytes());
+            datagram.extend_from_slice(&time.to_le_bytes());
+            datagram.extend_from_slice(&(i as u16).to_le_bytes());
+            datagram.extend_from_slice(&(count as u16).to_le_bytes());
+            datagram.extend_from_slice(&(first as u16).to_le_bytes());
+            datagram.extend_from_slice(&(scan.len() as u16).to_le_bytes());
+            for sample in samples {
+                let (angle, distance, quality) = match sample {
+                    Some(s) => (s.angle as f32, s.distance, s.quality),
+                    None => (f32::NAN, 0, 0),
+                };
+                datagram.extend_from_slice(&angle.to_le_bytes());
+                datagram.extend_from_slice(&distance.to_le_bytes());
+                datagram.extend_from_slice(&quality.to_le_bytes());
+            }
+            datagram
+        })
+        .collect()
+}
+
+/// A datagram of `encode_scan`, decoded.
+struct Fragment {
+    convention: Convention,
+    sequence: u32,
+    time: f64,
+    index: usize,
+    count: usize,
+    first: usize,
+    total: usize,
+    samples: Vec<Option<Sample>>,
+}
+
+fn decode_fragment(data: &[u8]) -> Option<Fragment> {
+    if data.len() < HEADER_SIZE || &data[..2] != MAGIC || data[2] != VERSION {
+        return None;
+    }
+    let u16_at = |i: usize| u16::from_le_bytes(data[i..i + 2].try_into().unwrap()) as usize;
+    let unit = if data[3] & 1 != 0 {
+        AngleUnit::Degrees
+    } else {
+        AngleUnit::Radians
+    };
+    let angles = Convention::new(
+        unit,
+        data[3] & 2 != 0,
+        f64::from_le_bytes(data[4..12].try_into().unwrap()),
+    );
+    // back to the named convention of the sender, when there is one
+    let convention = if data[3] & 4 != 0 {
+        Convention::ROBOT
+    } else {
+        [Convention::LD06, Convention::XV11, Convention::UST05LN]
+            .iter()
+            .copied()
+            .find(|c| c.same_angles(&angles))
+            .unwrap_or(angles)
+    };
+    let sequence = u32::from_le_bytes(data[12..16].try_into().unwrap());
+    let time = f64::from_le_bytes(data[16..24].try_into().unwrap());
+    let (index, count, first, total) = (u16_at(24), u16_at(26), u16_at(28), u16_at(30));
+    let body = &data[HEADER_SIZE..];
+    if index >= count || body.len() % SAMPLE_SIZE != 0 || first + body.len() / SAMPLE_SIZE > total {
+        return None;
+    }
+    let samples = body
+        .chunks(SAMPLE_SIZE)
+        .map(|s| {
+            let angle = f32::from_le_bytes(s[0..4].try_into().unwrap());
+            if angle.is_nan() {
+                return None;
+            }
+            Some(Sample {
+                angle: angle as f64,
+                distance: u16::from_le_bytes(s[4..6].try_into().unwrap()),
+                quality: u16::from_le_bytes(s[6..8].try_into().unwrap()),
+            })
+        })
+        .collect();
+    Some(Fragment {
+        convention,
+        sequence,
+        time,
+        index,
+        count,
+        first,
+        total,
+        samples,
+    })
+}
+
+/// Publishes scans as UDP datagrams (see `encode_scan`), to be received by a `NetworkLidar`.
+pub struct LidarServer {
+    socket: UdpSocket,
+    destinations: Vec<SocketAddr>,
+    convention: Convention,
+    sequence: u32,
+}
+
+impl LidarServer {
+    /// Sends from `addr` (`0.0.0.0:0` for any port) scans of the given convention.
+    pub fn bind<A: ToSocketAddrs>(addr: A, convention: Convention) -> io::Result<LidarServer> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_broadcast(true)?;
+        Ok(LidarServer {
+            socket,
+            destinations: vec![],
+            convention,
+            sequence: 0,
+        })
+    }
+
+    /// Adds a receiver, which can be a broadcast address.
+    pub fn with_destination<A: ToSocketAddrs>(mut self, addr: A) -> io::Result<LidarServer> {
+        self.add_destination(addr)?;
+        Ok(self)
+    }
+
+    pub fn add_destination<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<()> {
+        self.destinations.extend(addr.to_socket_addrs()?);
+        Ok(())
+    }
+
+    /// Sends a scan to every destination, timestamped now.
+    pub fn publish(&mut self, scan: &[Option<Sample>]) -> io::Result<()> {
+        let datagrams = encode_scan(self.sequence, unix_time(), self.convention, scan);
+        self.sequence = self.sequence.wrapping_add(1);
+        for destination in &self.destinations {
+            for datagram in &datagrams {
+                self.socket.send_to(datagram, destination)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A scan being reassembled.
+struct Partial {
+    time: f64,
+    received: Vec<bool>,
+    samples: Vec<Option<Sample>>,
+}
+
+struct Received {
+    convention: Option<Convention>,
+    scan: Option<(f64, Vec<Option<Sample>>)>,
+    stats: DriverStats,
+}
+
+/// Reassembles the scans from their datagrams, received in any order.
+#[derive(Default)]
+struct Reassembler {
+    partials: HashMap<u32, Partial>,
+    // sequence and time of the last complete scan
+    last: Option<(u32, f64)>,
+    // latest scans counted as dropped
+    dropped: VecDeque<u32>,
+}
+
+impl Reassembler {
+    /// Adds a datagram, a scan it completes replaces `received.scan`.
+    fn push(&mut self, data: &[u8], received: &mut Received) {
+        received.stats.packets += 1;
+        let fragment = match decode_fragment(data) {
+            Some(fragment) => fragment,
+            None => {
+                received.stats.checksum_errors += 1;
+                return;
+            }
+        };
+        received.convention = Some(fragment.convention);
+        // a scan sent before the last complete one is late, unless the server
+        // was restarted and counts from 0 again
+        if let Some((sequence, time)) = self.last {
+            let age = sequence.wrapping_sub(fragment.sequence) as i32;
+            if (0..MAX_PARTIALS as i32).contains(&age) && fragment.time <= time {
+                // not a duplicate of the last scan
+                if age > 0 {
+                    self.drop_scan(fragment.sequence, &mut received.stats);
+                }
+                return;
+            }
+        }
+        if self.partials.len() >= MAX_PARTIALS && !self.partials.contains_key(&fragment.sequence) {
+            let oldest = *self
+                .partials
+                .keys()
+                .max_by_key(|&&s| fragment.sequence.wrapping_sub(s))
+                .unwrap();
+            self.partials.remove(&oldest);
+            self.drop_scan(oldest, &mut received.stats);
+        }
+        let partial = self
+            .partials
+            .entry(fragment.sequence)
+            .or_insert_with(|| Partial {
+                time: fragment.time,
+                received: vec![false; fragment.count],
+                samples: vec![None; fragment.total],
+            });
+        if partial.received.len() != fragment.count || partial.samples.len() != fragment.total {
+            received.stats.checksum_errors += 1;
+            return;
+        }
+        if partial.received[fragment.index] {
+            // a duplicate
+            return;
+        }
+        partial.received[fragment.index] = true;
+        let end = fragment.first + fragment.samples.len();
+        partial.samples[fragment.first..end].copy_from_slice(&fragment.samples);
+
+        if partial.received.iter().all(|&r| r) {
+            let sequence = fragment.sequence;
+            let partial = self.partials.remove(&sequence).unwrap();
+            // the older scans won't be complete anymore
+            let older = self
+                .partials
+                .keys()
+                .copied()
+                .filter(|&s| s.wrapping_sub(sequence) as i32 <= 0)
+                .collect::<Vec<_>>();
+            for s in older {
+                self.partials.remove(&s);
+                self.drop_scan(s, &mut received.stats);
+            }
+            self.last = Some((sequence, partial.time));
+            received.scan = Some((partial.time, partial.samples));
+        }
+    }
+
+    /// Counts a scan as dropped, once.
+    fn drop_scan(&mut self, sequence: u32, stats: &mut DriverStats) {
+        if self.dropped.contains(&sequence) {
+            return;
+        }
+        if self.dropped.len() == 2 * MAX_PARTIALS {
+            self.dropped.pop_front();
+        }
+        self.dropped.push_back(sequence);
+        stats.dropped_scans += 1;
+    }
+}
+
+/// Receives the scans of a `LidarServer`, as a lidar.
+///
+/// `DriverStats::packets` counts the datagrams, `checksum_errors` the malformed
+/// ones, and `dropped_scans` the scans that were never complete or came late.
+pub struct NetworkLidar {
+    addr: String,
+    timeout: Duration,
+    convention: Option<Convention>,
+    tx_cmd: Option<mpsc::Sender<()>>,
+    join_handle: Option<thread::JoinHandle<()>>,
+    received: Arc<Mutex<Received>>,
+    last_time: Mutex<Option<f64>>,
+}
+
+impl NetworkLidar {
+    /// Receives on `addr`, e.g. `0.0.0.0:7777`.
+    pub fn new(addr: &str) -> NetworkLidar {
+        NetworkLidar {
+            addr: addr.into(),
+            timeout: Duration::from_secs(3),
+            convention: None,
+            tx_cmd: None,
+            join_handle: None,
+            received: Arc::new(Mutex::new(Received {
+                convention: None,
+                scan: None,
+                stats: DriverStats::default(),
+            })),
+            last_time: Mutex::new(None),
+        }
+    }
+
+    /// Convention of the scans, otherwise `start` waits for the first datagram to learn it.
+    pub fn with_convention(mut self, convention: Convention) -> NetworkLidar {
+        self.convention = Some(convention);
+        self
+    }
+
+    /// How long `start` waits for the first datagram, 3 s by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> NetworkLidar {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Time the last scan returned by `get_scan` was sent, in seconds since the UNIX epoch.
+    pub fn scan_time(&self) -> Option<f64> {
+        *self.last_time.lock().unwrap()
+    }
+}
+
+fn network_run(socket: UdpSocket, rx_cmd: Receiver<()>, received: Arc<Mutex<Received>>) {
+    let mut reassembler = Reassembler::default();
+    let mut buffer = [0; 65536];
+    loop {
+        match rx_cmd.try_recv() {
+            Ok(_) | Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
+        }
+        let n = match socket.recv(&mut buffer) {
+            Ok(n) => n,
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(e) => {
+                eprintln!("network lidar : {}", e);
+                break;
+            }
+        };
+        reassembler.push(&buffer[..n], &mut received.lock().unwrap());
+    }
+}
+
+impl Lidar for NetworkLidar {
+    fn get_scan(&self) -> Option<Vec<Option<Sample>>> {
+        let (time, scan) = self.received.lock().unwrap().scan.take()?;
+        *self.last_time.lock().unwrap() = Some(time);
+        Some(scan)
+    }
+
+    fn start(&mut self) -> Result<(), Box<dyn Error>> {
+        let socket = UdpSocket::bind(&self.addr)?;
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let (tx_cmd, rx_cmd) = mpsc::channel();
+        self.tx_cmd = Some(tx_cmd);
+        let received = self.received.clone();
+        self.join_handle = Some(thread::spawn(move || network_run(socket, rx_cmd, received)));
+
+        if self.convention.is_none() {
+            let start = Instant::now();
+            while self.received.lock().unwrap().convention.is_none() {
+                if start.elapsed() > self.timeout {
+                    self.stop();
+                    return Err(format!("no scan received on {}", self.addr).into());
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(tx) = self.tx_cmd.take() {
+            let _ = tx.send(());
+        }
+        if let Some(handle) = self.join_handle.take() {
+            handle.join().expect("failed to join thread");
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.join_handle.is_some()
+    }
+
+    /// The one given with `with_convention`, else the one of the received scans.
+    fn convention(&self) -> Convention {
+        self.convention
+            .or(self.received.lock().unwrap().convention)
+            .unwrap_or(Convention::ROBOT)
+    }
+
+    fn stats(&self) -> DriverStats {
+        self.received.lock().unwrap().stats
+    }
+}
+
+impl_iterator!(NetworkLidar);
+impl_drop!(NetworkLidar);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(len: usize, offset: u16) -> Vec<Option<Sample>> {
+        (0..len)
+            .map(|i| {
+                if i % 7 == 3 {
+                    return None;
+                }
+                Some(Sample {
+                    angle: i as f64 * 0.5,
+                    distance: offset + i as u16,
+                    quality: (i % 200) as u16,
+                })
+            })
+            .collect()
+    }
+
+    /// Samples as (angle, distance, quality) tuples, to be compared.
+    type Values = Vec<Option<(f64, u16, u16)>>;
+
+    fn values(scan: &[Option<Sample>]) -> Values {
+        scan.iter()
+            .map(|s| s.map(|s| (s.angle, s.distance, s.quality)))
+            .collect()
+    }
+
+    /// Takes the received scan, with its time.
+    fn take(received: &mut Received) -> Option<(f64, Values)> {
+        received
+            .scan
+            .take()
+            .map(|(time, scan)| (time, values(&scan)))
+    }
+
+    fn received() -> Received {
+        Received {
+            convention: None,
+            scan: None,
+            stats: DriverStats::default(),
+        }
+    }
+
+    #[test]
+    fn fragments_round_trip() {
+        let samples = scan(2 * SAMPLES_PER_DATAGRAM + 20, 1000);
+        let datagrams = encode_scan(3, 12.5, Convention::LD06, &samples);
+        assert_eq!(datagrams.len(), 3);
+        assert_eq!(
+            datagrams[0].len(),
+            HEADER_SIZE + SAMPLE_SIZE * SAMPLES_PER_DATAGRAM
+        );
+        assert_eq!(datagrams[2].len(), HEADER_SIZE + SAMPLE_SIZE * 20);
+
+        let (mut reassembler, mut received) = (Reassembler::default(), received());
+        for datagram in &datagrams[..2] {
+            reassembler.push(datagram, &mut received);
+            assert!(received.scan.is_none());
+        }
+        reassembler.push(&datagrams[2], &mut received);
+        assert_eq!(take(&mut received), Some((12.5, values(&samples))));
+        assert_eq!(received.convention, Some(Convention::LD06));
+        assert_eq!(received.stats.packets, 3);
+        assert_eq!(received.stats.checksum_errors, 0);
+        assert_eq!(received.stats.dropped_scans, 0);
+
+        // an empty scan is still sent
+        let datagrams = encode_scan(4, 13.0, Convention::ROBOT, &[]);
+        assert_eq!(datagrams.len(), 1);
+        reassembler.push(&datagrams[0], &mut received);
+        assert_eq!(take(&mut received), Some((13.0, vec![])));
+        assert_eq!(received.convention, Some(Convention::ROBOT));
+    }
+
+    #[test]
+    fn conventions() {
+        let custom = Convention::new(AngleUnit::Radians, true, 0.25);
+        for convention in [
+            Convention::XV11,
+            Convention::UST05LN,
+            Convention::ROBOT,
+            custom,
+        ] {
+            let datagram = &encode_scan(0, 0.0, convention, &scan(3, 0))[0];
+            let fragment = decode_fragment(datagram).unwrap();
+            assert_eq!(fragment.convention, convention);
+        }
+    }
+
+    #[test]
+    fn reordered_fragments() {
+        let (a, b) = (scan(320, 1000), scan(320, 2000));
+        let a_datagrams = encode_scan(10, 1.0, Convention::LD06, &a);
+        let b_datagrams = encode_scan(11, 1.1, Convention::LD06, &b);
+        let (mut reassembler, mut received) = (Reassembler::default(), received());
+        for datagram in [
+            &a_datagrams[2],
+            &b_datagrams[1],
+            &a_datagrams[0],
+            &b_datagrams[2],
+        ] {
+            reassembler.push(datagram, &mut received);
+        }
+        assert!(received.scan.is_none());
+        reassembler.push(&a_datagrams[1], &mut received);
+        assert_eq!(take(&mut received), Some((1.0, values(&a))));
+        reassembler.push(&b_datagrams[0], &mut received);
+        assert_eq!(take(&mut received), Some((1.1, values(&b))));
+        assert_eq!(received.stats.dropped_scans, 0);
+    }
+
+    #[test]
+    fn duplicated_fragments() {
+        let samples = scan(320, 1000);
+        let datagrams = encode_scan(0, 1.0, Convention::LD06, &samples);
+        let (mut reassembler, mut received) = (Reassembler::default(), received());
+        for datagram in datagrams.iter().flat_map(|d| [d, d]) {
+            reassembler.push(datagram, &mut received);
+        }
+        assert_eq!(take(&mut received), Some((1.0, values(&samples))));
+        reassembler.push(&datagrams[0], &mut received);
+        assert!(received.scan.is_none());
+        assert_eq!(received.stats.packets, 7);
+        assert_eq!(received.stats.checksum_errors, 0);
+        assert_eq!(received.stats.dropped_scans, 0);
+    }
+
+    #[test]
+    fn malformed_datagrams() {
+        let datagram = encode_scan(0, 1.0, Convention::LD06, &scan(10, 0)).remove(0);
+        let mut bad_magic = datagram.clone();
+        bad_magic[0] = b'X';
+        let mut bad_index = datagram.clone();
+        bad_index[24] = 1;
+        let mut bad_total = datagram.clone();
+        bad_total[30] = 5;
+        let malformed = [
+            datagram[..HEADER_SIZE - 1].to_vec(),
+            datagram[..datagram.len() - 3].to_vec(),
+            bad_magic,
+            bad_index,
+            bad_total,
+        ];
+        let (mut reassembler, mut received) = (Reassembler::default(), received());
+        for datagram in &malformed {
+            assert!(decode_fragment(datagram).is_none());
+            reassembler.push(datagram, &mut received);
+        }
+        assert_eq!(received.stats.packets, 5);
+        assert_eq!(received.stats.checksum_errors, 5);
+        assert!(received.scan.is_none() && received.convention.is_none());
+    }
+
+    #[test]
+    fn late_and_incomplete_scans() {
+        let datagrams = (0..4)
+            .map(|i| encode_scan(i, i as f64, Convention::LD06, &scan(320, 0)))
+            .collect::<Vec<_>>();
+        let (mut reassembler, mut received) = (Reassembler::default(), received());
+        // scan 2 is incomplete when scan 3 is
+        reassembler.push(&datagrams[2][0], &mut received);
+        for datagram in &datagrams[3] {
+            reassembler.push(datagram, &mut received);
+        }
+        assert_eq!(received.scan.take().map(|s| s.0), Some(3.0));
+        assert_eq!(received.stats.dropped_scans, 1);
+        // the rest of scan 2 and scan 1 come late
+        for datagram in datagrams[2][1..].iter().chain(&datagrams[1]) {
+            reassembler.push(datagram, &mut received);
+        }
+        assert!(received.scan.is_none());
+        assert_eq!(received.stats.dropped_scans, 2);
+        assert_eq!(received.stats.checksum_errors, 0);
+
+        // a restarted server counts from 0 again
+        for datagram in &encode_scan(0, 10.0, Convention::LD06, &scan(320, 0)) {
+            reassembler.push(datagram, &mut received);
+        }
+        assert_eq!(received.scan.take().map(|s| s.0), Some(10.0));
+        assert_eq!(received.stats.dropped_scans, 2);
+    }
+}
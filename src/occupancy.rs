@@ -0,0 +1,396 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::lidar::Sample;
+use crate::transform::{Mount, Point, Pose};
+
+fn log_odds(p: f64) -> f32 {
+    (p / (1.0 - p)).ln() as f32
+}
+
+/// Image format of an exported map.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageFormat {
+    Pgm,
+    Png,
+}
+
+/// Occupancy grid built from scans taken at known poses.
+///
+/// Each cell holds the log-odds of being occupied. Rays are traced with
+/// Bresenham's algorithm : crossed cells are made more likely free, the end
+/// cell more likely occupied.
+pub struct OccupancyGrid {
+    /// Size of a cell, in meters.
+    pub resolution: f64,
+    /// World position of the lower left corner of the grid.
+    pub origin: Point,
+    width: usize,
+    height: usize,
+    cells: Vec<f32>,
+    hit: f32,
+    miss: f32,
+    min: f32,
+    max: f32,
+    decay: f32,
+    /// Samples farther than this only clear cells, in meters.
+    pub max_range: f64,
+}
+
+impl OccupancyGrid {
+    /// A grid covering the rectangle from `min` to `max` (world frame, meters).
+    pub fn new(resolution: f64, min: Point, max: Point) -> OccupancyGrid {
+        let width = ((max.x - min.x) / resolution).ceil().max(1.0) as usize;
+        let height = ((max.y - min.y) / resolution).ceil().max(1.0) as usize;
+        OccupancyGrid {
+            resolution,
+            origin: min,
+            width,
+            height,
+            cells: vec![0.0; width * height],
+            hit: log_odds(0.7),
+            miss: log_odds(0.4),
+            min: log_odds(0.12),
+            max: log_odds(0.97),
+            decay: 1.0,
+            max_range: 8.0,
+        }
+    }
+
+    /// Probabilities used to update cells on a hit and on a miss.
+    pub fn with_update_probabilities(mut self, hit: f64, miss: f64) -> OccupancyGrid {
+        self.hit = log_odds(hit);
+        self.miss = log_odds(miss);
+        self
+    }
+
+    /// Bounds of the cell probabilities, so that cells can still change quickly.
+    pub fn with_clamping(mut self, min: f64, max: f64) -> OccupancyGrid {
+        self.min = log_odds(min);
+        self.max = log_odds(max);
+        self
+    }
+
+    /// Factor applied to all cells before each scan integration, in ]0, 1].
+    /// Lower values forget old observations faster.
+    pub fn with_decay(mut self, decay: f64) -> OccupancyGrid {
+        self.decay = decay as f32;
+        self
+    }
+
+    pub fn with_max_range(mut self, max_range: f64) -> OccupancyGrid {
+        self.max_range = max_range;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Cell containing a world point, if inside the grid.
+    pub fn cell_of(&self, p: &Point) -> Option<(usize, usize)> {
+        let (x, y) = self.cell_coords(p);
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            Some((x as usize, y as usize))
+        } else {
+            None
+        }
+    }
+
+    fn cell_coords(&self, p: &Point) -> (i64, i64) {
+        (
+            ((p.x - self.origin.x) / self.resolution).floor() as i64,
+            ((p.y - self.origin.y) / self.resolution).floor() as i64,
+        )
+    }
+
+    /// Occupancy probability of a cell, 0.5 when unknown.
+    pub fn probability(&self, x: usize, y: usize) -> f64 {
+        let l = self.cells[y * self.width + x] as f64;
+        1.0 - 1.0 / (1.0 + l.exp())
+    }
+
+    /// Occupancy probability at a world point.
+    pub fn probability_at(&self, p: &Point) -> Option<f64> {
+        self.cell_of(p).map(|(x, y)| self.probability(x, y))
+    }
+
+    fn update(&mut self, x: i64, y: i64, delta: f32) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            let cell = &mut self.cells[y as usize * self.width + x as usize];
+            *cell = (*cell + delta).clamp(self.min, self.max);
+        }
+    }
+
+    /// Integrates a scan taken by a sensor on `mount`, the robot being at `pose`.
+    pub fn integrate(&mut self, pose: &Pose, mount: &Mount, scan: &[Option<Sample>]) {
+        if self.decay < 1.0 {
+            for cell in self.cells.iter_mut() {
+                *cell *= self.decay;
+            }
+        }
+
+        let sensor = pose.transform(Point::new(mount.x, mount.y));
+        let start = self.cell_coords(&sensor);
+        for s in scan.iter().flatten() {
+            let p = mount.sample_to_point(s);
+            let range = Point::new(mount.x, mount.y).distance(&p);
+            if range <= 0.0 {
+                continue;
+            }
+            let hit = range <= self.max_range;
+            let p = if hit {
+                p
+            } else {
+                let k = self.max_range / range;
+                Point::new(mount.x + (p.x - mount.x) * k, mount.y + (p.y - mount.y) * k)
+            };
+            let end = self.cell_coords(&pose.transform(p));
+
+            let (hit_delta, miss_delta) = (self.hit, self.miss);
+            bresenham(start, end, |x, y| {
+                if (x, y) != end {
+                    self.update(x, y, miss_delta);
+                }
+            });
+            if hit {
+                self.update(end.0, end.1, hit_delta);
+            }
+        }
+    }
+
+    /// Grey levels in the ROS map_server convention : free is white, occupied
+    /// black, unknown grey. Rows go from top to bottom.
+    fn image(&self) -> Vec<u8> {
+        let mut image = Vec::with_capacity(self.width * self.height);
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let p = self.probability(x, y);
+                image.push(if p >= 0.65 {
+                    0
+                } else if p <= 0.196 {
+                    254
+                } else {
+                    205
+                });
+            }
+        }
+        image
+    }
+
+    pub fn write_pgm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P5\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.image())
+    }
+
+    pub fn write_png<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write_png(out, self.width as u32, self.height as u32, &self.image())
+    }
+
+    /// Writes the map_server YAML description of the map.
+    pub fn write_yaml<W: Write>(&self, out: &mut W, image: &str) -> io::Result<()> {
+        writeln!(out, "image: {}", image)?;
+        writeln!(out, "resolution: {}", self.resolution)?;
+        writeln!(out, "origin: [{}, {}, 0.0]", self.origin.x, self.origin.y)?;
+        writeln!(out, "negate: 0")?;
+        writeln!(out, "occupied_thresh: 0.65")?;
+        writeln!(out, "free_thresh: 0.196")
+    }
+
+    /// Saves the map in the ROS map_server format : `<path>.yaml` plus `<path>.pgm` or `<path>.png`.
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: ImageFormat) -> io::Result<()> {
+        let path = path.as_ref();
+        let image_path = path.with_extension(match format {
+            ImageFormat::Pgm => "pgm",
+            ImageFormat::Png => "png",
+        });
+        let mut image = BufWriter::new(File::create(&image_path)?);
+        match format {
+            ImageFormat::Pgm => self.write_pgm(&mut image)?,
+            ImageFormat::Png => self.write_png(&mut image)?,
+        }
+        image.flush()?;
+
+        let image_name = image_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let mut yaml = BufWriter::new(File::create(path.with_extension("yaml"))?);
+        self.write_yaml(&mut yaml, &image_name)?;
+        yaml.flush()
+    }
+}
+
+/// Calls `f` for each cell of the line from `a` to `b`, both included.
+fn bresenham<F: FnMut(i64, i64)>(a: (i64, i64), b: (i64, i64), mut f: F) {
+    let (mut x, mut y) = a;
+    let dx = (b.0 - x).abs();
+    let dy = -(b.1 - y).abs();
+    let sx = if x < b.0 { 1 } else { -1 };
+    let sy = if y < b.1 { 1 } else { -1 };
+    let mut err = dx + dy;
+    loop {
+        f(x, y);
+        if (x, y) == b {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn png_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+    out.write_all(&body)?;
+    out.write_all(&crc32(&body).to_be_bytes())
+}
+
+/// Writes an 8 bits grey PNG, with uncompressed deflate blocks.
+fn write_png<W: Write>(out: &mut W, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = vec![];
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+    png_chunk(out, b"IHDR", &header)?;
+
+    // each row starts with filter type 0
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(width as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(65_535).collect::<Vec<_>>();
+    for (i, block) in blocks.iter().enumerate() {
+        zlib.push((i == blocks.len() - 1) as u8);
+        let len = block.len() as u16;
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in &raw {
+        a = (a + byte as u32) % 65_521;
+        b = (b + a) % 65_521;
+    }
+    zlib.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    png_chunk(out, b"IDAT", &zlib)?;
+
+    png_chunk(out, b"IEND", &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Convention;
+    use std::convert::TryInto;
+    use std::f64::consts::PI;
+
+    fn at(grid: &OccupancyGrid, x: f64, y: f64) -> f64 {
+        grid.probability_at(&Point::new(x, y)).unwrap()
+    }
+
+    #[test]
+    fn ray_casting() {
+        let mut grid = OccupancyGrid::new(0.1, Point::new(-1.0, -1.0), Point::new(3.0, 1.0))
+            .with_max_range(2.0);
+        assert_eq!((grid.width(), grid.height()), (40, 20));
+        let mount = Mount::new(Convention::ROBOT);
+        let ray = |angle, distance| {
+            Some(Sample {
+                angle,
+                distance,
+                quality: 100,
+            })
+        };
+        // a hit 1.55 m ahead, a ray longer than max_range on the left
+        grid.integrate(
+            &Pose::default(),
+            &mount,
+            &[ray(0.0, 1550), ray(PI / 2.0, 5000)],
+        );
+
+        for i in 0..15 {
+            assert!(at(&grid, 0.05 + 0.1 * i as f64, 0.05) < 0.5);
+        }
+        assert!(at(&grid, 1.55, 0.05) > 0.5);
+        assert_eq!(at(&grid, 1.75, 0.05), 0.5);
+        assert_eq!(at(&grid, 0.05, -0.55), 0.5);
+        // the long ray only clears, up to the edge of the grid
+        assert!(at(&grid, 0.05, 0.95) < 0.5);
+        assert!(grid.probability_at(&Point::new(3.5, 0.0)).is_none());
+
+        // the same hit seen from another pose lands on another cell
+        grid.integrate(&Pose::new(1.0, 0.0, 0.0), &mount, &[ray(0.0, 1550)]);
+        assert!(at(&grid, 2.55, 0.05) > 0.5);
+        assert!(at(&grid, 1.55, 0.05) < at(&grid, 2.55, 0.05));
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn png() {
+        let mut out = vec![];
+        write_png(&mut out, 3, 2, &[0, 254, 205, 205, 254, 0]).unwrap();
+        assert_eq!(&out[..8], b"\x89PNG\r\n\x1a\n");
+
+        // chunks : length, type, data, CRC of type and data
+        let mut chunks = vec![];
+        let mut rest = &out[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let body = &rest[4..8 + len];
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(body));
+            chunks.push((body[..4].to_vec(), body[4..].to_vec()));
+            rest = &rest[12 + len..];
+        }
+        let kinds = chunks.iter().map(|(k, _)| &k[..]).collect::<Vec<_>>();
+        assert_eq!(kinds, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 0, 0, 0, 0]);
+        assert_eq!(&out[out.len() - 4..], [0xae, 0x42, 0x60, 0x82]);
+
+        // zlib header, one stored block, Adler-32 of the filtered rows
+        let raw = [0, 0, 254, 205, 0, 205, 254, 0];
+        let zlib = &chunks[1].1;
+        assert_eq!(&zlib[..7], [0x78, 0x01, 1, 8, 0, 0xf7, 0xff]);
+        assert_eq!(&zlib[7..15], raw);
+        assert_eq!(&zlib[15..], 0x0e60_0397u32.to_be_bytes());
+    }
+}
@@ -0,0 +1,524 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::lidar::{impl_drop, impl_iterator, Lidar, Sample};
+use crate::mcap::{is_mcap, McapReader};
+use crate::transform::{AngleUnit, Convention};
+
+const HEADER: &str = "# lidar_rd scans";
+
+/// A scan with its time, in seconds.
+pub type TimedScan = (f64, Vec<Option<Sample>>);
+
+/// Scans read from a recording.
+pub type Scans = Box<dyn Iterator<Item = Result<TimedScan, Box<dyn Error>>>>;
+
+type SharedScan = Arc<Mutex<Option<Vec<Option<Sample>>>>>;
+
+/// Output format of scans.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    /// Recording format, read back by `ScanReader` : a header line giving the
+    /// convention, then one line per scan, `<time> <angle>:<distance>,<quality> ...`,
+    /// with `-` for missing samples.
+    Scans,
+    /// One sample per line, scans separated by an empty line.
+    Text,
+    /// `time,angle,distance,quality` rows.
+    Csv,
+    /// One JSON object per scan and per line.
+    Json,
+}
+
+impl Format {
+    pub const NAMES: [(&'static str, Format); 4] = [
+        ("scans", Format::Scans),
+        ("text", Format::Text),
+        ("csv", Format::Csv),
+        ("json", Format::Json),
+    ];
+
+    /// Writes what comes before the first scan.
+    pub fn write_header<W: Write>(&self, out: &mut W, convention: Convention) -> io::Result<()> {
+        match self {
+            Format::Scans => {
+                write!(out, "{}", HEADER)?;
+                for (key, value) in convention_fields(&convention) {
+                    write!(out, " {}={}", key, value)?;
+                }
+                writeln!(out)
+            }
+            Format::Csv => writeln!(out, "time,angle,distance,quality"),
+            Format::Text | Format::Json => Ok(()),
+        }
+    }
+
+    /// Writes a scan received at `time` (seconds).
+    pub fn write_scan<W: Write>(
+        &self,
+        out: &mut W,
+        time: f64,
+        scan: &[Option<Sample>],
+    ) -> io::Result<()> {
+        match self {
+            Format::Scans => {
+                write!(out, "{:.6}", time)?;
+                for sample in scan {
+                    match sample {
+                        Some(s) => write!(out, " {}:{},{}", s.angle, s.distance, s.quality)?,
+                        None => write!(out, " -")?,
+                    }
+                }
+                writeln!(out)
+            }
+            Format::Text => {
+                for s in scan.iter().flatten() {
+                    writeln!(out, "{}", s)?;
+                }
+                writeln!(out)
+            }
+            Format::Csv => {
+                for s in scan.iter().flatten() {
+                    writeln!(out, "{:.6},{},{},{}", time, s.angle, s.distance, s.quality)?;
+                }
+                Ok(())
+            }
+            Format::Json => {
+                let samples = scan
+                    .iter()
+                    .map(|s| match s {
+                        Some(s) => format!("[{},{},{}]", s.angle, s.distance, s.quality),
+                        None => "null".to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                writeln!(
+                    out,
+                    "{{\"time\":{:.6},\"samples\":[{}]}}",
+                    time,
+                    samples.join(",")
+                )
+            }
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        Format::NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(s))
+            .map(|(_, f)| *f)
+            .ok_or_else(|| format!("unknown format '{}', expected scans, text, csv or json", s))
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = Format::NAMES
+            .iter()
+            .find(|(_, format)| format == self)
+            .unwrap()
+            .0;
+        write!(f, "{}", name)
+    }
+}
+
+/// `key=value` fields describing a convention : its name, or the unit,
+/// direction and zero of a custom one.
+pub(crate) fn convention_fields(convention: &Convention) -> Vec<(&'static str, String)> {
+    match convention.name() {
+        Some(name) => vec![("convention", name.to_string())],
+        None => vec![
+            (
+                "unit",
+                match convention.unit {
+                    AngleUnit::Degrees => "degrees",
+                    AngleUnit::Radians => "radians",
+                }
+                .to_string(),
+            ),
+            ("clockwise", convention.clockwise.to_string()),
+            ("zero", convention.zero.to_string()),
+        ],
+    }
+}
+
+/// Convention described by `convention_fields`, `field` giving the value of a key.
+pub(crate) fn parse_convention<'a, F>(field: F) -> Result<Convention, Box<dyn Error>>
+where
+    F: Fn(&str) -> Option<&'a str>,
+{
+    if let Some(name) = field("convention") {
+        return Convention::from_name(name)
+            .ok_or_else(|| format!("unknown convention: {}", name).into());
+    }
+    let unit = match field("unit").ok_or("missing convention")? {
+        "degrees" => AngleUnit::Degrees,
+        "radians" => AngleUnit::Radians,
+        unit => return Err(format!("unknown angle unit: {}", unit).into()),
+    };
+    let clockwise = field("clockwise")
+        .ok_or("missing convention direction")?
+        .parse()?;
+    let zero: f64 = field("zero").ok_or("missing convention zero")?.parse()?;
+    if !zero.is_finite() {
+        return Err(format!("invalid convention zero: {}", zero).into());
+    }
+    Ok(Convention::new(unit, clockwise, zero))
+}
+
+/// Writes scans in the recording format (`Format::Scans`).
+pub struct ScanWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> ScanWriter<W> {
+    pub fn new(mut out: W, convention: Convention) -> io::Result<ScanWriter<W>> {
+        Format::Scans.write_header(&mut out, convention)?;
+        Ok(ScanWriter { out })
+    }
+
+    pub fn write(&mut self, time: f64, scan: &[Option<Sample>]) -> io::Result<()> {
+        Format::Scans.write_scan(&mut self.out, time, scan)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl ScanWriter<io::BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, convention: Convention) -> io::Result<Self> {
+        ScanWriter::new(io::BufWriter::new(File::create(path)?), convention)
+    }
+}
+
+/// Reads back a recording, as `(time, scan)` pairs.
+pub struct ScanReader<R: BufRead> {
+    lines: io::Lines<R>,
+    convention: Convention,
+}
+
+impl<R: BufRead> ScanReader<R> {
+    pub fn new(input: R) -> Result<ScanReader<R>, Box<dyn Error>> {
+        let mut lines = input.lines();
+        let header = lines.next().ok_or("empty recording")??;
+        let fields = header
+            .strip_prefix(HEADER)
+            .ok_or("not a lidar_rd recording")?
+            .split_whitespace()
+            .filter_map(|field| field.split_once('='))
+            .collect::<Vec<_>>();
+        let convention =
+            parse_convention(|key| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v))?;
+        Ok(ScanReader { lines, convention })
+    }
+
+    /// Convention of the recorded angles.
+    pub fn convention(&self) -> Convention {
+        self.convention
+    }
+}
+
+impl ScanReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        ScanReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+fn parse_scan(line: &str) -> Result<TimedScan, Box<dyn Error>> {
+    let mut fields = line.split_whitespace();
+    let time = fields.next().ok_or("empty line")?.parse()?;
+    let mut scan = vec![];
+    for field in fields {
+        if field == "-" {
+            scan.push(None);
+            continue;
+        }
+        let (angle, rest) = field
+            .split_once(':')
+            .ok_or_else(|| format!("invalid sample: {}", field))?;
+        let (distance, quality) = rest
+            .split_once(',')
+            .ok_or_else(|| format!("invalid sample: {}", field))?;
+        scan.push(Some(Sample {
+            angle: angle.parse()?,
+            distance: distance.parse()?,
+            quality: quality.parse()?,
+        }));
+    }
+    Ok((time, scan))
+}
+
+impl<R: BufRead> Iterator for ScanReader<R> {
+    type Item = Result<TimedScan, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if !line.trim().is_empty() && !line.starts_with('#') {
+                return Some(parse_scan(&line));
+            }
+        }
+    }
+}
+
+/// Opens a recording in the `Format::Scans` format or MCAP (see
+/// `mcap::McapWriter`), with the convention of its scans.
+pub fn open_recording<P: AsRef<Path>>(path: P) -> Result<(Convention, Scans), Box<dyn Error>> {
+    if is_mcap(&path)? {
+        let reader = McapReader::open(path)?;
+        Ok((reader.convention(), Box::new(reader.scans())))
+    } else {
+        let reader = ScanReader::open(path)?;
+        Ok((reader.convention(), Box::new(reader)))
+    }
+}
+
+/// Plays a recording back as a lidar, with the recorded timing.
+pub struct ReplayLidar {
+    path: String,
+    convention: Convention,
+    speed: f64,
+    looping: bool,
+    tx_cmd: Option<mpsc::Sender<()>>,
+    join_handle: Option<thread::JoinHandle<()>>,
+    running: Arc<AtomicBool>,
+    data: SharedScan,
+}
+
+impl ReplayLidar {
+    /// Checks the recording header, the scans are read once started. MCAP
+    /// recordings are supported too.
+    pub fn new(path: &str) -> Result<ReplayLidar, Box<dyn Error>> {
+        let (convention, _) = open_recording(path)?;
+        Ok(ReplayLidar {
+            path: path.into(),
+            convention,
+            speed: 1.0,
+            looping: false,
+            tx_cmd: None,
+            join_handle: None,
+            running: Arc::new(AtomicBool::new(false)),
+            data: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Playback speed factor, 1 by default. 0 plays as fast as scans are consumed.
+    pub fn with_speed(mut self, speed: f64) -> ReplayLidar {
+        self.speed = speed;
+        self
+    }
+
+    /// Starts over at the end of the recording.
+    pub fn with_loop(mut self, looping: bool) -> ReplayLidar {
+        self.looping = looping;
+        self
+    }
+}
+
+fn replay_run(
+    path: String,
+    speed: f64,
+    looping: bool,
+    rx_cmd: mpsc::Receiver<()>,
+    running: Arc<AtomicBool>,
+    data: SharedScan,
+) {
+    'replay: loop {
+        match rx_cmd.try_recv() {
+            Ok(_) | Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
+        }
+        let reader = match open_recording(&path) {
+            Ok((_, reader)) => reader,
+            Err(e) => {
+                eprintln!("failed to open {} : {}", path, e);
+                break;
+            }
+        };
+        let start = Instant::now();
+        let mut first = None;
+        for item in reader {
+            let (time, scan) = match item {
+                Ok(item) => item,
+                Err(e) => {
+                    eprintln!("invalid scan in {} : {}", path, e);
+                    continue;
+                }
+            };
+            let first = *first.get_or_insert(time);
+            loop {
+                match rx_cmd.try_recv() {
+                    Ok(_) | Err(TryRecvError::Disconnected) => break 'replay,
+                    Err(TryRecvError::Empty) => {}
+                }
+                let ready = if speed > 0.0 {
+                    start.elapsed().as_secs_f64() * speed >= time - first
+                } else {
+                    data.lock().unwrap().is_none()
+                };
+                if ready {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            *data.lock().unwrap() = Some(scan);
+        }
+        if first.is_none() {
+            eprintln!("no scan in {}", path);
+            break;
+        }
+        if !looping {
+            break;
+        }
+    }
+    running.store(false, Ordering::SeqCst);
+}
+
+impl Lidar for ReplayLidar {
+    fn get_scan(&self) -> Option<Vec<Option<Sample>>> {
+        self.data.lock().unwrap().take()
+    }
+
+    fn start(&mut self) -> Result<(), Box<dyn Error>> {
+        let (tx_cmd, rx_cmd) = mpsc::channel();
+        self.tx_cmd = Some(tx_cmd);
+        self.running.store(true, Ordering::SeqCst);
+        let (path, speed, looping) = (self.path.clone(), self.speed, self.looping);
+        let (running, data) = (self.running.clone(), self.data.clone());
+        self.join_handle = Some(thread::spawn(move || {
+            replay_run(path, speed, looping, rx_cmd, running, data)
+        }));
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(tx) = self.tx_cmd.take() {
+            let _ = tx.send(());
+        }
+        if let Some(handle) = self.join_handle.take() {
+            handle.join().expect("failed to join thread");
+        }
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    /// `false` once the whole recording was played, and its last scan read.
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst) || self.data.lock().unwrap().is_some()
+    }
+
+    fn convention(&self) -> Convention {
+        self.convention
+    }
+}
+
+impl_iterator!(ReplayLidar);
+impl_drop!(ReplayLidar);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn sample(angle: f64) -> Option<Sample> {
+        Some(Sample {
+            angle,
+            distance: 1000,
+            quality: 10,
+        })
+    }
+
+    #[test]
+    fn round_trip() {
+        let custom = Convention::new(AngleUnit::Radians, true, 0.25);
+        for &convention in &[Convention::LD06, Convention::ROBOT, custom] {
+            let mut writer = ScanWriter::new(vec![], convention).unwrap();
+            writer.write(1.5, &[sample(0.5), None]).unwrap();
+            let recording = writer.into_inner();
+            let mut reader = ScanReader::new(&recording[..]).unwrap();
+            assert_eq!(reader.convention(), convention);
+            let (time, scan) = reader.next().unwrap().unwrap();
+            assert_eq!(time, 1.5);
+            assert_eq!(scan.len(), 2);
+            assert_eq!(
+                scan[0].map(|s| (s.angle, s.distance, s.quality)),
+                Some((0.5, 1000, 10))
+            );
+            assert!(scan[1].is_none());
+            assert!(reader.next().is_none());
+        }
+
+        let invalid = [
+            "",
+            "time angle distance\n",
+            "# lidar_rd scans convention=lds\n",
+            "# lidar_rd scans unit=grads clockwise=true zero=0\n",
+            "# lidar_rd scans unit=radians clockwise=true zero=NaN\n",
+            "# lidar_rd scans unit=radians zero=0\n",
+        ];
+        for header in &invalid {
+            assert!(ScanReader::new(header.as_bytes()).is_err(), "{}", header);
+        }
+    }
+
+    fn recording(name: &str, scans: usize) -> String {
+        let path = env::temp_dir().join(format!("lidar_rd_{}_{}.txt", name, std::process::id()));
+        let mut writer = ScanWriter::create(&path, Convention::LD06).unwrap();
+        for i in 0..scans {
+            writer.write(i as f64 * 0.1, &[sample(i as f64)]).unwrap();
+        }
+        writer.flush().unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn replay_loop() {
+        let path = recording("replay_loop", 2);
+        let mut lidar = ReplayLidar::new(&path)
+            .unwrap()
+            .with_speed(0.0)
+            .with_loop(true);
+        lidar.start().unwrap();
+        let angles = (&mut lidar)
+            .take(5)
+            .map(|scan| scan[0].unwrap().angle)
+            .collect::<Vec<_>>();
+        assert_eq!(angles, [0.0, 1.0, 0.0, 1.0, 0.0]);
+        lidar.stop();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replay_empty_loop() {
+        let path = recording("replay_empty_loop", 0);
+        let mut lidar = ReplayLidar::new(&path).unwrap().with_loop(true);
+        lidar.start().unwrap();
+        let start = Instant::now();
+        while lidar.is_running() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+        lidar.stop();
+        fs::remove_file(path).unwrap();
+    }
+}
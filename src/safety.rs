@@ -0,0 +1,244 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use std::f64::consts::PI;
+
+use crate::lidar::Sample;
+use crate::map::Polygon;
+use crate::transform::{Mount, Point};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FieldKind {
+    /// Something is close : slow down.
+    Warning,
+    /// Something is too close : stop.
+    Protective,
+}
+
+/// A monitored area, in the robot frame.
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub name: String,
+    pub kind: FieldKind,
+    pub polygon: Polygon,
+}
+
+impl Field {
+    pub fn new(name: &str, kind: FieldKind, polygon: Polygon) -> Field {
+        Field {
+            name: name.into(),
+            kind,
+            polygon,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FieldEvent {
+    /// Name of the field.
+    pub field: String,
+    pub kind: FieldKind,
+    /// `true` when the field becomes violated, `false` when it is cleared.
+    pub violated: bool,
+    /// Number of points inside the field in the last scan.
+    pub points: usize,
+}
+
+struct FieldState {
+    violated: bool,
+    // consecutive scans disagreeing with `violated`
+    streak: usize,
+    points: usize,
+}
+
+/// Watches fields around the robot, like the protective fields of industrial
+/// safety scanners.
+///
+/// A field is violated when at least `min_points` samples fall inside it during
+/// `debounce` consecutive scans, and cleared after `debounce` consecutive scans
+/// below that.
+pub struct SafetyMonitor {
+    mount: Mount,
+    fields: Vec<Field>,
+    states: Vec<FieldState>,
+    pub min_points: usize,
+    pub debounce: usize,
+    subscribers: Vec<Sender<FieldEvent>>,
+}
+
+impl SafetyMonitor {
+    pub fn new(mount: Mount, min_points: usize, debounce: usize) -> SafetyMonitor {
+        SafetyMonitor {
+            mount,
+            fields: vec![],
+            states: vec![],
+            min_points,
+            debounce,
+            subscribers: vec![],
+        }
+    }
+
+    pub fn with_field(mut self, field: Field) -> SafetyMonitor {
+        self.add_field(field);
+        self
+    }
+
+    pub fn add_field(&mut self, field: Field) {
+        self.fields.push(field);
+        self.states.push(FieldState {
+            violated: false,
+            streak: 0,
+            points: 0,
+        });
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Returns a receiver getting every event emitted by `update`.
+    pub fn subscribe(&mut self) -> Receiver<FieldEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    pub fn is_violated(&self, name: &str) -> bool {
+        self.fields
+            .iter()
+            .zip(&self.states)
+            .any(|(f, s)| f.name == name && s.violated)
+    }
+
+    /// `true` if any field of this kind is violated.
+    pub fn any_violated(&self, kind: FieldKind) -> bool {
+        self.fields
+            .iter()
+            .zip(&self.states)
+            .any(|(f, s)| f.kind == kind && s.violated)
+    }
+
+    /// Checks a new scan and returns the field state changes.
+    pub fn update(&mut self, scan: &[Option<Sample>]) -> Vec<FieldEvent> {
+        let points = scan
+            .iter()
+            .flatten()
+            .map(|s| self.mount.sample_to_point(s))
+            .collect::<Vec<_>>();
+
+        let mut events = vec![];
+        for (field, state) in self.fields.iter().zip(self.states.iter_mut()) {
+            state.points = points.iter().filter(|p| field.polygon.contains(p)).count();
+            let violated = state.points >= self.min_points.max(1);
+            if violated == state.violated {
+                state.streak = 0;
+                continue;
+            }
+            state.streak += 1;
+            if state.streak >= self.debounce.max(1) {
+                state.violated = violated;
+                state.streak = 0;
+                events.push(FieldEvent {
+                    field: field.name.clone(),
+                    kind: field.kind,
+                    violated,
+                    points: state.points,
+                });
+            }
+        }
+
+        self.subscribers
+            .retain(|tx| events.iter().all(|e| tx.send(e.clone()).is_ok()));
+        events
+    }
+}
+
+/// Distance to the closest point of each of `sectors` equal angular sectors
+/// around the robot, `None` for empty sectors. Points are in the robot frame,
+/// sector 0 is centered on the front and the next ones go counter-clockwise.
+pub fn sector_minima(points: &[Point], sectors: usize) -> Vec<Option<f64>> {
+    let sectors = sectors.max(1);
+    let width = 2.0 * PI / sectors as f64;
+    let mut minima: Vec<Option<f64>> = vec![None; sectors];
+    for p in points {
+        let i = ((p.angle() / width).round() as i64).rem_euclid(sectors as i64) as usize;
+        let d = p.norm();
+        if minima[i].map_or(true, |m| d < m) {
+            minima[i] = Some(d);
+        }
+    }
+    minima
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Convention;
+
+    /// Three samples `distance` mm in front of the robot.
+    fn obstacle(distance: u16) -> Vec<Option<Sample>> {
+        [-0.05, 0.0, 0.05]
+            .iter()
+            .map(|&angle| {
+                Some(Sample {
+                    angle,
+                    distance,
+                    quality: 100,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn debouncing() {
+        let rectangle = |x, y: f64| Polygon::rectangle(Point::new(0.0, -y), Point::new(x, y));
+        let mut monitor = SafetyMonitor::new(Mount::new(Convention::ROBOT), 2, 3)
+            .with_field(Field::new(
+                "stop",
+                FieldKind::Protective,
+                rectangle(0.5, 0.3),
+            ))
+            .with_field(Field::new("slow", FieldKind::Warning, rectangle(1.0, 0.5)));
+        let events = monitor.subscribe();
+
+        // a one scan glitch does not count
+        assert!(monitor.update(&obstacle(300)).is_empty());
+        assert!(monitor.update(&obstacle(300)).is_empty());
+        assert!(monitor.update(&obstacle(2000)).is_empty());
+        assert!(monitor.update(&obstacle(300)).is_empty());
+        assert!(monitor.update(&obstacle(300)).is_empty());
+        assert!(!monitor.is_violated("stop"));
+
+        let violated = monitor.update(&obstacle(300));
+        assert_eq!(violated.len(), 2);
+        assert!(violated.iter().all(|e| e.violated && e.points == 3));
+        assert!(monitor.is_violated("stop") && monitor.is_violated("slow"));
+
+        // moving away clears the protective field only, again after 3 scans
+        for _ in 0..2 {
+            assert!(monitor.update(&obstacle(800)).is_empty());
+            assert!(monitor.any_violated(FieldKind::Protective));
+        }
+        let cleared = monitor.update(&obstacle(800));
+        assert_eq!(cleared.len(), 1);
+        assert_eq!(
+            (cleared[0].field.as_str(), cleared[0].violated),
+            ("stop", false)
+        );
+        assert!(!monitor.any_violated(FieldKind::Protective));
+        assert!(monitor.any_violated(FieldKind::Warning));
+
+        assert_eq!(events.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn sectors() {
+        let points = [
+            Point::new(1.0, 0.1),
+            Point::new(0.5, -0.05),
+            Point::new(-2.0, 0.0),
+        ];
+        let minima = sector_minima(&points, 4);
+        assert!((minima[0].unwrap() - 0.5025).abs() < 1e-3);
+        assert_eq!((minima[1], minima[2], minima[3]), (None, Some(2.0), None));
+    }
+}
@@ -0,0 +1,214 @@
+use crate::lidar::Sample;
+use crate::transform::{normalize_angle, Mount, Point};
+
+/// Parameters of the adaptive breakpoint detector.
+#[derive(Copy, Clone, Debug)]
+pub struct SegmentationConfig {
+    /// Worst incidence angle (radians) of a surface still considered continuous.
+    pub lambda: f64,
+    /// Range noise of the sensor, in meters.
+    pub sigma: f64,
+    /// Clusters with fewer points are dropped.
+    pub min_points: usize,
+    /// Number of consecutive missing samples tolerated inside a cluster.
+    pub max_gap: usize,
+}
+
+impl Default for SegmentationConfig {
+    fn default() -> SegmentationConfig {
+        SegmentationConfig {
+            lambda: 10f64.to_radians(),
+            sigma: 0.01,
+            min_points: 3,
+            max_gap: 1,
+        }
+    }
+}
+
+/// Axis aligned bounding box, in the robot frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl BoundingBox {
+    pub fn width(&self) -> f64 {
+        self.max.x - self.min.x
+    }
+
+    pub fn height(&self) -> f64 {
+        self.max.y - self.min.y
+    }
+}
+
+/// A group of consecutive samples belonging to the same object.
+#[derive(Clone, Debug)]
+pub struct Cluster {
+    /// Indices of the samples in the scan.
+    pub indices: Vec<usize>,
+    /// Samples as points in the robot frame.
+    pub points: Vec<Point>,
+    pub centroid: Point,
+    /// Distance between the first and the last point, i.e. the visible width of the object.
+    pub extent: f64,
+    pub bbox: BoundingBox,
+}
+
+impl Cluster {
+    fn new(indices: Vec<usize>, points: Vec<Point>) -> Cluster {
+        let n = points.len() as f64;
+        let centroid = Point::new(
+            points.iter().map(|p| p.x).sum::<f64>() / n,
+            points.iter().map(|p| p.y).sum::<f64>() / n,
+        );
+        let extent = points[0].distance(&points[points.len() - 1]);
+        let bbox = points.iter().fold(
+            BoundingBox {
+                min: points[0],
+                max: points[0],
+            },
+            |b, p| BoundingBox {
+                min: Point::new(b.min.x.min(p.x), b.min.y.min(p.y)),
+                max: Point::new(b.max.x.max(p.x), b.max.y.max(p.y)),
+            },
+        );
+        Cluster {
+            indices,
+            points,
+            centroid,
+            extent,
+            bbox,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+/// `true` if two consecutive samples belong to different objects.
+///
+/// Adaptive breakpoint detector (Borges & Aldon) : the largest allowed jump
+/// grows with the range and the angular step.
+fn is_breakpoint(s1: &Sample, s2: &Sample, mount: &Mount, config: &SegmentationConfig) -> bool {
+    let c = mount.convention;
+    let dphi = normalize_angle(c.to_sensor_angle(s2.angle) - c.to_sensor_angle(s1.angle)).abs();
+    if dphi >= config.lambda {
+        return true;
+    }
+    let r = s1.distance as f64 / 1000.0;
+    let d_max = r * dphi.sin() / (config.lambda - dphi).sin() + 3.0 * config.sigma;
+    mount
+        .sample_to_point(s1)
+        .distance(&mount.sample_to_point(s2))
+        > d_max
+}
+
+/// Splits a scan into clusters of points.
+pub fn segment(
+    scan: &[Option<Sample>],
+    mount: &Mount,
+    config: &SegmentationConfig,
+) -> Vec<Cluster> {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    let mut current: Vec<usize> = vec![];
+    let mut last: Option<usize> = None;
+
+    for (i, sample) in scan.iter().enumerate() {
+        let s = match sample {
+            Some(s) => s,
+            None => continue,
+        };
+        if let Some(l) = last {
+            let gap = i - l - 1;
+            if gap > config.max_gap || is_breakpoint(scan[l].as_ref().unwrap(), s, mount, config) {
+                groups.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(i);
+        last = Some(i);
+    }
+    if !current.is_empty() {
+        groups.push(current);
+    }
+
+    // on a full turn, the first and last groups may be the same object
+    if groups.len() > 1 {
+        let first = groups[0][0];
+        let last = *groups[groups.len() - 1].last().unwrap();
+        let gap = first + scan.len() - last - 1;
+        let (s1, s2) = (scan[last].as_ref().unwrap(), scan[first].as_ref().unwrap());
+        if gap <= config.max_gap && !is_breakpoint(s1, s2, mount, config) {
+            let mut tail = groups.pop().unwrap();
+            tail.append(&mut groups[0]);
+            groups[0] = tail;
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|g| g.len() >= config.min_points.max(1))
+        .map(|indices| {
+            let points = indices
+                .iter()
+                .map(|&i| mount.sample_to_point(scan[i].as_ref().unwrap()))
+                .collect();
+            Cluster::new(indices, points)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Convention;
+
+    #[test]
+    fn clusters() {
+        // LD06 samples every degree
+        let mut scan = vec![None; 360];
+        let mut put = |range: std::ops::Range<usize>, distance| {
+            for i in range {
+                scan[i] = Some(Sample {
+                    angle: i as f64,
+                    distance,
+                    quality: 100,
+                });
+            }
+        };
+        put(355..360, 1500);
+        put(0..5, 1500);
+        put(10..20, 1000);
+        put(20..30, 2000);
+        put(100..101, 1000);
+        put(200..205, 1000);
+        put(206..210, 1000);
+
+        let mount = Mount::new(Convention::LD06);
+        let clusters = segment(&scan, &mount, &SegmentationConfig::default());
+        let indices = clusters
+            .iter()
+            .map(|c| c.indices.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            indices,
+            [
+                (355..360).chain(0..5).collect::<Vec<_>>(),
+                (10..20).collect(),
+                (20..30).collect(),
+                (200..205).chain(206..210).collect(),
+            ]
+        );
+
+        let wrapped = &clusters[0];
+        assert!((wrapped.centroid.x - 1.5).abs() < 0.01 && wrapped.centroid.y.abs() < 0.02);
+        let expected = 2.0 * 1.5 * (4.5f64).to_radians().sin();
+        assert!((wrapped.extent - expected).abs() < 0.005);
+        assert!(wrapped.bbox.height() > 0.2 && wrapped.bbox.width() < 0.02);
+    }
+}
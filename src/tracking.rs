@@ -0,0 +1,319 @@
+use crate::linalg::{add, identity, invert, mul, sub, transpose, Matrix};
+use crate::map::StaticMap;
+use crate::segmentation::Cluster;
+use crate::transform::{Point, Pose};
+
+/// Parameters of the tracker.
+#[derive(Clone, Debug)]
+pub struct TrackerConfig {
+    /// Standard deviation of the measured positions, in meters.
+    pub measurement_sigma: f64,
+    /// Acceleration noise of the constant velocity model, in m/s².
+    pub acceleration_sigma: f64,
+    /// Standard deviation of the unknown velocity of new tracks, in m/s.
+    pub initial_velocity_std: f64,
+    /// Mahalanobis distance gate for associating a measurement to a track.
+    pub gate: f64,
+    /// Euclidean distance gate, in meters.
+    pub max_distance: f64,
+    /// Number of updates before a track is confirmed.
+    pub confirm_hits: u32,
+    /// A track is dropped after this many scans without measurement.
+    pub max_missed: u32,
+    /// Clusters wider than this are not robots, in meters.
+    pub max_extent: f64,
+    /// Measurements explained by this map are ignored.
+    pub static_map: Option<StaticMap>,
+    /// Distance under which a measurement is explained by the static map, in meters.
+    pub static_tolerance: f64,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> TrackerConfig {
+        TrackerConfig {
+            measurement_sigma: 0.03,
+            acceleration_sigma: 2.0,
+            initial_velocity_std: 1.0,
+            gate: 3.0,
+            max_distance: 0.5,
+            confirm_hits: 3,
+            max_missed: 5,
+            max_extent: 0.5,
+            static_map: None,
+            static_tolerance: 0.05,
+        }
+    }
+}
+
+/// A tracked object, filtered by a constant velocity Kalman filter.
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub id: u32,
+    pub position: Point,
+    /// Velocity, in m/s.
+    pub velocity: Point,
+    /// Covariance of `(x, y, vx, vy)`.
+    pub covariance: [[f64; 4]; 4],
+    /// Time of the first measurement, in seconds.
+    pub created: f64,
+    /// Time of the last measurement, in seconds.
+    pub updated: f64,
+    pub hits: u32,
+    pub missed: u32,
+    confirmed: bool,
+}
+
+impl Track {
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed
+    }
+
+    /// Time since the track was created, in seconds.
+    pub fn lifetime(&self) -> f64 {
+        self.updated - self.created
+    }
+
+    fn state(&self) -> [[f64; 1]; 4] {
+        [
+            [self.position.x],
+            [self.position.y],
+            [self.velocity.x],
+            [self.velocity.y],
+        ]
+    }
+
+    fn set_state(&mut self, x: &[[f64; 1]; 4]) {
+        self.position = Point::new(x[0][0], x[1][0]);
+        self.velocity = Point::new(x[2][0], x[3][0]);
+    }
+}
+
+const H: Matrix<2, 4> = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]];
+
+/// Multi-target tracker.
+///
+/// Measurements (cluster centroids) are associated to the predicted tracks
+/// with a Mahalanobis gate, closest pairs first. Unassociated measurements
+/// start new tracks.
+pub struct Tracker {
+    pub config: TrackerConfig,
+    tracks: Vec<Track>,
+    next_id: u32,
+    last_time: Option<f64>,
+}
+
+impl Tracker {
+    pub fn new(config: TrackerConfig) -> Tracker {
+        Tracker {
+            config,
+            tracks: vec![],
+            next_id: 0,
+            last_time: None,
+        }
+    }
+
+    /// All tracks, including tentative ones.
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn confirmed_tracks(&self) -> impl Iterator<Item = &Track> {
+        self.tracks.iter().filter(|t| t.confirmed)
+    }
+
+    /// Updates the tracks with the clusters of a scan taken at time `t` (seconds)
+    /// from the robot `pose`. Tracks are kept in the world frame.
+    pub fn update_clusters(&mut self, t: f64, pose: &Pose, clusters: &[Cluster]) -> &[Track] {
+        let measurements = clusters
+            .iter()
+            .filter(|c| c.extent <= self.config.max_extent)
+            .map(|c| pose.transform(c.centroid))
+            .collect::<Vec<_>>();
+        self.update(t, &measurements)
+    }
+
+    /// Updates the tracks with positions measured at time `t` (seconds).
+    pub fn update(&mut self, t: f64, measurements: &[Point]) -> &[Track] {
+        let dt = self.last_time.map_or(0.0, |last| (t - last).max(0.0));
+        self.last_time = Some(t);
+
+        let measurements = measurements
+            .iter()
+            .filter(|m| match &self.config.static_map {
+                Some(map) => !map.explains(m, self.config.static_tolerance),
+                None => true,
+            })
+            .copied()
+            .collect::<Vec<_>>();
+
+        for track in self.tracks.iter_mut() {
+            predict(track, dt, self.config.acceleration_sigma);
+        }
+
+        // candidate pairs within the gates, closest first
+        let r = self.config.measurement_sigma.powi(2);
+        let mut candidates = vec![];
+        for (k, track) in self.tracks.iter().enumerate() {
+            let s = add(
+                &mul(&mul(&H, &track.covariance), &transpose(&H)),
+                &[[r, 0.0], [0.0, r]],
+            );
+            let si = match invert(&s) {
+                Some(si) => si,
+                None => continue,
+            };
+            for (m, p) in measurements.iter().enumerate() {
+                let y = [p.x - track.position.x, p.y - track.position.y];
+                let d2 = y[0] * (si[0][0] * y[0] + si[0][1] * y[1])
+                    + y[1] * (si[1][0] * y[0] + si[1][1] * y[1]);
+                if d2.sqrt() <= self.config.gate
+                    && p.distance(&track.position) <= self.config.max_distance
+                {
+                    candidates.push((d2, k, m));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut track_used = vec![false; self.tracks.len()];
+        let mut measurement_used = vec![false; measurements.len()];
+        for (_, k, m) in candidates {
+            if !track_used[k] && !measurement_used[m] {
+                track_used[k] = true;
+                measurement_used[m] = true;
+                correct(&mut self.tracks[k], &measurements[m], r);
+                let track = &mut self.tracks[k];
+                track.updated = t;
+                track.hits += 1;
+                track.missed = 0;
+                if track.hits >= self.config.confirm_hits {
+                    track.confirmed = true;
+                }
+            }
+        }
+
+        for (track, used) in self.tracks.iter_mut().zip(track_used) {
+            if !used {
+                track.missed += 1;
+            }
+        }
+        let max_missed = self.config.max_missed;
+        self.tracks.retain(|t| t.missed <= max_missed);
+
+        for (m, used) in measurements.iter().zip(measurement_used) {
+            if !used {
+                let track = self.new_track(t, *m);
+                self.tracks.push(track);
+            }
+        }
+
+        &self.tracks
+    }
+
+    fn new_track(&mut self, t: f64, p: Point) -> Track {
+        let r = self.config.measurement_sigma.powi(2);
+        let v = self.config.initial_velocity_std.powi(2);
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        Track {
+            id,
+            position: p,
+            velocity: Point::default(),
+            covariance: [
+                [r, 0.0, 0.0, 0.0],
+                [0.0, r, 0.0, 0.0],
+                [0.0, 0.0, v, 0.0],
+                [0.0, 0.0, 0.0, v],
+            ],
+            created: t,
+            updated: t,
+            hits: 1,
+            missed: 0,
+            confirmed: self.config.confirm_hits <= 1,
+        }
+    }
+}
+
+fn predict(track: &mut Track, dt: f64, acceleration_sigma: f64) {
+    let f = [
+        [1.0, 0.0, dt, 0.0],
+        [0.0, 1.0, 0.0, dt],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+    let q = acceleration_sigma.powi(2);
+    let (a, b, c) = (dt.powi(4) / 4.0 * q, dt.powi(3) / 2.0 * q, dt.powi(2) * q);
+    let noise = [
+        [a, 0.0, b, 0.0],
+        [0.0, a, 0.0, b],
+        [b, 0.0, c, 0.0],
+        [0.0, b, 0.0, c],
+    ];
+    let x = mul(&f, &track.state());
+    track.set_state(&x);
+    track.covariance = add(&mul(&mul(&f, &track.covariance), &transpose(&f)), &noise);
+}
+
+fn correct(track: &mut Track, p: &Point, r: f64) {
+    let ht = transpose(&H);
+    let s = add(
+        &mul(&mul(&H, &track.covariance), &ht),
+        &[[r, 0.0], [0.0, r]],
+    );
+    let si = match invert(&s) {
+        Some(si) => si,
+        None => return,
+    };
+    let k = mul(&mul(&track.covariance, &ht), &si);
+    let y = [[p.x - track.position.x], [p.y - track.position.y]];
+    let x = add(&track.state(), &mul(&k, &y));
+    track.set_state(&x);
+    track.covariance = mul(&sub(&identity(), &mul(&k, &H)), &track.covariance);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn velocity_converges() {
+        let config = TrackerConfig {
+            static_map: Some(
+                StaticMap::new().with_segment(Point::new(3.0, -1.0), Point::new(3.0, 1.0)),
+            ),
+            ..TrackerConfig::default()
+        };
+        let mut tracker = Tracker::new(config);
+        // a robot going at (0.5, -0.2) m/s, a still one, and the wall, every 0.1 s
+        for i in 0..30 {
+            let t = i as f64 * 0.1;
+            let noise = if i % 2 == 0 { 0.01 } else { -0.01 };
+            let moving = Point::new(0.5 * t + noise, 1.0 - 0.2 * t);
+            let still = Point::new(-1.0, -1.0 + noise);
+            tracker.update(t, &[moving, still, Point::new(3.0, 0.0)]);
+            if i == 1 {
+                assert_eq!(tracker.confirmed_tracks().count(), 0);
+            }
+        }
+
+        let tracks = tracker.confirmed_tracks().collect::<Vec<_>>();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!((tracks[0].id, tracks[1].id), (0, 1));
+        assert!(tracks[0].velocity.distance(&Point::new(0.5, -0.2)) < 0.05);
+        assert!(tracks[1].velocity.norm() < 0.05);
+        assert!((tracks[0].lifetime() - 2.9).abs() < 1e-9);
+        assert!(tracks[0].covariance[2][2] < 0.1);
+    }
+
+    #[test]
+    fn lost_tracks() {
+        let mut tracker = Tracker::new(TrackerConfig::default());
+        tracker.update(0.0, &[Point::new(1.0, 0.0)]);
+        for i in 1..=5 {
+            tracker.update(i as f64 * 0.1, &[]);
+            assert_eq!(tracker.tracks().len(), 1);
+        }
+        tracker.update(0.6, &[]);
+        assert!(tracker.tracks().is_empty());
+    }
+}
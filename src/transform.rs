@@ -0,0 +1,566 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::lidar::Sample;
+
+/// Unit of the `angle` field of the samples produced by a driver.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AngleUnit {
+    Degrees,
+    Radians,
+}
+
+/// Describes how a driver reports `Sample::angle`.
+///
+/// The LD06 reports clockwise degrees starting at the sensor front, the XV11
+/// clockwise radians shifted by half a turn, and the UST05LN counter-clockwise
+/// radians centered on its front. `Convention` brings all of them back to the
+/// sensor frame: counter-clockwise radians, zero along the sensor front.
+///
+/// Predefined conventions are told apart by their name, so that `ROBOT` differs
+/// from `UST05LN` although their angles are the same.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Convention {
+    pub unit: AngleUnit,
+    pub clockwise: bool,
+    /// Angle of the driver zero in the sensor frame (counter-clockwise radians).
+    pub zero: f64,
+    name: Option<&'static str>,
+}
+
+impl Convention {
+    /// A custom convention, without name.
+    pub const fn new(unit: AngleUnit, clockwise: bool, zero: f64) -> Convention {
+        Convention {
+            unit,
+            clockwise,
+            zero,
+            name: None,
+        }
+    }
+
+    pub const LD06: Convention = Convention {
+        unit: AngleUnit::Degrees,
+        clockwise: true,
+        zero: 0.0,
+        name: Some("ld06"),
+    };
+
+    pub const XV11: Convention = Convention {
+        unit: AngleUnit::Radians,
+        clockwise: true,
+        zero: PI,
+        name: Some("xv11"),
+    };
+
+    pub const UST05LN: Convention = Convention {
+        unit: AngleUnit::Radians,
+        clockwise: false,
+        zero: 0.0,
+        name: Some("ust05ln"),
+    };
+
+    /// Counter-clockwise radians, zero forward. Used by scans already expressed in the robot frame.
+    pub const ROBOT: Convention = Convention {
+        unit: AngleUnit::Radians,
+        clockwise: false,
+        zero: 0.0,
+        name: Some("robot"),
+    };
+
+    /// Named conventions, as written in mount configuration files.
+    const NAMES: [(&'static str, Convention); 4] = [
+        ("ld06", Convention::LD06),
+        ("xv11", Convention::XV11),
+        ("ust05ln", Convention::UST05LN),
+        ("robot", Convention::ROBOT),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Convention> {
+        Convention::NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, c)| *c)
+    }
+
+    /// Name of a predefined convention, as accepted by `from_name`.
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// Whether both conventions give the same angles, whatever their name.
+    pub fn same_angles(&self, other: &Convention) -> bool {
+        self.unit == other.unit && self.clockwise == other.clockwise && self.zero == other.zero
+    }
+
+    /// Converts a raw sample angle into the sensor frame.
+    pub fn to_sensor_angle(&self, angle: f64) -> f64 {
+        let angle = match self.unit {
+            AngleUnit::Degrees => angle.to_radians(),
+            AngleUnit::Radians => angle,
+        };
+        let angle = if self.clockwise { -angle } else { angle };
+        normalize_angle(self.zero + angle)
+    }
+
+    /// Converts a sensor frame angle back into a raw sample angle.
+    pub fn from_sensor_angle(&self, angle: f64) -> f64 {
+        let angle = normalize_angle(angle - self.zero);
+        let angle = if self.clockwise { -angle } else { angle };
+        match self.unit {
+            AngleUnit::Degrees => angle.to_degrees().rem_euclid(360.0),
+            AngleUnit::Radians => angle,
+        }
+    }
+
+    /// Converts an angular width (not a position) into radians.
+    pub fn to_radians(&self, width: f64) -> f64 {
+        match self.unit {
+            AngleUnit::Degrees => width.to_radians(),
+            AngleUnit::Radians => width,
+        }
+    }
+}
+
+/// Wraps an angle in ]-PI, PI].
+pub fn normalize_angle(angle: f64) -> f64 {
+    let a = (angle + PI).rem_euclid(2.0 * PI) - PI;
+    if a <= -PI {
+        a + 2.0 * PI
+    } else {
+        a
+    }
+}
+
+/// A cartesian point, in meters.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Point {
+    pub fn new(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.x.hypot(self.y)
+    }
+
+    pub fn distance(&self, other: &Point) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+
+    pub fn angle(&self) -> f64 {
+        self.y.atan2(self.x)
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.3},{:.3}", self.x, self.y)
+    }
+}
+
+/// Position and orientation of the robot in the world frame (meters, radians).
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Pose {
+    pub x: f64,
+    pub y: f64,
+    pub theta: f64,
+}
+
+impl Pose {
+    pub fn new(x: f64, y: f64, theta: f64) -> Pose {
+        Pose { x, y, theta }
+    }
+
+    /// Transforms a point from the robot frame to the world frame.
+    pub fn transform(&self, p: Point) -> Point {
+        let (s, c) = self.theta.sin_cos();
+        Point::new(self.x + c * p.x - s * p.y, self.y + s * p.x + c * p.y)
+    }
+
+    /// Transforms a point from the world frame to the robot frame.
+    pub fn inverse_transform(&self, p: Point) -> Point {
+        let (s, c) = self.theta.sin_cos();
+        let (dx, dy) = (p.x - self.x, p.y - self.y);
+        Point::new(c * dx + s * dy, -s * dx + c * dy)
+    }
+
+    /// The pose `other`, expressed in this pose frame, brought to the world frame.
+    pub fn compose(&self, other: &Pose) -> Pose {
+        let p = self.transform(Point::new(other.x, other.y));
+        Pose::new(p.x, p.y, normalize_angle(self.theta + other.theta))
+    }
+
+    pub fn inverse(&self) -> Pose {
+        let p = Pose::new(0.0, 0.0, -self.theta).transform(Point::new(-self.x, -self.y));
+        Pose::new(p.x, p.y, -self.theta)
+    }
+
+    /// Linear interpolation between two poses, `ratio` going from 0 (`self`) to 1 (`other`).
+    pub fn interpolate(&self, other: &Pose, ratio: f64) -> Pose {
+        Pose::new(
+            self.x + (other.x - self.x) * ratio,
+            self.y + (other.y - self.y) * ratio,
+            normalize_angle(self.theta + normalize_angle(other.theta - self.theta) * ratio),
+        )
+    }
+}
+
+impl fmt::Display for Pose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.3},{:.3},{:.3}", self.x, self.y, self.theta)
+    }
+}
+
+/// Position and orientation of a lidar on the robot.
+///
+/// `x`, `y` (meters) and `yaw` (radians, counter-clockwise) locate the sensor
+/// frame in the robot frame. `flipped` is set when the sensor is mounted upside
+/// down, which mirrors its scan.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mount {
+    pub x: f64,
+    pub y: f64,
+    pub yaw: f64,
+    pub flipped: bool,
+    pub convention: Convention,
+}
+
+impl Mount {
+    /// A sensor at the robot origin, looking forward.
+    pub fn new(convention: Convention) -> Mount {
+        Mount {
+            x: 0.0,
+            y: 0.0,
+            yaw: 0.0,
+            flipped: false,
+            convention,
+        }
+    }
+
+    pub fn with_offset(mut self, x: f64, y: f64) -> Mount {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    pub fn with_yaw(mut self, yaw: f64) -> Mount {
+        self.yaw = yaw;
+        self
+    }
+
+    pub fn with_flipped(mut self, flipped: bool) -> Mount {
+        self.flipped = flipped;
+        self
+    }
+
+    /// Parses a mount configuration (see `MountConfig::parse`), without its frame id.
+    pub fn parse(text: &str) -> Result<Mount, Box<dyn Error>> {
+        MountConfig::parse(text).map(|config| config.mount)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Mount, Box<dyn Error>> {
+        Mount::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Angle of a sample in the robot frame.
+    pub fn robot_angle(&self, angle: f64) -> f64 {
+        let a = self.convention.to_sensor_angle(angle);
+        let a = if self.flipped { -a } else { a };
+        normalize_angle(a + self.yaw)
+    }
+
+    /// Projects a sample in the robot frame.
+    pub fn sample_to_point(&self, sample: &Sample) -> Point {
+        let a = self.convention.to_sensor_angle(sample.angle);
+        let a = if self.flipped { -a } else { a };
+        let r = sample.distance as f64 / 1000.0;
+        self.to_robot(Point::new(r * a.cos(), r * a.sin()))
+    }
+
+    /// Converts a robot frame point back into a sample of this sensor.
+    pub fn point_to_sample(&self, point: Point, quality: u16) -> Sample {
+        let p = self.to_sensor(point);
+        let a = if self.flipped { -p.angle() } else { p.angle() };
+        Sample {
+            angle: self.convention.from_sensor_angle(a),
+            distance: (p.norm() * 1000.0).round().min(u16::MAX as f64) as u16,
+            quality,
+        }
+    }
+
+    /// Transforms a point from the sensor frame to the robot frame.
+    pub fn to_robot(&self, p: Point) -> Point {
+        let (s, c) = self.yaw.sin_cos();
+        Point::new(self.x + c * p.x - s * p.y, self.y + s * p.x + c * p.y)
+    }
+
+    /// Transforms a point from the robot frame to the sensor frame.
+    pub fn to_sensor(&self, p: Point) -> Point {
+        let (s, c) = self.yaw.sin_cos();
+        let (dx, dy) = (p.x - self.x, p.y - self.y);
+        Point::new(c * dx + s * dy, -s * dx + c * dy)
+    }
+}
+
+/// A mount configuration file : the mount of a sensor, and the optional name
+/// of its frame for tools like Foxglove.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MountConfig {
+    pub mount: Mount,
+    pub frame_id: Option<String>,
+}
+
+impl MountConfig {
+    pub fn new(mount: Mount) -> MountConfig {
+        MountConfig {
+            mount,
+            frame_id: None,
+        }
+    }
+
+    pub fn with_frame_id(mut self, frame_id: &str) -> MountConfig {
+        self.frame_id = Some(frame_id.to_string());
+        self
+    }
+
+    /// Parses a mount configuration, made of `key = value` lines :
+    ///
+    /// ```text
+    /// convention = ld06
+    /// x = 0.1
+    /// y = 0.0
+    /// yaw = 0.0
+    /// flipped = false
+    /// frame_id = lidar
+    /// ```
+    ///
+    /// Empty lines and lines starting with `#` are ignored. Missing keys keep the
+    /// values of `Mount::new`, `convention` is mandatory and `frame_id` optional.
+    pub fn parse(text: &str) -> Result<MountConfig, Box<dyn Error>> {
+        let mut convention = None;
+        let (mut x, mut y, mut yaw, mut flipped) = (0.0, 0.0, 0.0, false);
+        let mut frame_id = None;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("invalid mount line: {}", line).into()),
+            };
+            match key {
+                "convention" => {
+                    convention = Some(
+                        Convention::from_name(value)
+                            .ok_or_else(|| format!("unknown convention: {}", value))?,
+                    )
+                }
+                "x" => x = value.parse()?,
+                "y" => y = value.parse()?,
+                "yaw" => yaw = value.parse()?,
+                "flipped" => flipped = value.parse()?,
+                "frame_id" => frame_id = Some(value.to_string()),
+                _ => return Err(format!("unknown mount key: {}", key).into()),
+            }
+        }
+        let convention = convention.ok_or("missing convention in mount")?;
+        Ok(MountConfig {
+            mount: Mount::new(convention)
+                .with_offset(x, y)
+                .with_yaw(yaw)
+                .with_flipped(flipped),
+            frame_id,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MountConfig, Box<dyn Error>> {
+        MountConfig::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for MountConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mount)?;
+        match &self.frame_id {
+            Some(frame_id) => writeln!(f, "frame_id = {}", frame_id),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Mount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.convention.name() {
+            Some(name) => writeln!(f, "convention = {}", name)?,
+            None => writeln!(f, "# custom convention: {:?}", self.convention)?,
+        }
+        writeln!(f, "x = {}", self.x)?;
+        writeln!(f, "y = {}", self.y)?;
+        writeln!(f, "yaw = {}", self.yaw)?;
+        writeln!(f, "flipped = {}", self.flipped)
+    }
+}
+
+/// Cartesian conversion of a scan.
+pub trait Scan {
+    /// Returns the valid samples of the scan as points in the robot frame, in meters.
+    fn to_points(&self, mount: &Mount) -> Vec<Point>;
+}
+
+impl Scan for [Option<Sample>] {
+    fn to_points(&self, mount: &Mount) -> Vec<Point> {
+        self.iter()
+            .filter_map(|s| s.as_ref())
+            .map(|s| mount.sample_to_point(s))
+            .collect()
+    }
+}
+
+/// Rigid transform best mapping `from` points onto `to` points (least squares).
+///
+/// The returned pose transforms `from[i]` close to `to[i]`. Returns `None`
+/// with fewer than 2 pairs.
+pub fn fit_rigid_transform(from: &[Point], to: &[Point]) -> Option<Pose> {
+    let n = from.len().min(to.len());
+    if n < 2 {
+        return None;
+    }
+    let mean = |pts: &[Point]| {
+        Point::new(
+            pts[..n].iter().map(|p| p.x).sum::<f64>() / n as f64,
+            pts[..n].iter().map(|p| p.y).sum::<f64>() / n as f64,
+        )
+    };
+    let (cf, ct) = (mean(from), mean(to));
+    let (mut sin, mut cos) = (0.0, 0.0);
+    for (f, t) in from.iter().zip(to) {
+        let (fx, fy) = (f.x - cf.x, f.y - cf.y);
+        let (tx, ty) = (t.x - ct.x, t.y - ct.y);
+        cos += fx * tx + fy * ty;
+        sin += fx * ty - fy * tx;
+    }
+    let theta = sin.atan2(cos);
+    let rotated = Pose::new(0.0, 0.0, theta).transform(cf);
+    Some(Pose::new(ct.x - rotated.x, ct.y - rotated.y, theta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn convention_angles() {
+        assert!(close(Convention::LD06.to_sensor_angle(90.0), -PI / 2.0));
+        assert!(close(Convention::XV11.to_sensor_angle(0.0), PI));
+        assert!(close(Convention::XV11.to_sensor_angle(PI / 2.0), PI / 2.0));
+        assert!(close(Convention::UST05LN.to_sensor_angle(0.5), 0.5));
+        for convention in [Convention::LD06, Convention::XV11, Convention::UST05LN].iter() {
+            for &a in [-3.0, -1.0, 0.0, 0.5, 2.5].iter() {
+                let raw = convention.from_sensor_angle(a);
+                assert!(
+                    close(convention.to_sensor_angle(raw), a),
+                    "{:?} {}",
+                    convention,
+                    a
+                );
+            }
+        }
+        assert!(close(Convention::LD06.to_radians(180.0), PI));
+    }
+
+    #[test]
+    fn convention_names() {
+        for name in ["ld06", "xv11", "ust05ln", "robot"].iter() {
+            assert_eq!(Convention::from_name(name).unwrap().name(), Some(*name));
+        }
+        assert_eq!(Convention::ROBOT.name(), Some("robot"));
+        assert_ne!(Convention::ROBOT, Convention::UST05LN);
+        assert!(Convention::ROBOT.same_angles(&Convention::UST05LN));
+        assert_eq!(Convention::new(AngleUnit::Degrees, false, 1.0).name(), None);
+        assert!(Convention::from_name("unknown").is_none());
+    }
+
+    #[test]
+    fn mount_round_trip() {
+        let mount = Mount::new(Convention::XV11)
+            .with_offset(0.12, -0.05)
+            .with_yaw(0.3)
+            .with_flipped(true);
+        assert_eq!(Mount::parse(&mount.to_string()).unwrap(), mount);
+
+        let path = std::env::temp_dir().join(format!("lidar_rd_mount_{}.cfg", std::process::id()));
+        mount.save(&path).unwrap();
+        let loaded = Mount::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.unwrap(), mount);
+    }
+
+    #[test]
+    fn mount_config_frame_id() {
+        let config =
+            MountConfig::new(Mount::new(Convention::LD06).with_yaw(0.5)).with_frame_id("laser");
+        assert_eq!(MountConfig::parse(&config.to_string()).unwrap(), config);
+        assert_eq!(Mount::parse(&config.to_string()).unwrap(), config.mount);
+
+        let config = MountConfig::parse("convention = ust05ln").unwrap();
+        assert_eq!(config, MountConfig::new(Mount::new(Convention::UST05LN)));
+        assert!(!config.to_string().contains("frame_id"));
+    }
+
+    #[test]
+    fn mount_parse_errors() {
+        assert!(Mount::parse("x = 1").is_err());
+        assert!(Mount::parse("convention = ld06\nheight = 1").is_err());
+        assert!(Mount::parse("convention = nope").is_err());
+        assert!(Mount::parse("convention = ld06\nx").is_err());
+        let mount = Mount::parse("# comment\n\nconvention = ld06\nx = 0.5").unwrap();
+        assert_eq!(mount, Mount::new(Convention::LD06).with_offset(0.5, 0.0));
+    }
+
+    #[test]
+    fn sample_point_round_trip() {
+        let mount = Mount::new(Convention::LD06)
+            .with_offset(0.1, 0.2)
+            .with_yaw(PI / 2.0)
+            .with_flipped(true);
+        let sample = Sample {
+            angle: 30.0,
+            distance: 1500,
+            quality: 200,
+        };
+        let point = mount.sample_to_point(&sample);
+        let back = mount.point_to_sample(point, sample.quality);
+        assert!((back.angle - sample.angle).abs() < 1e-6);
+        assert_eq!(back.distance, sample.distance);
+
+        // a sensor looking forward at the origin : angle 0 is straight ahead
+        let front = Mount::new(Convention::LD06).sample_to_point(&Sample {
+            angle: 0.0,
+            distance: 1000,
+            quality: 0,
+        });
+        assert!(close(front.x, 1.0) && close(front.y, 0.0));
+    }
+}
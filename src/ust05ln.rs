@@ -13,18 +13,26 @@ use std::sync::RwLock;
 use std::thread;
 use std::time::Duration;
 
-use crate::lidar::{Lidar, Sample, impl_iterator};
+use crate::calibration::{stored_correction, RangeCorrection};
+use crate::filter::{Pipeline, QualityFilter, RangeFilter};
+use crate::lidar::{DriverStats, Lidar, PacketLog, RawPacket, Sample, impl_iterator};
+use crate::transform::Convention;
 
 pub struct UST05LN {
     inner: Arc<RwLock<UST05LNInner>>,
+    baud_rate: u32,
     tx: Option<mpsc::Sender<()>>,
     started: bool,
     join_handle: Option<thread::JoinHandle<()>>,
+    correction: Option<RangeCorrection>,
+    filters: Mutex<Pipeline>,
 }
 
 struct UST05LNInner {
     scan: Mutex<Box<Option<Vec<Option<Sample>>>>>,
     timestamp: Mutex<u64>,
+    stats: Mutex<DriverStats>,
+    packets: PacketLog,
     port_path: String,
 }
 
@@ -34,7 +42,11 @@ pub struct UST05LNIter<'a> {
 
 impl Lidar for UST05LN {
     fn get_scan(&self) -> Option<Vec<Option<Sample>>> {
-        self.inner.read().unwrap().get_turn()
+        let mut turn = self.inner.read().unwrap().get_turn()?;
+        if let Some(correction) = &self.correction {
+            correction.correct(&mut turn);
+        }
+        Some(self.filters.lock().unwrap().run(turn))
     }
 
     fn start(&mut self) -> Result<(), Box<dyn Error>> {
@@ -46,8 +58,9 @@ impl Lidar for UST05LN {
 
         let mut port = serial::open(&local_self.read().unwrap().port_path)?;
 
+        let baud_rate = serial::BaudRate::from_speed(self.baud_rate as usize);
         port.reconfigure(&|settings| {
-            settings.set_baud_rate(serial::Baud115200)?;
+            settings.set_baud_rate(baud_rate)?;
             settings.set_char_size(serial::Bits8);
             settings.set_parity(serial::ParityNone);
             settings.set_stop_bits(serial::Stop1);
@@ -63,7 +76,6 @@ impl Lidar for UST05LN {
                 .unwrap()
                 .read_ust(&mut port, rx)
                 .expect("XV11 reading failed !");
-            println!("Stopped !");
         }));
 
         self.started = true;
@@ -85,6 +97,22 @@ impl Lidar for UST05LN {
     fn is_running(&self) -> bool {
         self.started
     }
+
+    fn convention(&self) -> Convention {
+        Convention::UST05LN
+    }
+
+    fn stats(&self) -> DriverStats {
+        *self.inner.read().unwrap().stats.lock().unwrap()
+    }
+
+    fn set_packet_capture(&self, enabled: bool) {
+        self.inner.read().unwrap().packets.set_enabled(enabled);
+    }
+
+    fn take_packets(&self) -> Vec<RawPacket> {
+        self.inner.read().unwrap().packets.take()
+    }
 }
 
 impl<'a> Iterator for UST05LNIter<'a> {
@@ -104,19 +132,59 @@ impl<'a> Iterator for UST05LNIter<'a> {
 }
 
 impl UST05LN {
+    pub const BAUD_RATE: u32 = 115_200;
+
     pub fn new(port_path: &str) -> UST05LN {
         UST05LN {
             inner: Arc::new(RwLock::new(UST05LNInner {
                 port_path: port_path.to_string(),
                 scan: Mutex::new(Box::new(None)),
                 timestamp: Mutex::new(0),
+                stats: Mutex::new(DriverStats::default()),
+                packets: PacketLog::default(),
             })),
+            baud_rate: UST05LN::BAUD_RATE,
             tx: None,
             started: false,
             join_handle: None,
+            correction: None,
+            filters: Mutex::new(UST05LN::default_filters()),
         }
     }
 
+    /// Baud rate used when started, `BAUD_RATE` by default.
+    pub fn with_baud_rate(mut self, baud_rate: u32) -> UST05LN {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    /// Filters applied by default : samples without reflectance are not echoes,
+    /// and ranges beyond 6m are out of the sensor specification.
+    pub fn default_filters() -> Pipeline {
+        Pipeline::new()
+            .with(QualityFilter::new(1))
+            .with(RangeFilter::new(0, 6_000))
+    }
+
+    /// Replaces the filters applied to the scans returned by `get_scan`. The range
+    /// correction, if any, is kept and applied before them.
+    pub fn set_filters(&mut self, filters: Pipeline) {
+        self.filters = Mutex::new(filters);
+    }
+
+    /// Corrects the range bias of this unit, see `calibration::RangeCorrection`.
+    pub fn with_range_correction(mut self, correction: RangeCorrection) -> UST05LN {
+        self.correction = Some(correction);
+        self
+    }
+
+    /// Corrects the range bias with the correction stored for the unit on the
+    /// port, if any (see `calibration::stored_correction`).
+    pub fn with_stored_correction(mut self) -> Result<UST05LN, Box<dyn Error>> {
+        self.correction = stored_correction(&self.inner.read().unwrap().port_path.clone())?;
+        Ok(self)
+    }
+
     pub fn iter<'a>(&'a self) -> UST05LNIter<'a> {
         UST05LNIter { inner: &self }
     }
@@ -154,14 +222,28 @@ impl UST05LNInner {
         let scan_regex = Regex::new(r"#GT00:([0-9A-F]{12}):([0-9]{6}):([0-9A-F]{4332})").unwrap();
         let mes_regex = Regex::new(r"(.{4})(.{4})").unwrap();
 
-        for line in buf.lines() {
-            match line {
-                Ok(line) => {
-                    if let Some((timestamp, scan)) =
-                        UST05LNInner::parse_scan(&line, &scan_regex, &mes_regex)
-                    {
-                        self.set_turn(Box::new(Some(scan)));
-                        self.set_timestamp(timestamp);
+        let mut raw = vec![];
+        loop {
+            // a line interrupted by a timeout is completed by the next read
+            match buf.read_until(b'\n', &mut raw) {
+                Ok(0) => break,
+                Ok(_) => {
+                    // packets are kept as received, end of line included
+                    self.packets.push(&raw);
+                    let line = String::from_utf8_lossy(&raw).into_owned();
+                    raw.clear();
+                    // only scan lines count, not the command echoes and acknowledgements
+                    if let Some(m) = scan_regex.find(&line) {
+                        let mut stats = self.stats.lock().unwrap();
+                        stats.packets += 1;
+                        if !UST05LNInner::check(m.as_str()) {
+                            stats.checksum_errors += 1;
+                        } else if let Some((timestamp, scan)) =
+                            UST05LNInner::parse_scan(&line, &scan_regex, &mes_regex)
+                        {
+                            self.set_turn(Box::new(Some(scan)));
+                            self.set_timestamp(timestamp);
+                        }
                     }
                 }
                 Err(e) => {
@@ -171,7 +253,6 @@ impl UST05LNInner {
 
             match rx.try_recv() {
                 Ok(_) | Err(TryRecvError::Disconnected) => {
-                    println!("Terminating.");
                     break;
                 }
                 Err(TryRecvError::Empty) => {}
@@ -185,9 +266,7 @@ impl UST05LNInner {
     Sends "#GT15466\n" to the LIDAR to stop ranging. The LIDAR should reply with "#ST00A845\n".
     */
     fn start_ranging<S: Read + Write>(&self, buf: &mut bufstream::BufStream<S>) {
-        while let Err(()) = self.stop_ranging(buf) {
-            println!("try again!")
-        }
+        while let Err(()) = self.stop_ranging(buf) {}
 
         match buf.write(b"#GT15466\n") {
             Ok(_) => (),
@@ -203,8 +282,6 @@ impl UST05LNInner {
             if ret != "#ST00A845\n" {
                 //println!("Unexpected answer. Expecting '#ST00A845\\n' but got {:?}", ret);
                 println!("Unexpected answer to start_ranging.");
-            } else {
-                println!("success");
             }
         }
     }
@@ -222,7 +299,6 @@ impl UST05LNInner {
         buf.flush().unwrap();
         let mut ret: String = String::new();
         buf.read_line(&mut ret).unwrap();
-        println!("stop ranging ret={}", ret);
         if ret == "#ST00A845\n" {
             Ok(())
         } else {
@@ -230,6 +306,26 @@ impl UST05LNInner {
         }
     }
 
+    /*
+    Checks the CRC-16 (CCITT) ending a message, computed over everything before it.
+    */
+    fn check(message: &str) -> bool {
+        if message.len() < 4 || !message.is_char_boundary(message.len() - 4) {
+            return false;
+        }
+        let (body, check) = message.split_at(message.len() - 4);
+        let crc = body.bytes().fold(0xffffu16, |crc, b| {
+            (0..8).fold(crc ^ ((b as u16) << 8), |crc, _| {
+                if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x1021
+                } else {
+                    crc << 1
+                }
+            })
+        });
+        u16::from_str_radix(check, 16) == Ok(crc)
+    }
+
     /*
     Parse data from LIDAR
     */
@@ -255,15 +351,11 @@ impl UST05LNInner {
                 let angle = ((i as f64) * 270.0 / 540.0 - (270.0 / 2.0)).to_radians();
                 let distance = u16::from_str_radix(c.get(1).unwrap().as_str(), 16).unwrap();
                 let quality = u16::from_str_radix(c.get(2).unwrap().as_str(), 16).unwrap();
-                if quality > 0 && distance < 6000 {
-                    samples.push(Some(Sample {
-                        angle,
-                        distance,
-                        quality: quality,
-                    }));
-                } else {
-                    samples.push(None);
-                }
+                samples.push(Some(Sample {
+                    angle,
+                    distance,
+                    quality,
+                }));
             }
             Some((timestamp, samples))
         } else {
@@ -273,3 +365,18 @@ impl UST05LNInner {
 }
 
 impl_iterator!(UST05LN);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum() {
+        assert!(UST05LNInner::check("#ST00A845"));
+        assert!(UST05LNInner::check("#GT15466"));
+        assert!(UST05LNInner::check("#ST5297"));
+        assert!(!UST05LNInner::check("#ST00A846"));
+        assert!(!UST05LNInner::check("#ST01A845"));
+        assert!(!UST05LNInner::check("#ST"));
+    }
+}
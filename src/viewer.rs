@@ -0,0 +1,210 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, style, terminal};
+
+use crate::lidar::{Lidar, Sample};
+use crate::transform::{Mount, Point};
+
+/// Dots drawn with Unicode braille patterns : each character holds 2 x 4 dots.
+pub struct BrailleCanvas {
+    width: usize,
+    height: usize,
+    cells: Vec<u8>,
+}
+
+impl BrailleCanvas {
+    /// A canvas of `width` x `height` characters.
+    pub fn new(width: usize, height: usize) -> BrailleCanvas {
+        BrailleCanvas {
+            width,
+            height,
+            cells: vec![0; width * height],
+        }
+    }
+
+    /// Size in dots.
+    pub fn dots(&self) -> (usize, usize) {
+        (self.width * 2, self.height * 4)
+    }
+
+    /// Sets the dot at column `x` and row `y` (from the top), ignored outside.
+    pub fn set(&mut self, x: i64, y: i64) {
+        let (w, h) = self.dots();
+        if x < 0 || y < 0 || x as usize >= w || y as usize >= h {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        const BITS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+        self.cells[(y / 4) * self.width + x / 2] |= BITS[x % 2][y % 4];
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.iter_mut().for_each(|c| *c = 0);
+    }
+
+    /// Rows of characters, empty cells being spaces.
+    pub fn rows(&self) -> Vec<String> {
+        self.cells
+            .chunks(self.width)
+            .map(|row| {
+                row.iter()
+                    .map(|&c| match c {
+                        0 => ' ',
+                        c => std::char::from_u32(0x2800 + c as u32).unwrap(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Interactive top-down view of the scans of a lidar, in the terminal.
+///
+/// Keys : `q` or Esc quits, space pauses, `+` and `-` zoom, `0` resets the zoom.
+pub struct Viewer {
+    mount: Mount,
+    /// Distance from the center to the top of the view, in meters.
+    pub range: f64,
+    initial_range: f64,
+    paused: bool,
+    scan: Vec<Option<Sample>>,
+    // time between scans, smoothed
+    period: Option<f64>,
+    last_scan: Option<Instant>,
+}
+
+impl Viewer {
+    pub fn new(mount: Mount, range: f64) -> Viewer {
+        Viewer {
+            mount,
+            range,
+            initial_range: range,
+            paused: false,
+            scan: vec![],
+            period: None,
+            last_scan: None,
+        }
+    }
+
+    /// Runs until `q` is pressed or the lidar stops. The lidar must be started.
+    pub fn run(&mut self, lidar: &dyn Lidar) -> io::Result<()> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let result = self.event_loop(lidar, &mut out);
+        execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        result
+    }
+
+    fn event_loop<W: Write>(&mut self, lidar: &dyn Lidar, out: &mut W) -> io::Result<()> {
+        let mut redraw = true;
+        while lidar.is_running() {
+            if let Some(scan) = lidar.get_scan() {
+                let now = Instant::now();
+                if let Some(last) = self.last_scan {
+                    let dt = now.duration_since(last).as_secs_f64();
+                    self.period = Some(self.period.map_or(dt, |p| 0.8 * p + 0.2 * dt));
+                }
+                self.last_scan = Some(now);
+                if !self.paused {
+                    self.scan = scan;
+                    redraw = true;
+                }
+            }
+
+            if event::poll(Duration::from_millis(10))? {
+                match event::read()? {
+                    Event::Key(key) if key.kind != KeyEventKind::Release => match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            return Ok(())
+                        }
+                        KeyCode::Char(' ') | KeyCode::Char('p') => self.paused = !self.paused,
+                        KeyCode::Char('+') | KeyCode::Char('=') => {
+                            self.range = (self.range / 1.25).max(0.1)
+                        }
+                        KeyCode::Char('-') => self.range = (self.range * 1.25).min(100.0),
+                        KeyCode::Char('0') => self.range = self.initial_range,
+                        _ => {}
+                    },
+                    _ => {}
+                }
+                redraw = true;
+            }
+
+            if redraw {
+                self.draw(lidar, out)?;
+                redraw = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn draw<W: Write>(&self, lidar: &dyn Lidar, out: &mut W) -> io::Result<()> {
+        let (columns, lines) = terminal::size()?;
+        let (width, height) = (columns.max(10) as usize, lines.max(4) as usize - 2);
+        let mut canvas = BrailleCanvas::new(width, height);
+        let (w, h) = canvas.dots();
+        let scale = (h as f64 / 2.0) / self.range;
+        // robot frame : x forward is up, y left is left ; a dot is square
+        let to_dots = |p: &Point| {
+            (
+                (w as f64 / 2.0 - p.y * scale).round() as i64,
+                (h as f64 / 2.0 - p.x * scale).round() as i64,
+            )
+        };
+
+        // 1 m rings, when they are not too dense
+        if self.range <= 20.0 {
+            for r in 1..=(self.range * 1.5) as usize {
+                let steps = (2.0 * std::f64::consts::PI * r as f64 * scale).max(16.0) as usize;
+                for i in (0..steps).step_by(3) {
+                    let a = i as f64 / steps as f64 * 2.0 * std::f64::consts::PI;
+                    let (x, y) = to_dots(&Point::new(r as f64 * a.cos(), r as f64 * a.sin()));
+                    canvas.set(x, y);
+                }
+            }
+        }
+        let (cx, cy) = to_dots(&Point::new(self.mount.x, self.mount.y));
+        for (dx, dy) in [(0, 0), (0, -1), (-1, 0), (1, 0)] {
+            canvas.set(cx + dx, cy + dy);
+        }
+        for s in self.scan.iter().flatten() {
+            let (x, y) = to_dots(&self.mount.sample_to_point(s));
+            canvas.set(x, y);
+        }
+
+        let stats = lidar.stats();
+        let valid = self.scan.iter().flatten().count();
+        let status = format!(
+            " rpm {}  pts/turn {} ({} ok)  checksum err {:.2}%  {}  range {:.1} m{}",
+            stats
+                .rpm
+                .map_or("-".to_string(), |rpm| format!("{:.0}", rpm)),
+            self.scan.len(),
+            valid,
+            100.0 * stats.error_rate(),
+            self.period
+                .map_or("-".to_string(), |p| format!("{:.1} Hz", 1.0 / p)),
+            self.range,
+            if self.paused { "  [paused]" } else { "" }
+        );
+        let help = " q quit  space pause  +/- zoom  0 reset zoom";
+
+        let mut rows = canvas.rows();
+        rows.push(status);
+        rows.push(help.to_string());
+        for (i, row) in rows.iter().enumerate() {
+            queue!(
+                out,
+                cursor::MoveTo(0, i as u16),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                style::Print(row.chars().take(width).collect::<String>())
+            )?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,371 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::segmentation::Cluster;
+use crate::tracking::Track;
+use crate::transform::Point;
+
+const PAGE: &str = include_str!("web/index.html");
+
+/// How frames are sent to a WebSocket client, chosen with the `format` query
+/// parameter of the WebSocket URL (`/ws?format=binary`).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+    Json,
+    Binary,
+}
+
+/// What the page draws, in the robot frame (meters).
+pub struct Frame<'a> {
+    /// Time of the scan, in seconds.
+    pub time: f64,
+    pub points: &'a [Point],
+    pub clusters: &'a [Cluster],
+    pub tracks: &'a [Track],
+}
+
+impl<'a> Frame<'a> {
+    /// `{"time":t,"points":[[x,y],...],"clusters":[{"centroid":[x,y],"min":[x,y],"max":[x,y]},...],
+    /// "tracks":[{"id":i,"position":[x,y],"velocity":[vx,vy],"confirmed":b},...]}`
+    pub fn to_json(&self) -> String {
+        let xy = |p: &Point| format!("[{:.3},{:.3}]", p.x, p.y);
+        let points = self.points.iter().map(xy).collect::<Vec<_>>();
+        let clusters = self
+            .clusters
+            .iter()
+            .map(|c| {
+                format!(
+                    "{{\"centroid\":{},\"min\":{},\"max\":{}}}",
+                    xy(&c.centroid),
+                    xy(&c.bbox.min),
+                    xy(&c.bbox.max)
+                )
+            })
+            .collect::<Vec<_>>();
+        let tracks = self
+            .tracks
+            .iter()
+            .map(|t| {
+                format!(
+                    "{{\"id\":{},\"position\":{},\"velocity\":{},\"confirmed\":{}}}",
+                    t.id,
+                    xy(&t.position),
+                    xy(&t.velocity),
+                    t.is_confirmed()
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{{\"time\":{:.6},\"points\":[{}],\"clusters\":[{}],\"tracks\":[{}]}}",
+            self.time,
+            points.join(","),
+            clusters.join(","),
+            tracks.join(",")
+        )
+    }
+
+    /// Little endian : `u8` version (1), `f64` time, `u32` point count then `f32` x, y
+    /// per point, `u32` cluster count then `f32` centroid, min and max (x, y) per
+    /// cluster, `u32` track count then `u32` id, `f32` x, y, vx, vy and `u8`
+    /// confirmed per track.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut out = vec![1];
+        out.extend_from_slice(&self.time.to_le_bytes());
+        let push_xy = |out: &mut Vec<u8>, p: &Point| {
+            out.extend_from_slice(&(p.x as f32).to_le_bytes());
+            out.extend_from_slice(&(p.y as f32).to_le_bytes());
+        };
+        out.extend_from_slice(&(self.points.len() as u32).to_le_bytes());
+        for p in self.points {
+            push_xy(&mut out, p);
+        }
+        out.extend_from_slice(&(self.clusters.len() as u32).to_le_bytes());
+        for c in self.clusters {
+            push_xy(&mut out, &c.centroid);
+            push_xy(&mut out, &c.bbox.min);
+            push_xy(&mut out, &c.bbox.max);
+        }
+        out.extend_from_slice(&(self.tracks.len() as u32).to_le_bytes());
+        for t in self.tracks {
+            out.extend_from_slice(&t.id.to_le_bytes());
+            push_xy(&mut out, &t.position);
+            push_xy(&mut out, &t.velocity);
+            out.push(t.is_confirmed() as u8);
+        }
+        out
+    }
+}
+
+struct Client {
+    socket: WebSocket<TcpStream>,
+    encoding: Encoding,
+}
+
+impl Client {
+    /// Reads what the client sent without blocking, answering pings and close
+    /// requests. Returns false once the connection is closed.
+    fn poll(&mut self) -> bool {
+        if self.socket.get_ref().set_nonblocking(true).is_err() {
+            return false;
+        }
+        let open = loop {
+            match self.socket.read() {
+                Ok(Message::Close(_)) => {
+                    // sends the close reply queued by the read
+                    let _ = self.socket.flush();
+                    break false;
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    break true
+                }
+                Err(_) => break false,
+            }
+        };
+        open && self.socket.get_ref().set_nonblocking(false).is_ok()
+    }
+}
+
+/// Serves the visualizer page on `/` and streams frames to the WebSocket clients on `/ws`.
+pub struct WebServer {
+    addr: SocketAddr,
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+impl WebServer {
+    /// Starts listening, connections are handled in background threads.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<WebServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let clients = Arc::new(Mutex::new(vec![]));
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let clients = accepted.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, clients) {
+                        eprintln!("web connection failed : {}", e);
+                    }
+                });
+            }
+        });
+        Ok(WebServer { addr, clients })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of connected WebSocket clients.
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Sends a frame to every client. Clients that can't keep up are disconnected,
+    /// as well as those which closed the connection.
+    pub fn publish(&self, frame: &Frame) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let (mut json, mut binary) = (None, None);
+        clients.retain_mut(|client| {
+            let message = match client.encoding {
+                Encoding::Json => {
+                    Message::Text(json.get_or_insert_with(|| frame.to_json()).clone())
+                }
+                Encoding::Binary => {
+                    Message::Binary(binary.get_or_insert_with(|| frame.to_binary()).clone())
+                }
+            };
+            client.poll() && client.socket.send(message).is_ok()
+        });
+    }
+}
+
+/// Request line and headers of an HTTP request.
+struct Request {
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Reads and parses the request head. Returns it with the bytes read past it.
+    fn read(stream: &mut TcpStream) -> io::Result<(Request, Vec<u8>)> {
+        let invalid = |reason| io::Error::new(io::ErrorKind::InvalidData, reason);
+        let mut data = vec![];
+        let mut buffer = [0; 1024];
+        let end = loop {
+            if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end;
+            }
+            if data.len() > 8192 {
+                return Err(invalid("request head too large"));
+            }
+            let n = stream.read(&mut buffer)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            data.extend_from_slice(&buffer[..n]);
+        };
+        let rest = data.split_off(end + 4);
+        let head = String::from_utf8(data).map_err(|_| invalid("request head is not UTF-8"))?;
+
+        let mut lines = head.split("\r\n");
+        let path = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .ok_or_else(|| invalid("bad request line"))?
+            .to_string();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Ok((Request { path, headers }, rest))
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn handle_connection(mut stream: TcpStream, clients: Arc<Mutex<Vec<Client>>>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let (request, rest) = Request::read(&mut stream)?;
+    let path = request.path.as_str();
+
+    if path == "/ws" || path.starts_with("/ws?") {
+        let encoding = if path.contains("format=binary") {
+            Encoding::Binary
+        } else {
+            Encoding::Json
+        };
+        let upgrade = request
+            .header("Upgrade")
+            .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+        let key = match request.header("Sec-WebSocket-Key") {
+            Some(key) if upgrade => key,
+            _ => {
+                return respond(
+                    &mut stream,
+                    "400 Bad Request",
+                    "text/plain",
+                    "expected a WebSocket upgrade\n",
+                )
+            }
+        };
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            derive_accept_key(key.as_bytes())
+        )?;
+        stream.flush()?;
+        // a stalled client must not block the others
+        stream.set_write_timeout(Some(Duration::from_millis(500)))?;
+        let socket = WebSocket::from_partially_read(stream, rest, Role::Server, None);
+        clients.lock().unwrap().push(Client { socket, encoding });
+        return Ok(());
+    }
+
+    match path.split('?').next() {
+        Some("/") | Some("/index.html") => {
+            respond(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE)
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "not found\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn wait_for_client(server: &WebServer) {
+        let start = Instant::now();
+        while server.clients() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn serves_page() {
+        let server = WebServer::bind("127.0.0.1:0").unwrap();
+        let page = get(server.local_addr(), "/");
+        assert!(page.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(page.ends_with(PAGE));
+        assert!(get(server.local_addr(), "/nothing").starts_with("HTTP/1.1 404"));
+        assert!(get(server.local_addr(), "/ws").starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn streams_frames() {
+        let server = WebServer::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/ws?format=binary", server.local_addr());
+        let stream = TcpStream::connect(server.local_addr()).unwrap();
+        let (mut socket, _) = tungstenite::client(url.as_str(), stream).unwrap();
+        wait_for_client(&server);
+
+        let points = [Point { x: 1.0, y: -2.0 }];
+        let frame = Frame {
+            time: 3.5,
+            points: &points,
+            clusters: &[],
+            tracks: &[],
+        };
+        socket.send(Message::Ping(vec![7])).unwrap();
+        server.publish(&frame);
+        let mut messages = vec![];
+        while messages.len() < 2 {
+            messages.push(socket.read().unwrap());
+        }
+        assert!(messages.contains(&Message::Pong(vec![7])));
+        assert!(messages.contains(&Message::Binary(frame.to_binary())));
+
+        socket.close(None).unwrap();
+        let start = Instant::now();
+        while server.clients() != 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            server.publish(&frame);
+            thread::sleep(Duration::from_millis(10));
+        }
+        // the server answers the close request
+        loop {
+            match socket.read() {
+                Ok(_) => {}
+                Err(tungstenite::Error::ConnectionClosed) => break,
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+}
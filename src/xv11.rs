@@ -10,7 +10,10 @@ use std::thread;
 use std::time::Duration;
 use std::error::Error;
 
-use crate::lidar::{Lidar, Sample, impl_iterator};
+use crate::calibration::{stored_correction, RangeCorrection};
+use crate::filter::{Pipeline, QualityFilter, RangeFilter};
+use crate::lidar::{DriverStats, Lidar, PacketLog, RawPacket, Sample, impl_iterator};
+use crate::transform::Convention;
 
 pub struct XV11Iter<'a> {
     inner: &'a XV11,
@@ -18,14 +21,19 @@ pub struct XV11Iter<'a> {
 
 pub struct XV11 {
     inner: Arc<RwLock<XV11Inner>>,
+    baud_rate: u32,
     tx: Option<mpsc::Sender<()>>,
     started: bool,
     join_handle: Option<thread::JoinHandle<()>>,
+    correction: Option<RangeCorrection>,
+    filters: Mutex<Pipeline>,
 }
 
 struct XV11Inner {
     scan: Mutex<Box<Option<Vec<Option<Sample>>>>>,
     lidar_speed: Mutex<f64>,
+    stats: Mutex<DriverStats>,
+    packets: PacketLog,
     port_path: String,
 }
 
@@ -99,11 +107,20 @@ impl XV11Inner {
                 InitLevel::Reading => {
                     f.read_exact(&mut buffer[2..])?;
                     init_level = InitLevel::Idle;
+                    self.packets.push(&buffer);
                     let (speed, mut samples) = decode_packet(buffer);
 
                     if let Some(speed) = speed {
                         self.set_lidar_speed(speed);
                     }
+                    {
+                        let mut stats = self.stats.lock().unwrap();
+                        stats.packets += 1;
+                        match speed {
+                            Some(speed) => stats.rpm = Some(speed),
+                            None => stats.checksum_errors += 1,
+                        }
+                    }
 
                     if let Some((a_min, a_max)) = get_min_max(&samples) {
                         if a_min < angle_max {
@@ -121,7 +138,6 @@ impl XV11Inner {
 
             match rx.try_recv() {
                 Ok(_) | Err(TryRecvError::Disconnected) => {
-                    println!("Terminating.");
                     break Ok(());
                 }
                 Err(TryRecvError::Empty) => {}
@@ -131,19 +147,60 @@ impl XV11Inner {
 }
 
 impl XV11 {
+    pub const BAUD_RATE: u32 = 115_200;
+
     pub fn new(port_path: &str) -> XV11 {
         XV11 {
             inner: Arc::new(RwLock::new(XV11Inner {
                 port_path: port_path.to_string(),
                 scan: Mutex::new(Box::new(None)),
                 lidar_speed: Mutex::new(0.0),
+                stats: Mutex::new(DriverStats::default()),
+                packets: PacketLog::default(),
             })),
+            baud_rate: XV11::BAUD_RATE,
             tx: None,
             started: false,
             join_handle: None,
+            correction: None,
+            filters: Mutex::new(XV11::default_filters()),
         }
     }
 
+    /// Baud rate used when started, `BAUD_RATE` by default.
+    pub fn with_baud_rate(mut self, baud_rate: u32) -> XV11 {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    /// Filters applied by default : the XV11 is specified from 6cm to 5m, and low
+    /// strength returns are unreliable. Samples flagged invalid or with a strength
+    /// warning by the sensor are already dropped by the decoder.
+    pub fn default_filters() -> Pipeline {
+        Pipeline::new()
+            .with(RangeFilter::new(60, 5_000))
+            .with(QualityFilter::new(20))
+    }
+
+    /// Replaces the filters applied to the scans returned by `get_scan`. The range
+    /// correction, if any, is kept and applied before them.
+    pub fn set_filters(&mut self, filters: Pipeline) {
+        self.filters = Mutex::new(filters);
+    }
+
+    /// Corrects the range bias of this unit, see `calibration::RangeCorrection`.
+    pub fn with_range_correction(mut self, correction: RangeCorrection) -> XV11 {
+        self.correction = Some(correction);
+        self
+    }
+
+    /// Corrects the range bias with the correction stored for the unit on the
+    /// port, if any (see `calibration::stored_correction`).
+    pub fn with_stored_correction(mut self) -> Result<XV11, Box<dyn Error>> {
+        self.correction = stored_correction(&self.inner.read().unwrap().port_path.clone())?;
+        Ok(self)
+    }
+
     pub fn iter<'a>(&'a self) -> XV11Iter<'a> {
         XV11Iter { inner: &self }
     }
@@ -155,7 +212,11 @@ impl XV11 {
 
 impl Lidar for XV11 {
     fn get_scan(&self) -> Option<Vec<Option<Sample>>> {
-        self.inner.clone().read().unwrap().get_turn()
+        let mut turn = self.inner.clone().read().unwrap().get_turn()?;
+        if let Some(correction) = &self.correction {
+            correction.correct(&mut turn);
+        }
+        Some(self.filters.lock().unwrap().run(turn))
     }
 
     fn start(&mut self) -> Result<(), Box<dyn Error>> {
@@ -167,8 +228,9 @@ impl Lidar for XV11 {
 
         let mut port = serial::open(&local_self.read().unwrap().port_path).unwrap();
 
+        let baud_rate = serial::BaudRate::from_speed(self.baud_rate as usize);
         port.reconfigure(&|settings| {
-            settings.set_baud_rate(serial::Baud115200)?;
+            settings.set_baud_rate(baud_rate)?;
             settings.set_char_size(serial::Bits8);
             settings.set_parity(serial::ParityNone);
             settings.set_stop_bits(serial::Stop1);
@@ -185,7 +247,6 @@ impl Lidar for XV11 {
                 .unwrap()
                 .read_xv11(&mut port, rx)
                 .expect("XV11 reading failed !");
-            println!("Stopped !");
         }));
 
         self.started = true;
@@ -206,6 +267,22 @@ impl Lidar for XV11 {
     fn is_running(&self) -> bool {
         self.started
     }
+
+    fn convention(&self) -> Convention {
+        Convention::XV11
+    }
+
+    fn stats(&self) -> DriverStats {
+        *self.inner.read().unwrap().stats.lock().unwrap()
+    }
+
+    fn set_packet_capture(&self, enabled: bool) {
+        self.inner.read().unwrap().packets.set_enabled(enabled);
+    }
+
+    fn take_packets(&self) -> Vec<RawPacket> {
+        self.inner.read().unwrap().packets.take()
+    }
 }
 
 fn get_min_max(samples: &Vec<Option<Sample>>) -> Option<(f64, f64)> {
@@ -278,3 +355,21 @@ fn checksum(buffer: &[u8]) -> u16 {
 }
 
 impl_iterator!(XV11);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flagged_samples_are_dropped() {
+        // 1000 mm, strength 300
+        let data = [0xe8, 0x03, 0x2c, 0x01];
+        let sample = decode_data(180, &data).unwrap();
+        assert_eq!((sample.distance, sample.quality), (1000, 300));
+        assert!(sample.angle.abs() < 1e-9);
+        // invalid data flag
+        assert!(decode_data(180, &[0xe8, 0x83, 0x2c, 0x01]).is_none());
+        // strength warning, dropped even with a high strength
+        assert!(decode_data(180, &[0xe8, 0x43, 0x2c, 0x01]).is_none());
+    }
+}